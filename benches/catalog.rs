@@ -0,0 +1,87 @@
+use std::net::SocketAddr;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use futures_channel::mpsc::unbounded;
+use remo_auth::models::{
+    filter::{AnyOf, RoomFilter},
+    listing::{RoomQuery, SortKey},
+    state::State,
+    store::Store,
+};
+
+const OSES: [&str; 3] = ["windows", "linux", "macos"];
+
+fn fleet(size: usize) -> State {
+    let dir = std::env::temp_dir().join(format!("remo-auth-bench-{}", size));
+    let _ = std::fs::remove_dir_all(&dir);
+    let state = State::with_store(Store::open(&dir).expect("Failed to open bench store"));
+    let mut state = std::sync::Arc::try_unwrap(state)
+        .ok()
+        .expect("State is not shared yet")
+        .into_inner();
+    for index in 0..size {
+        let (tx, _rx) = unbounded();
+        state
+            .add_server(
+                format!("room{}", index),
+                format!("host-{}-{}", OSES[index % 3], index),
+                OSES[index % 3].to_string(),
+                format!("1.{}", index % 10),
+                index % 2 == 0,
+                tx,
+                SocketAddr::from((
+                    [10, (index >> 16) as u8, (index >> 8) as u8, index as u8],
+                    9000,
+                )),
+            )
+            .unwrap();
+    }
+    state
+}
+
+fn list_rooms(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_available_rooms");
+    let queries = [
+        ("unfiltered", RoomQuery::default()),
+        (
+            "by_name",
+            RoomQuery {
+                sort_by: Some(SortKey::Name),
+                ..Default::default()
+            },
+        ),
+        (
+            "os_and_version",
+            RoomQuery {
+                filter: RoomFilter {
+                    os: AnyOf(vec!["linux".to_string()]),
+                    version: AnyOf(vec!["1.3".to_string()]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ),
+        (
+            "name_substring",
+            RoomQuery {
+                filter: RoomFilter {
+                    name: AnyOf(vec!["-1234".to_string()]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ),
+    ];
+    for size in [1_000, 10_000, 50_000] {
+        let state = fleet(size);
+        for (name, query) in &queries {
+            group.bench_with_input(BenchmarkId::new(*name, size), query, |b, query| {
+                b.iter(|| state.get_available_rooms(query, None).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, list_rooms);
+criterion_main!(benches);
@@ -1,10 +1,56 @@
 use clap::Parser;
 
+use crate::models::view::RoomField;
+
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
     /// Listening address
     /// ./file --address 0.0.0.0:8080
     #[arg(short, long, default_value = "0.0.0.0:8080")]
-    pub(crate) address: String,
+    pub address: String,
+
+    /// Directory of the embedded store holding accounts
+    /// ./file --data-dir /var/lib/remo-auth
+    #[arg(short, long, default_value = "data")]
+    pub data_dir: String,
+
+    /// Directory of the audit log, which is disabled unless set
+    /// ./file --audit-dir /var/log/remo-auth
+    #[arg(long)]
+    pub audit_dir: Option<String>,
+
+    /// Rotate the audit log once the current file reaches this many bytes
+    #[arg(long, default_value_t = 10 * 1024 * 1024)]
+    pub audit_max_bytes: u64,
+
+    /// Rotate the audit log once the current file is this many seconds old
+    #[arg(long, default_value_t = 24 * 60 * 60)]
+    pub audit_max_age: u64,
+
+    /// Keep ended sessions in the history for this many days, 0 keeps them
+    /// forever
+    #[arg(long, default_value_t = 90)]
+    pub history_retention_days: u64,
+
+    /// On SIGTERM or Ctrl+C, keep serving this many seconds while reporting
+    /// not ready, so load balancers stop sending new clients
+    #[arg(long, default_value_t = 0)]
+    pub drain_secs: u64,
+
+    /// Identifying room fields shown to callers who neither own the room nor
+    /// are admins, out of viewers, server, owner, controller and ips
+    /// ./file --anonymous-fields viewers,server
+    #[arg(long, value_delimiter = ',')]
+    pub anonymous_fields: Option<Vec<RoomField>>,
+
+    /// Identifying room fields shown to the owner of a room, all but ips by
+    /// default
+    #[arg(long, value_delimiter = ',')]
+    pub owner_fields: Option<Vec<RoomField>>,
+
+    /// Verify the hash chain of the audit log in this directory and exit
+    /// ./file --verify-audit /var/log/remo-auth
+    #[arg(long)]
+    pub verify_audit: Option<String>,
 }
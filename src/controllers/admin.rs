@@ -0,0 +1,120 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use log::info;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    args::Args,
+    controllers::auth::{api_error, ApiError, ApiResult},
+    middleware::{admin::AdminActor, ip::RealIp},
+    models::{audit::AuditEvent, metrics::lock_state, rtc::SignallerMessage, state::StateType},
+};
+
+#[derive(Deserialize)]
+pub struct KickRequest {
+    pub reason: Option<String>,
+}
+
+pub async fn list_sessions(
+    State((state, _args)): State<(StateType, Args)>,
+    Extension(AdminActor(admin)): Extension<AdminActor>,
+    Extension(RealIp(ip)): Extension<RealIp>,
+) -> ApiResult {
+    let mut state = lock_state(&state).await;
+    state.audit(
+        Some(ip),
+        AuditEvent::admin_action(&admin, "list_sessions", ""),
+    );
+    Ok(Json(json!({ "sessions": state.admin_sessions() })))
+}
+
+pub async fn list_connections(
+    State((state, _args)): State<(StateType, Args)>,
+    Extension(AdminActor(admin)): Extension<AdminActor>,
+    Extension(RealIp(ip)): Extension<RealIp>,
+) -> ApiResult {
+    let mut state = lock_state(&state).await;
+    state.audit(
+        Some(ip),
+        AuditEvent::admin_action(&admin, "list_connections", ""),
+    );
+    Ok(Json(json!({ "connections": state.admin_connections() })))
+}
+
+pub async fn list_peers(
+    State((state, _args)): State<(StateType, Args)>,
+    Extension(AdminActor(admin)): Extension<AdminActor>,
+    Extension(RealIp(ip)): Extension<RealIp>,
+) -> ApiResult {
+    let mut state = lock_state(&state).await;
+    state.audit(Some(ip), AuditEvent::admin_action(&admin, "list_peers", ""));
+    Ok(Json(json!({ "peers": state.admin_peers() })))
+}
+
+/// End a session, sending `server_closed` to its viewers.
+pub async fn close_room(
+    State((state, _args)): State<(StateType, Args)>,
+    Extension(AdminActor(admin)): Extension<AdminActor>,
+    Extension(RealIp(ip)): Extension<RealIp>,
+    Path(room): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let mut state = lock_state(&state).await;
+    state
+        .close_room(&room)
+        .map_err(|e| api_error(StatusCode::NOT_FOUND, e))?;
+    info!("{} closed room {} via admin API", admin, room);
+    state.audit(
+        Some(ip),
+        AuditEvent::admin_action(&admin, "close_room", &room),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn kick_viewer(
+    State((state, _args)): State<(StateType, Args)>,
+    Extension(AdminActor(admin)): Extension<AdminActor>,
+    Extension(RealIp(ip)): Extension<RealIp>,
+    Path((room, viewer)): Path<(String, String)>,
+    Json(request): Json<KickRequest>,
+) -> Result<StatusCode, ApiError> {
+    let mut state = lock_state(&state).await;
+    state
+        .kick_viewer(&room, &viewer, request.reason)
+        .map_err(|e| api_error(StatusCode::NOT_FOUND, e))?;
+    info!(
+        "{} kicked {} from room {} via admin API",
+        admin, viewer, room
+    );
+    state.audit(
+        Some(ip),
+        AuditEvent::admin_action(&admin, "kick_viewer", &format!("{}/{}", room, viewer)),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Send a signalling message to a peer. The body must be a valid
+/// `SignallerMessage`, as clients drop anything else.
+pub async fn send_message(
+    State((state, _args)): State<(StateType, Args)>,
+    Extension(AdminActor(admin)): Extension<AdminActor>,
+    Extension(RealIp(ip)): Extension<RealIp>,
+    Path(id): Path<String>,
+    Json(message): Json<Value>,
+) -> Result<StatusCode, ApiError> {
+    let message: SignallerMessage =
+        serde_json::from_value(message).map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    let mut state = lock_state(&state).await;
+    state
+        .send_to_peer(&id, &message)
+        .map_err(|e| api_error(StatusCode::NOT_FOUND, e))?;
+    info!("{} sent {:?} to {} via admin API", admin, message, id);
+    state.audit(
+        Some(ip),
+        AuditEvent::admin_action(&admin, "send_message", &id),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
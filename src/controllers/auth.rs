@@ -0,0 +1,164 @@
+use axum::{
+    extract::{Path, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    Extension, Json,
+};
+use log::info;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    args::Args,
+    middleware::ip::RealIp,
+    models::{
+        account::{Account, AccountRole, SESSION_TOKEN_TTL},
+        admin::ADMIN_TOKEN_ACTOR,
+        audit::AuditEvent,
+        metrics::lock_state,
+        state::StateType,
+    },
+    services::auth,
+};
+
+pub type ApiError = (StatusCode, Json<Value>);
+pub type ApiResult = Result<Json<Value>, ApiError>;
+
+#[derive(Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+    /// TOTP or recovery code for accounts with two-factor authentication.
+    pub totp: Option<String>,
+}
+
+pub fn api_error(status: StatusCode, message: impl ToString) -> ApiError {
+    (status, Json(json!({ "error": message.to_string() })))
+}
+
+/// Extract the token from an `Authorization: Bearer <token>` header.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Resolve the bearer token of a request to its account.
+pub async fn require_account(state: &StateType, headers: &HeaderMap) -> Result<Account, ApiError> {
+    let token = bearer_token(headers)
+        .ok_or_else(|| api_error(StatusCode::UNAUTHORIZED, "Missing bearer token"))?;
+    lock_state(state)
+        .await
+        .authenticate_token(token)
+        .ok_or_else(|| api_error(StatusCode::UNAUTHORIZED, "Invalid or expired token"))
+}
+
+pub async fn require_admin(state: &StateType, headers: &HeaderMap) -> Result<Account, ApiError> {
+    let account = require_account(state, headers).await?;
+    if account.role != AccountRole::Admin {
+        return Err(api_error(StatusCode::FORBIDDEN, "Admin role required"));
+    }
+    Ok(account)
+}
+
+/// Accept either `ADMIN_TOKEN` or the session token of an admin account,
+/// returning who to log the action under.
+pub async fn require_admin_or_token(
+    state: &StateType,
+    headers: &HeaderMap,
+) -> Result<String, ApiError> {
+    let token = bearer_token(headers)
+        .ok_or_else(|| api_error(StatusCode::UNAUTHORIZED, "Missing bearer token"))?;
+    if lock_state(state).await.is_admin_token(token) {
+        return Ok(ADMIN_TOKEN_ACTOR.to_string());
+    }
+    Ok(require_admin(state, headers).await?.username)
+}
+
+pub async fn register(
+    State((state, _args)): State<(StateType, Args)>,
+    Json(credentials): Json<Credentials>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let account = auth::register_account(&state, &credentials.username, &credentials.password)
+        .await
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "username": account.username, "role": account.role })),
+    ))
+}
+
+pub async fn login(
+    State((state, _args)): State<(StateType, Args)>,
+    Json(credentials): Json<Credentials>,
+) -> ApiResult {
+    let token = auth::login(
+        &state,
+        &credentials.username,
+        &credentials.password,
+        credentials.totp.as_deref(),
+    )
+    .await
+    .map_err(|e| api_error(StatusCode::UNAUTHORIZED, e))?;
+    Ok(Json(json!({
+        "token": token,
+        "token_type": "Bearer",
+        "expires_in": SESSION_TOKEN_TTL.as_secs(),
+    })))
+}
+
+pub async fn logout(
+    State((state, _args)): State<(StateType, Args)>,
+    headers: HeaderMap,
+) -> StatusCode {
+    if let Some(token) = bearer_token(&headers) {
+        lock_state(&state).await.logout(token);
+    }
+    StatusCode::NO_CONTENT
+}
+
+pub async fn me(State((state, _args)): State<(StateType, Args)>, headers: HeaderMap) -> ApiResult {
+    let account = require_account(&state, &headers).await?;
+    Ok(Json(json!({
+        "username": account.username,
+        "role": account.role,
+        "created_at": account.created_at,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct RoleRequest {
+    pub role: AccountRole,
+}
+
+/// Appoint or demote an admin. Also takes `ADMIN_TOKEN`, which is how the
+/// first admin of a deployment is appointed.
+pub async fn set_role(
+    State((state, _args)): State<(StateType, Args)>,
+    headers: HeaderMap,
+    Extension(RealIp(ip)): Extension<RealIp>,
+    Path(username): Path<String>,
+    Json(request): Json<RoleRequest>,
+) -> ApiResult {
+    let actor = require_admin_or_token(&state, &headers).await?;
+    let mut state = lock_state(&state).await;
+    let account = state
+        .set_account_role(&username, request.role.clone())
+        .map_err(|e| api_error(StatusCode::NOT_FOUND, e))?;
+    info!(
+        "{} set the role of {} to {:?}",
+        actor, username, request.role
+    );
+    let action = match request.role {
+        AccountRole::Admin => "grant_admin",
+        AccountRole::User => "revoke_admin",
+    };
+    state.audit(
+        Some(ip),
+        AuditEvent::admin_action(&actor, action, &username),
+    );
+    Ok(Json(
+        json!({ "username": account.username, "role": account.role }),
+    ))
+}
@@ -0,0 +1,36 @@
+use axum::{extract::State, http::HeaderMap, http::StatusCode, Json};
+use serde_json::json;
+
+use crate::{
+    args::Args,
+    controllers::auth::{api_error, require_account, ApiResult},
+    models::{metrics::lock_state, state::StateType},
+};
+
+/// Devices paired to the caller's account, with their online status.
+pub async fn list_devices(
+    State((state, _args)): State<(StateType, Args)>,
+    headers: HeaderMap,
+) -> ApiResult {
+    let account = require_account(&state, &headers).await?;
+    let state = lock_state(&state).await;
+    let devices = state
+        .devices_for_owner(&account.username)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let devices: Vec<_> = devices
+        .into_iter()
+        .map(|device| {
+            json!({
+                "room": device.room,
+                "name": device.name,
+                "paired_at": device.paired_at,
+                "online": state.sessions.contains_key(&device.room),
+                "last_seen": state
+                    .known_devices
+                    .get(&device.room)
+                    .map(|known| known.last_seen),
+            })
+        })
+        .collect();
+    Ok(Json(json!({ "devices": devices })))
+}
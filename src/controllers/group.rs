@@ -0,0 +1,86 @@
+use std::collections::BTreeSet;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    args::Args,
+    controllers::auth::{api_error, require_account, require_admin, ApiError, ApiResult},
+    middleware::ip::RealIp,
+    models::{audit::AuditEvent, group::DeviceGroup, metrics::lock_state, state::StateType},
+};
+
+#[derive(Deserialize)]
+pub struct PutGroupRequest {
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub rooms: BTreeSet<String>,
+    #[serde(default)]
+    pub description: String,
+}
+
+pub async fn list_groups(
+    State((state, _args)): State<(StateType, Args)>,
+    headers: HeaderMap,
+) -> ApiResult {
+    let account = require_account(&state, &headers).await?;
+    let groups = lock_state(&state)
+        .await
+        .list_groups(Some(&account.username));
+    Ok(Json(json!({ "groups": groups })))
+}
+
+pub async fn put_group(
+    State((state, _args)): State<(StateType, Args)>,
+    headers: HeaderMap,
+    Extension(RealIp(ip)): Extension<RealIp>,
+    Path(name): Path<String>,
+    Json(request): Json<PutGroupRequest>,
+) -> ApiResult {
+    let admin = require_admin(&state, &headers).await?;
+    let group = DeviceGroup {
+        name,
+        parent: request.parent,
+        rooms: request.rooms,
+        description: request.description,
+    };
+    let mut state = lock_state(&state).await;
+    state
+        .put_group(group.clone())
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    info!(
+        "{} saved group {} via admin API",
+        admin.username, group.name
+    );
+    state.audit(
+        Some(ip),
+        AuditEvent::admin_action(&admin.username, "put_group", &group.name),
+    );
+    Ok(Json(json!(group)))
+}
+
+pub async fn delete_group(
+    State((state, _args)): State<(StateType, Args)>,
+    headers: HeaderMap,
+    Extension(RealIp(ip)): Extension<RealIp>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let admin = require_admin(&state, &headers).await?;
+    let mut state = lock_state(&state).await;
+    state
+        .delete_group(&name)
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    info!("{} deleted group {} via admin API", admin.username, name);
+    state.audit(
+        Some(ip),
+        AuditEvent::admin_action(&admin.username, "delete_group", &name),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
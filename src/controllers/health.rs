@@ -1,6 +1,170 @@
-use axum::Json;
-use serde_json::json;
+use std::time::{Duration, Instant};
 
-pub async fn health_check() -> Json<serde_json::Value> {
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
+use failure::Error;
+use log::info;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::time::timeout;
+
+use crate::{
+    args::Args,
+    controllers::auth::{api_error, require_admin, ApiResult},
+    middleware::ip::RealIp,
+    models::{
+        audit::AuditEvent,
+        health::{check_store, IceCheck, ServerMode},
+        metrics::lock_state,
+        state::configured_ice_servers,
+        state::StateType,
+    },
+    services::ice::{check_ice_servers, ICE_PROBE_TIMEOUT},
+};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// How long liveness waits for the state lock before reporting a deadlock.
+pub const LIVE_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long readiness waits for the state lock.
+pub const READY_LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+type HealthResponse = (StatusCode, Json<Value>);
+
+#[derive(Deserialize)]
+pub struct MaintenanceRequest {
+    pub enabled: bool,
+}
+
+pub async fn health_check() -> Json<Value> {
     Json(json!({ "status": "ok" }))
 }
+
+fn check_result(result: Result<(), Error>) -> (bool, Value) {
+    match result {
+        Ok(()) => (true, json!("ok")),
+        Err(e) => (false, json!(e.to_string())),
+    }
+}
+
+fn lock_timed_out() -> HealthResponse {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({
+            "status": "unavailable",
+            "version": VERSION,
+            "checks": { "state_lock": "timed out" },
+        })),
+    )
+}
+
+/// Whether the process is responsive: fails if the state lock cannot be
+/// taken in time, as a deadlocked server never recovers by itself.
+pub async fn live(State((state, _args)): State<(StateType, Args)>) -> HealthResponse {
+    let Ok(state) = timeout(LIVE_LOCK_TIMEOUT, lock_state(&state)).await else {
+        return lock_timed_out();
+    };
+    (
+        StatusCode::OK,
+        Json(json!({
+            "status": "ok",
+            "version": VERSION,
+            "uptime_secs": state.uptime_secs(),
+            "mode": state.mode,
+            "counters": state.health_counters(),
+        })),
+    )
+}
+
+/// Whether the server should receive traffic: the state lock is free, the
+/// store and the configured ICE servers respond, and it is neither in
+/// maintenance nor draining. ICE results are reused for `ICE_CHECK_TTL`.
+pub async fn ready(State((state, _args)): State<(StateType, Args)>) -> HealthResponse {
+    let Ok(locked_state) = timeout(READY_LOCK_TIMEOUT, lock_state(&state)).await else {
+        return lock_timed_out();
+    };
+    let store = locked_state.store.clone();
+    let mode = locked_state.mode;
+    let uptime_secs = locked_state.uptime_secs();
+    let counters = locked_state.health_counters();
+    let recent_ice_check = locked_state.recent_ice_check().cloned();
+    // The probes take a while, so they run without holding the lock
+    drop(locked_state);
+
+    let (store_ok, store) = check_result(check_store(&store));
+    let ice_servers = configured_ice_servers();
+    let ice_result = match recent_ice_check {
+        Some(check) => Some(check.result),
+        None if ice_servers.is_empty() => None,
+        None => {
+            let result = check_ice_servers(&ice_servers, ICE_PROBE_TIMEOUT)
+                .await
+                .map_err(|e| e.to_string());
+            // Not worth waiting for if the state is busy, the next probe retries
+            if let Ok(mut locked_state) = timeout(READY_LOCK_TIMEOUT, lock_state(&state)).await {
+                locked_state.ice_check = Some(IceCheck {
+                    checked_at: Instant::now(),
+                    result: result.clone(),
+                });
+            }
+            Some(result)
+        }
+    };
+    let (ice_ok, ice) = match ice_result {
+        None => (true, json!("not_configured")),
+        Some(Ok(())) => (true, json!("ok")),
+        Some(Err(e)) => (false, json!(e)),
+    };
+    let ready = store_ok && ice_ok && mode == ServerMode::Serving;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(json!({
+            "status": if ready { "ready" } else { "not_ready" },
+            "version": VERSION,
+            "uptime_secs": uptime_secs,
+            "mode": mode,
+            "checks": {
+                "state_lock": "ok",
+                "store": store,
+                "ice": ice,
+                "mode": if mode == ServerMode::Serving { json!("ok") } else { json!(mode) },
+            },
+            "counters": counters,
+        })),
+    )
+}
+
+/// Take the server out of rotation, or put it back.
+pub async fn set_maintenance(
+    State((state, _args)): State<(StateType, Args)>,
+    headers: HeaderMap,
+    Extension(RealIp(ip)): Extension<RealIp>,
+    Json(request): Json<MaintenanceRequest>,
+) -> ApiResult {
+    let admin = require_admin(&state, &headers).await?;
+    let mut state = lock_state(&state).await;
+    state
+        .set_maintenance(request.enabled)
+        .map_err(|e| api_error(StatusCode::CONFLICT, e))?;
+    info!(
+        "{} set maintenance mode to {} via admin API",
+        admin.username, request.enabled
+    );
+    let action = if request.enabled {
+        "enable_maintenance"
+    } else {
+        "disable_maintenance"
+    };
+    state.audit(
+        Some(ip),
+        AuditEvent::admin_action(&admin.username, action, "server"),
+    );
+    Ok(Json(json!({ "mode": state.mode })))
+}
@@ -0,0 +1,57 @@
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde_json::json;
+
+use crate::{
+    args::Args,
+    controllers::auth::{api_error, require_account, ApiResult},
+    models::{
+        history::{session_history, session_usage, HistoryQuery},
+        metrics::lock_state,
+        state::StateType,
+    },
+};
+
+/// Ended sessions, newest first. Admins see every room with IPs, other
+/// accounts the rooms paired to them.
+pub async fn list_sessions(
+    State((state, _args)): State<(StateType, Args)>,
+    headers: HeaderMap,
+    Query(query): Query<HistoryQuery>,
+) -> ApiResult {
+    let account = require_account(&state, &headers).await?;
+    // The store is read without holding the state lock
+    let (store, caller) = {
+        let state = lock_state(&state).await;
+        (
+            state.store.clone(),
+            state.caller_for(Some(&account.username)),
+        )
+    };
+    let sessions = session_history(&store, &query, &caller)
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(json!({ "sessions": sessions })))
+}
+
+/// Connected time per device over a time range.
+pub async fn usage(
+    State((state, _args)): State<(StateType, Args)>,
+    headers: HeaderMap,
+    Query(query): Query<HistoryQuery>,
+) -> ApiResult {
+    let account = require_account(&state, &headers).await?;
+    // The store is read without holding the state lock
+    let (store, caller) = {
+        let state = lock_state(&state).await;
+        (
+            state.store.clone(),
+            state.caller_for(Some(&account.username)),
+        )
+    };
+    let devices = session_usage(&store, &query, &caller)
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(json!({ "devices": devices })))
+}
@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    args::Args,
+    controllers::auth::{api_error, require_admin, ApiError, ApiResult},
+    middleware::ip::RealIp,
+    models::{
+        audit::AuditEvent, invite::DEFAULT_INVITE_TTL, metrics::lock_state, peer::ViewerRole,
+        state::StateType,
+    },
+};
+
+#[derive(Deserialize)]
+pub struct CreateInviteRequest {
+    pub room: String,
+    pub role: ViewerRole,
+    pub ttl_secs: Option<u64>,
+    pub max_uses: Option<u32>,
+}
+
+pub async fn create_invite(
+    State((state, _args)): State<(StateType, Args)>,
+    headers: HeaderMap,
+    Extension(RealIp(ip)): Extension<RealIp>,
+    Json(request): Json<CreateInviteRequest>,
+) -> ApiResult {
+    let admin = require_admin(&state, &headers).await?;
+    let mut state = lock_state(&state).await;
+    let (claims, token) = state
+        .create_invite(
+            &request.room,
+            request.role,
+            request
+                .ttl_secs
+                .map_or(DEFAULT_INVITE_TTL, Duration::from_secs),
+            request.max_uses.unwrap_or(1),
+        )
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    info!(
+        "{} created invite {} via admin API",
+        admin.username, claims.id
+    );
+    state.audit(
+        Some(ip),
+        AuditEvent::admin_action(&admin.username, "create_invite", &claims.id),
+    );
+    Ok(Json(json!({
+        "id": claims.id,
+        "room": claims.room,
+        "role": claims.role,
+        "token": token,
+        "expires_at": claims.exp,
+        "max_uses": claims.max_uses,
+    })))
+}
+
+pub async fn revoke_invite(
+    State((state, _args)): State<(StateType, Args)>,
+    headers: HeaderMap,
+    Extension(RealIp(ip)): Extension<RealIp>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let admin = require_admin(&state, &headers).await?;
+    let mut state = lock_state(&state).await;
+    state
+        .revoke_invite(&id, None)
+        .map_err(|e| api_error(StatusCode::NOT_FOUND, e))?;
+    info!("{} revoked invite {} via admin API", admin.username, id);
+    state.audit(
+        Some(ip),
+        AuditEvent::admin_action(&admin.username, "revoke_invite", &id),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
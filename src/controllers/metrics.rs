@@ -0,0 +1,11 @@
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::{
+    args::Args,
+    models::{metrics::lock_state, state::StateType},
+};
+
+pub async fn metrics(State((state, _args)): State<(StateType, Args)>) -> impl IntoResponse {
+    let body = lock_state(&state).await.render_metrics();
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
@@ -1,2 +1,11 @@
+pub mod admin;
+pub mod auth;
+pub mod device;
+pub mod group;
 pub mod health;
+pub mod history;
+pub mod invite;
+pub mod metrics;
+pub mod oidc;
+pub mod totp;
 pub mod websocket;
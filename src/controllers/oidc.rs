@@ -0,0 +1,93 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Redirect,
+    Json,
+};
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    args::Args,
+    controllers::auth::{api_error, ApiError, ApiResult},
+    models::{account::SESSION_TOKEN_TTL, metrics::lock_state, oidc::OidcConfig, state::StateType},
+    services::oidc,
+};
+
+#[derive(Deserialize)]
+pub struct CallbackParams {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+async fn oidc_config(state: &StateType) -> Result<OidcConfig, ApiError> {
+    lock_state(state)
+        .await
+        .oidc
+        .clone()
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "OpenID Connect is not configured"))
+}
+
+/// Redirect the browser to the identity provider's authorization endpoint.
+pub async fn oidc_login(
+    State((state, _args)): State<(StateType, Args)>,
+) -> Result<Redirect, ApiError> {
+    let config = oidc_config(&state).await?;
+    let metadata = oidc::discover(&config)
+        .await
+        .map_err(|e| api_error(StatusCode::BAD_GATEWAY, e))?;
+    let (login_state, nonce) = lock_state(&state).await.begin_oidc_login();
+    let url = oidc::authorization_url(&config, &metadata, &login_state, &nonce)
+        .map_err(|e| api_error(StatusCode::BAD_GATEWAY, e))?;
+    Ok(Redirect::to(&url))
+}
+
+/// Complete the authorization-code flow and issue a session token.
+pub async fn oidc_callback(
+    State((state, _args)): State<(StateType, Args)>,
+    Query(params): Query<CallbackParams>,
+) -> ApiResult {
+    let config = oidc_config(&state).await?;
+    if let Some(error) = params.error {
+        return Err(api_error(StatusCode::UNAUTHORIZED, error));
+    }
+    let (code, login_state) = params
+        .code
+        .zip(params.state)
+        .ok_or_else(|| api_error(StatusCode::BAD_REQUEST, "Missing code or state"))?;
+    let nonce = lock_state(&state)
+        .await
+        .finish_oidc_login(&login_state)
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+
+    let metadata = oidc::discover(&config)
+        .await
+        .map_err(|e| api_error(StatusCode::BAD_GATEWAY, e))?;
+    let id_token = oidc::exchange_code(&config, &metadata, &code)
+        .await
+        .map_err(|e| api_error(StatusCode::BAD_GATEWAY, e))?;
+    let claims = oidc::verify_id_token(&config, &metadata, &id_token, &nonce)
+        .await
+        .map_err(|e| api_error(StatusCode::UNAUTHORIZED, e))?;
+    let subject =
+        oidc::subject_from_claims(&claims).map_err(|e| api_error(StatusCode::UNAUTHORIZED, e))?;
+    let username = oidc::suggested_username(&config, &claims)
+        .map_err(|e| api_error(StatusCode::UNAUTHORIZED, e))?;
+    let role = oidc::map_role(&config, &claims);
+
+    let mut state = lock_state(&state).await;
+    let account = state
+        .provision_external_account(&config.issuer, &subject, &username, role)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let token = state.issue_token(&account.username);
+    info!("{} logged in through {}", account.username, config.issuer);
+    Ok(Json(json!({
+        "token": token,
+        "token_type": "Bearer",
+        "expires_in": SESSION_TOKEN_TTL.as_secs(),
+        "username": account.username,
+        "role": account.role,
+    })))
+}
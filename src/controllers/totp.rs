@@ -0,0 +1,114 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    args::Args,
+    controllers::auth::{api_error, require_admin, ApiError, ApiResult, Credentials},
+    middleware::ip::RealIp,
+    models::{account::Account, audit::AuditEvent, metrics::lock_state, state::StateType},
+    services::auth,
+};
+
+#[derive(Deserialize)]
+pub struct TotpRequired {
+    pub required: bool,
+}
+
+/// Verify the password, and the second factor when one is already enabled.
+/// Enrollment takes credentials rather than a session token so accounts that
+/// must enroll before they can log in are not locked out.
+async fn authenticate(state: &StateType, credentials: &Credentials) -> Result<Account, ApiError> {
+    let mut account = auth::verify_credentials(state, &credentials.username, &credentials.password)
+        .await
+        .map_err(|e| api_error(StatusCode::UNAUTHORIZED, e))?;
+    if account.totp_enabled {
+        let code = credentials
+            .totp
+            .as_deref()
+            .ok_or_else(|| api_error(StatusCode::UNAUTHORIZED, "Two-factor code required"))?;
+        auth::verify_second_factor(state, &mut account, code)
+            .await
+            .map_err(|e| api_error(StatusCode::UNAUTHORIZED, e))?;
+    }
+    Ok(account)
+}
+
+pub async fn enroll(
+    State((state, _args)): State<(StateType, Args)>,
+    Json(credentials): Json<Credentials>,
+) -> ApiResult {
+    let mut account = authenticate(&state, &credentials).await?;
+    let (secret, otpauth_uri) = lock_state(&state)
+        .await
+        .begin_totp_enrollment(&mut account)
+        .map_err(|e| api_error(StatusCode::CONFLICT, e))?;
+    Ok(Json(
+        json!({ "secret": secret, "otpauth_uri": otpauth_uri }),
+    ))
+}
+
+/// Confirm the enrollment with a code from the new secret. `totp` carries
+/// that code here.
+pub async fn confirm(
+    State((state, _args)): State<(StateType, Args)>,
+    Json(credentials): Json<Credentials>,
+) -> ApiResult {
+    let code = credentials
+        .totp
+        .as_deref()
+        .ok_or_else(|| api_error(StatusCode::BAD_REQUEST, "Missing two-factor code"))?;
+    let mut account =
+        auth::verify_credentials(&state, &credentials.username, &credentials.password)
+            .await
+            .map_err(|e| api_error(StatusCode::UNAUTHORIZED, e))?;
+    let recovery_codes = auth::confirm_totp_enrollment(&state, &mut account, code)
+        .await
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(json!({ "recovery_codes": recovery_codes })))
+}
+
+pub async fn disable(
+    State((state, _args)): State<(StateType, Args)>,
+    Json(credentials): Json<Credentials>,
+) -> Result<StatusCode, ApiError> {
+    let mut account = authenticate(&state, &credentials).await?;
+    lock_state(&state)
+        .await
+        .disable_totp(&mut account)
+        .map_err(|e| api_error(StatusCode::FORBIDDEN, e))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn set_required(
+    State((state, _args)): State<(StateType, Args)>,
+    headers: HeaderMap,
+    Extension(RealIp(ip)): Extension<RealIp>,
+    Path(username): Path<String>,
+    Json(request): Json<TotpRequired>,
+) -> Result<StatusCode, ApiError> {
+    let admin = require_admin(&state, &headers).await?;
+    let mut state = lock_state(&state).await;
+    state
+        .set_totp_required(&username, request.required)
+        .map_err(|e| api_error(StatusCode::NOT_FOUND, e))?;
+    info!(
+        "{} set two-factor requirement of {} to {}",
+        admin.username, username, request.required
+    );
+    let action = if request.required {
+        "require_totp"
+    } else {
+        "unrequire_totp"
+    };
+    state.audit(
+        Some(ip),
+        AuditEvent::admin_action(&admin.username, action, &username),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,22 +1,47 @@
 use axum::{
-    extract::{ConnectInfo, State, WebSocketUpgrade},
-    response::IntoResponse,
+    extract::{ConnectInfo, Query, State, WebSocketUpgrade},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Extension,
 };
+use serde::Deserialize;
 use std::net::SocketAddr;
 
 use crate::{
-    args::Args, middleware::ip::RealIp, models::state::StateType,
+    args::Args,
+    controllers::auth::{api_error, bearer_token},
+    middleware::ip::RealIp,
+    models::{metrics::lock_state, state::StateType},
     services::websocket::handle_connection,
 };
 
+#[derive(Deserialize)]
+pub struct HandshakeParams {
+    /// Session token, for clients that cannot set an `Authorization` header.
+    pub token: Option<String>,
+}
+
 pub async fn websocket_handler(
     State((state, args)): State<(StateType, Args)>,
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Extension(RealIp(real_ip)): Extension<RealIp>,
-) -> impl IntoResponse {
+    Query(params): Query<HandshakeParams>,
+    headers: HeaderMap,
+) -> Response {
+    // A token presented during the handshake authenticates the connection
+    // before the first message, just like a `login` would.
+    let token = bearer_token(&headers).map(str::to_string).or(params.token);
+    if let Some(token) = &token {
+        if lock_state(&state).await.authenticate_token(token).is_none() {
+            return api_error(
+                StatusCode::UNAUTHORIZED,
+                failure::format_err!("Invalid or expired token"),
+            )
+            .into_response();
+        }
+    }
     ws.on_upgrade(move |socket| async move {
-        handle_connection(args, state, socket, addr, Some(&real_ip)).await
+        handle_connection(args, state, socket, addr, Some(&real_ip), token).await
     })
 }
@@ -0,0 +1,9 @@
+pub mod args;
+pub mod controllers;
+pub mod middleware;
+pub mod models;
+pub mod routes;
+pub mod services;
+
+#[cfg(test)]
+mod tests;
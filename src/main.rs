@@ -1,24 +1,20 @@
+use std::future::IntoFuture;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::str::FromStr;
+use std::time::Duration;
 
 use axum::serve;
 use clap::Parser;
 use log::info;
 use tokio::net::TcpListener;
 
-mod args;
-mod controllers;
-mod middleware;
-mod models;
-mod routes;
-mod services;
-
-use crate::args::Args;
-use crate::models::state::State;
-use crate::routes::router::create_router;
-
-#[cfg(test)]
-mod tests;
+use remo_auth::args::Args;
+use remo_auth::models::audit::{verify_audit_log, AuditLog, Rotation};
+use remo_auth::models::health::ServerMode;
+use remo_auth::models::state::State;
+use remo_auth::models::store::Store;
+use remo_auth::models::view::Audience;
+use remo_auth::routes::router::create_router;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -27,14 +23,46 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
 
     let args = Args::parse();
+    if let Some(dir) = &args.verify_audit {
+        let summary = verify_audit_log(dir)?;
+        println!(
+            "Verified {} audit entries starting at {:?}, last hash {}",
+            summary.entries, summary.first_seq, summary.last_hash
+        );
+        return Ok(());
+    }
     let address = &args.address.split(':').collect::<Vec<&str>>();
     let addr = SocketAddrV4::new(
         Ipv4Addr::from_str(address[0]).unwrap(),
         address[1].parse().unwrap(),
     );
 
-    let state = State::new();
-    let app = create_router(state, args);
+    let store = Store::open(&args.data_dir)?;
+    let state = State::with_store(store);
+    state.lock().await.history_retention = (args.history_retention_days > 0)
+        .then(|| Duration::from_secs(args.history_retention_days * 24 * 60 * 60));
+    if let Some(fields) = &args.anonymous_fields {
+        let mut state = state.lock().await;
+        state
+            .projection
+            .set_fields(Audience::Anonymous, fields.iter().copied());
+    }
+    if let Some(fields) = &args.owner_fields {
+        let mut state = state.lock().await;
+        state
+            .projection
+            .set_fields(Audience::Owner, fields.iter().copied());
+    }
+    if let Some(dir) = &args.audit_dir {
+        let rotation = Rotation {
+            max_bytes: args.audit_max_bytes,
+            max_age: Duration::from_secs(args.audit_max_age),
+        };
+        state.lock().await.audit_log = Some(AuditLog::open(dir, rotation)?);
+        info!("Writing audit log to {}", dir);
+    }
+    let drain = Duration::from_secs(args.drain_secs);
+    let app = create_router(state.clone(), args);
 
     info!("Server listening on {}", addr);
 
@@ -42,6 +70,35 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let listener = TcpListener::bind(addr).await?;
     let service = app.into_make_service_with_connect_info::<SocketAddr>();
     // Serve with both the listener and the service
-    serve(listener, service).await?;
+    tokio::select! {
+        result = serve(listener, service).into_future() => result?,
+        _ = shutdown_signal() => {
+            info!("Draining for {}s before shutting down", drain.as_secs());
+            state.lock().await.mode = ServerMode::Draining;
+            tokio::time::sleep(drain).await;
+        }
+    }
     Ok(())
 }
+
+/// Wait for Ctrl+C or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
@@ -0,0 +1,29 @@
+use axum::{
+    body::Body,
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{controllers::auth::require_admin_or_token, models::state::StateType};
+
+/// Let requests through only with `ADMIN_TOKEN` or the session token of an
+/// admin account, so no route of a router it wraps can skip the check.
+pub async fn admin_only(
+    State(state): State<StateType>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    match require_admin_or_token(&state, request.headers()).await {
+        Ok(actor) => {
+            request.extensions_mut().insert(AdminActor(actor));
+            next.run(request).await
+        }
+        Err(error) => error.into_response(),
+    }
+}
+
+// Who an admin request is logged under, set by `admin_only`
+#[derive(Clone)]
+pub struct AdminActor(pub String);
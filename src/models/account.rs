@@ -0,0 +1,405 @@
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Argon2,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use failure::{format_err, Error};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::models::connection::Connection;
+use crate::models::state::State;
+
+type Result<T> = std::result::Result<T, Error>;
+
+pub const ACCOUNTS_TREE: &str = "accounts";
+/// Username of each provider account, keyed by `external_identity_key`.
+pub const EXTERNAL_IDENTITIES_TREE: &str = "external_identities";
+
+/// How long a bearer session token stays valid after it was issued.
+pub const SESSION_TOKEN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Consecutive failed logins before an account is locked.
+pub const MAX_FAILED_LOGINS: u32 = 5;
+/// How long an account stays locked once it hit `MAX_FAILED_LOGINS`.
+pub const LOCKOUT_DURATION: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountRole {
+    #[default]
+    User,
+    Admin,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Account {
+    pub username: String,
+    pub password_hash: String,
+    pub role: AccountRole,
+    pub created_at: u64,
+    #[serde(default)]
+    pub failed_logins: u32,
+    #[serde(default)]
+    pub locked_until: u64,
+    /// Issuer of an account provisioned through an external identity
+    /// provider. Such accounts have no usable password.
+    #[serde(default)]
+    pub provider: Option<String>,
+    /// The provider's stable `sub` for this account. Together with
+    /// `provider` it identifies the account on every later login.
+    #[serde(default)]
+    pub subject: Option<String>,
+    /// Base32 TOTP secret. Only enforced once `totp_enabled` is set.
+    #[serde(default)]
+    pub totp_secret: Option<String>,
+    #[serde(default)]
+    pub totp_enabled: bool,
+    /// Login is refused until a second factor is enrolled, and it cannot be
+    /// disabled.
+    #[serde(default)]
+    pub totp_required: bool,
+    /// Last accepted TOTP time step, so a code cannot be replayed.
+    #[serde(default)]
+    pub totp_last_step: u64,
+    /// argon2 hashes of the unused recovery codes.
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
+}
+
+/// A bearer token issued by a successful login.
+pub struct AuthToken {
+    pub username: String,
+    pub expires_at: SystemTime,
+}
+
+/// Store key of an external identity. Issuers are URLs, which cannot
+/// contain a space, so the pair cannot collide.
+fn external_identity_key(provider: &str, subject: &str) -> String {
+    format!("{} {}", provider, subject)
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// URL-safe random token with `bytes` bytes of entropy.
+pub fn random_token(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buf);
+    URL_SAFE_NO_PAD.encode(buf)
+}
+
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format_err!("Failed to hash password: {}", e))
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|parsed| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+pub fn validate_credentials(username: &str, password: &str) -> Result<()> {
+    if username.len() < 3 || username.len() > 64 {
+        return Err(format_err!("Username must be between 3 and 64 characters"));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        return Err(format_err!(
+            "Username may only contain letters, digits, '.', '_' and '-'"
+        ));
+    }
+    if password.len() < 8 {
+        return Err(format_err!("Password must be at least 8 characters"));
+    }
+    Ok(())
+}
+
+/// The second factor `account` needs to log in, if any.
+pub fn second_factor_code<'a>(account: &Account, totp: Option<&'a str>) -> Result<Option<&'a str>> {
+    if account.totp_enabled {
+        totp.map(Some)
+            .ok_or_else(|| format_err!("Two-factor code required"))
+    } else if account.totp_required {
+        Err(format_err!("Two-factor enrollment required"))
+    } else {
+        Ok(None)
+    }
+}
+
+impl State {
+    pub fn get_account(&self, username: &str) -> Result<Option<Account>> {
+        self.store.get(ACCOUNTS_TREE, username)
+    }
+
+    pub fn save_account(&self, account: &Account) -> Result<()> {
+        self.store.put(ACCOUNTS_TREE, &account.username, account)
+    }
+
+    /// Replace `account` with its stored version, for accounts read before
+    /// the lock was last released.
+    pub fn refresh_account(&self, account: &mut Account) -> Result<()> {
+        if let Some(current) = self.get_account(&account.username)? {
+            *account = current;
+        }
+        Ok(())
+    }
+
+    pub fn check_username_available(&self, username: &str) -> Result<()> {
+        if self.get_account(username)?.is_some() {
+            return Err(format_err!("Username is already taken"));
+        }
+        Ok(())
+    }
+
+    /// Save a new account with an already hashed password. Accounts start
+    /// as users; admins are appointed with `set_account_role`, for the first
+    /// one through `ADMIN_TOKEN`.
+    pub fn create_account(&mut self, username: &str, password_hash: String) -> Result<Account> {
+        self.check_username_available(username)?;
+        let account = Account {
+            username: username.to_string(),
+            password_hash,
+            role: AccountRole::User,
+            created_at: now_secs(),
+            ..Default::default()
+        };
+        self.save_account(&account)?;
+        info!("Registered account {}", username);
+        Ok(account)
+    }
+
+    pub fn set_account_role(&mut self, username: &str, role: AccountRole) -> Result<Account> {
+        let mut account = self
+            .get_account(username)?
+            .ok_or_else(|| format_err!("Account does not exist"))?;
+        account.role = role;
+        self.save_account(&account)?;
+        self.refresh_connection_roles(&account);
+        Ok(account)
+    }
+
+    /// Update the role cached on the connections logged in as `account`, so
+    /// a promotion or demotion applies to what they are sent right away.
+    fn refresh_connection_roles(&mut self, account: &Account) {
+        for connection in self.connections.values_mut() {
+            let bound = connection
+                .token
+                .as_ref()
+                .and_then(|token| self.auth_tokens.get(token))
+                .is_some_and(|auth| auth.username == account.username);
+            if bound {
+                connection.role = Some(account.role.clone());
+            }
+        }
+    }
+
+    /// Create or refresh the account bound to the `provider` and `subject`
+    /// pair. `suggested_username` only names a new account, with a numeric
+    /// suffix when taken; the provider may let users change it, so it never
+    /// selects an existing one. The role is re-applied on every login.
+    pub fn provision_external_account(
+        &mut self,
+        provider: &str,
+        subject: &str,
+        suggested_username: &str,
+        role: AccountRole,
+    ) -> Result<Account> {
+        let key = external_identity_key(provider, subject);
+        let linked = match self.store.get::<String>(EXTERNAL_IDENTITIES_TREE, &key)? {
+            Some(username) => self.get_account(&username)?,
+            None => None,
+        };
+        let account = match linked {
+            Some(account) => Account { role, ..account },
+            None => {
+                let username = self.free_username(suggested_username)?;
+                info!(
+                    "Provisioned account {} for {} from {}",
+                    username, subject, provider
+                );
+                Account {
+                    username,
+                    password_hash: String::new(),
+                    role,
+                    created_at: now_secs(),
+                    provider: Some(provider.to_string()),
+                    subject: Some(subject.to_string()),
+                    ..Default::default()
+                }
+            }
+        };
+        self.save_account(&account)?;
+        self.store
+            .put(EXTERNAL_IDENTITIES_TREE, &key, &account.username)?;
+        self.refresh_connection_roles(&account);
+        Ok(account)
+    }
+
+    /// `username`, or the first of `username-2`, `username-3`, ... that is
+    /// not taken.
+    fn free_username(&self, username: &str) -> Result<String> {
+        if self.get_account(username)?.is_none() {
+            return Ok(username.to_string());
+        }
+        for suffix in 2.. {
+            let candidate = format!("{}-{}", username, suffix);
+            if self.get_account(&candidate)?.is_none() {
+                return Ok(candidate);
+            }
+        }
+        unreachable!()
+    }
+
+    /// The account about to be logged into, unless it is locked. Locked
+    /// accounts fail like unknown usernames, so neither reveals which
+    /// accounts exist.
+    pub fn account_for_login(&self, username: &str) -> Result<Account> {
+        self.get_account(username)?
+            .filter(|account| account.locked_until <= now_secs())
+            .ok_or_else(|| format_err!("Invalid username or password"))
+    }
+
+    /// Record the outcome of a password check. The account is read again as
+    /// the check may have run without the lock held.
+    pub fn finish_credentials(&mut self, username: &str, valid: bool) -> Result<Account> {
+        let mut account = self.account_for_login(username)?;
+        if !valid {
+            self.record_failed_login(&mut account)?;
+            return Err(format_err!("Invalid username or password"));
+        }
+        // With a second factor the counter is only reset once that passed too,
+        // so guessing TOTP codes still runs into the lockout
+        if account.failed_logins > 0 && !account.totp_enabled {
+            account.failed_logins = 0;
+            self.save_account(&account)?;
+        }
+        Ok(account)
+    }
+
+    /// Count a failed login against the stored account, locking it after too
+    /// many.
+    pub fn record_failed_login(&mut self, account: &mut Account) -> Result<()> {
+        self.refresh_account(account)?;
+        account.failed_logins += 1;
+        if account.failed_logins >= MAX_FAILED_LOGINS {
+            info!("Locking account {} after failed logins", account.username);
+            account.failed_logins = 0;
+            account.locked_until = now_secs() + LOCKOUT_DURATION.as_secs();
+        }
+        self.save_account(account)
+    }
+
+    /// Issue a bearer session token for an already verified account. Expired
+    /// tokens that were never presented again are dropped here.
+    pub fn issue_token(&mut self, username: &str) -> String {
+        let now = SystemTime::now();
+        self.auth_tokens.retain(|_, auth| auth.expires_at > now);
+        let token = random_token(32);
+        self.auth_tokens.insert(
+            token.clone(),
+            AuthToken {
+                username: username.to_string(),
+                expires_at: SystemTime::now() + SESSION_TOKEN_TTL,
+            },
+        );
+        token
+    }
+
+    pub fn logout(&mut self, token: &str) {
+        self.auth_tokens.remove(token);
+        for connection in self.connections.values_mut() {
+            if connection.token.as_deref() == Some(token) {
+                connection.token = None;
+                connection.role = None;
+            }
+        }
+    }
+
+    /// Resolve a bearer token to its account, dropping it if it expired.
+    pub fn authenticate_token(&mut self, token: &str) -> Option<Account> {
+        let expired = match self.auth_tokens.get(token) {
+            Some(auth) => auth.expires_at <= SystemTime::now(),
+            None => return None,
+        };
+        if expired {
+            self.logout(token);
+            return None;
+        }
+        let username = self.auth_tokens[token].username.clone();
+        self.get_account(&username).ok().flatten()
+    }
+
+    /// Bind a token to a WebSocket connection so that peers it creates carry
+    /// the account identity.
+    pub fn authenticate_connection(
+        &mut self,
+        socket_addr: SocketAddr,
+        token: &str,
+    ) -> Result<Account> {
+        let account = self
+            .authenticate_token(token)
+            .ok_or_else(|| format_err!("Invalid or expired token"))?;
+        let connection = self
+            .connections
+            .get_mut(&socket_addr)
+            .ok_or_else(|| format_err!("Unknown connection"))?;
+        connection.token = Some(token.to_string());
+        connection.role = Some(account.role.clone());
+        Ok(account)
+    }
+
+    pub fn logout_connection(&mut self, socket_addr: &SocketAddr) {
+        let token = self
+            .connections
+            .get_mut(socket_addr)
+            .and_then(|connection| connection.token.take());
+        if let Some(token) = token {
+            self.logout(&token);
+        }
+    }
+
+    pub fn connection_account(&self, socket_addr: &SocketAddr) -> Option<String> {
+        self.connections
+            .get(socket_addr)
+            .and_then(|connection| self.account_of(connection))
+            .map(str::to_string)
+    }
+
+    /// Account the connection is logged in as, unless its token expired.
+    pub fn account_of(&self, connection: &Connection) -> Option<&str> {
+        connection
+            .token
+            .as_ref()
+            .and_then(|token| self.auth_tokens.get(token))
+            .filter(|auth| auth.expires_at > SystemTime::now())
+            .map(|auth| auth.username.as_str())
+    }
+
+    /// Attach the connection's account, if any, to the peer `id`.
+    pub fn bind_peer_account(&mut self, id: &str, socket_addr: &SocketAddr) {
+        let account = self.connection_account(socket_addr);
+        if let Some(peer) = self.peers.get_mut(id) {
+            peer.account = account;
+        }
+    }
+}
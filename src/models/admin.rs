@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+
+use axum::extract::ws::Message;
+use failure::{format_err, Error};
+use serde::Serialize;
+
+use crate::models::history::{EndReason, ViewerVisit};
+use crate::models::peer::{ClientInfo, PeerType, ViewerRole};
+use crate::models::room::Visibility;
+use crate::models::rtc::SignallerMessage;
+use crate::models::state::State;
+use crate::models::view::unix_secs;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Name admin actions taken with `ADMIN_TOKEN` are logged under.
+pub const ADMIN_TOKEN_ACTOR: &str = "admin-token";
+
+/// A queued viewer, without its sender.
+#[derive(Clone, Debug, Serialize)]
+pub struct AdminQueuedViewer {
+    pub id: String,
+    pub role: ViewerRole,
+    pub ip: Option<IpAddr>,
+    pub account: Option<String>,
+    pub client: ClientInfo,
+}
+
+/// Everything the server knows about a running session.
+#[derive(Clone, Debug, Serialize)]
+pub struct AdminSession {
+    pub room: String,
+    pub server: String,
+    pub server_socket_addr: SocketAddr,
+    pub server_ip: Option<IpAddr>,
+    pub name: String,
+    pub os: String,
+    pub version: String,
+    pub control: bool,
+    pub owner: Option<String>,
+    pub controller: Option<String>,
+    pub max_viewers: Option<usize>,
+    pub viewers: Vec<String>,
+    pub waiting: Vec<AdminQueuedViewer>,
+    pub tags: BTreeMap<String, String>,
+    pub description: String,
+    pub visibility: Visibility,
+    /// Unix time, in seconds.
+    pub start_time: u64,
+    pub uptime_secs: u64,
+    /// Viewers that already left.
+    pub visits: Vec<ViewerVisit>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminPeer<'a> {
+    pub id: String,
+    pub room: String,
+    #[serde(flatten)]
+    pub peer_type: &'a PeerType,
+    pub account: Option<String>,
+    pub ip: Option<IpAddr>,
+    /// Unix time, in seconds.
+    pub joined_at: u64,
+    pub client: ClientInfo,
+    /// ID of the connection the peer belongs to.
+    pub connection: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AdminConnection {
+    pub id: String,
+    pub socket_addr: SocketAddr,
+    pub ip: Option<IpAddr>,
+    pub account: Option<String>,
+    /// Names of its room update subscriptions.
+    pub subscriptions: Vec<String>,
+    /// Peers it hosts or joined with.
+    pub peers: Vec<String>,
+}
+
+impl State {
+    /// Whether `token` is the `ADMIN_TOKEN` of the environment, compared in
+    /// constant time.
+    pub fn is_admin_token(&self, token: &str) -> bool {
+        let Some(admin_token) = &self.admin_token else {
+            return false;
+        };
+        admin_token.len() == token.len()
+            && admin_token
+                .bytes()
+                .zip(token.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    /// Running sessions, by room.
+    pub fn admin_sessions(&self) -> Vec<AdminSession> {
+        let mut sessions: Vec<AdminSession> = self
+            .sessions
+            .iter()
+            .map(|(room, session)| {
+                let mut viewers: Vec<String> = session.viewers.iter().cloned().collect();
+                viewers.sort();
+                AdminSession {
+                    room: room.clone(),
+                    server: session.server.clone(),
+                    server_socket_addr: session.server_socket_addr,
+                    server_ip: self.peers.get(&session.server).and_then(|peer| peer.ip),
+                    name: session.name.clone(),
+                    os: session.os.clone(),
+                    version: session.version.clone(),
+                    control: session.control,
+                    owner: session.owner.clone(),
+                    controller: session.controller.clone(),
+                    max_viewers: session.max_viewers,
+                    viewers,
+                    waiting: session
+                        .waiting
+                        .iter()
+                        .map(|queued| AdminQueuedViewer {
+                            id: queued.id.clone(),
+                            role: queued.role,
+                            ip: queued.ip,
+                            account: queued.account.clone(),
+                            client: queued.client.clone(),
+                        })
+                        .collect(),
+                    tags: session.tags.clone(),
+                    description: session.description.clone(),
+                    visibility: session.visibility,
+                    start_time: unix_secs(session.start_time),
+                    uptime_secs: session.start_time.elapsed().unwrap_or_default().as_secs(),
+                    visits: session.visits.clone(),
+                }
+            })
+            .collect();
+        sessions.sort_by(|a, b| a.room.cmp(&b.room));
+        sessions
+    }
+
+    /// Hosts and admitted viewers, by ID.
+    pub fn admin_peers(&self) -> Vec<AdminPeer<'_>> {
+        let mut peers: Vec<AdminPeer> = self
+            .peers
+            .iter()
+            .map(|(id, peer)| AdminPeer {
+                id: id.clone(),
+                room: peer.room.clone(),
+                peer_type: &peer.peer_type,
+                account: peer.account.clone(),
+                ip: peer.ip,
+                joined_at: unix_secs(peer.joined_at),
+                client: peer.client.clone(),
+                connection: peer.connection.clone(),
+            })
+            .collect();
+        peers.sort_by(|a, b| a.id.cmp(&b.id));
+        peers
+    }
+
+    /// Open WebSockets, by connection ID.
+    pub fn admin_connections(&self) -> Vec<AdminConnection> {
+        let mut peers_by_connection: HashMap<&str, Vec<String>> = HashMap::new();
+        for (id, peer) in &self.peers {
+            if let Some(connection) = &peer.connection {
+                peers_by_connection
+                    .entry(connection)
+                    .or_default()
+                    .push(id.clone());
+            }
+        }
+        let mut connections: Vec<AdminConnection> = self
+            .connections
+            .iter()
+            .map(|(socket_addr, connection)| {
+                let mut subscriptions: Vec<String> = self
+                    .room_update_subscribers
+                    .get(&connection.id)
+                    .map(|subscriptions| subscriptions.keys().cloned().collect())
+                    .unwrap_or_default();
+                subscriptions.sort();
+                let mut peers = peers_by_connection
+                    .remove(connection.id.as_str())
+                    .unwrap_or_default();
+                peers.sort();
+                AdminConnection {
+                    id: connection.id.clone(),
+                    socket_addr: *socket_addr,
+                    ip: connection.ip,
+                    account: self.account_of(connection).map(str::to_string),
+                    subscriptions,
+                    peers,
+                }
+            })
+            .collect();
+        connections.sort_by_key(|connection| {
+            // conn-<n>, in the order they were opened
+            connection
+                .id
+                .trim_start_matches("conn-")
+                .parse::<u64>()
+                .unwrap_or_default()
+        });
+        connections
+    }
+
+    /// End the session of `room` as if its host left, telling every viewer
+    /// with `server_closed`.
+    pub fn close_room(&mut self, room: &str) -> Result<()> {
+        if !self.sessions.contains_key(room) {
+            return Err(format_err!("Device is offline"));
+        }
+        self.remove_session(&room.to_string(), EndReason::Closed);
+        Ok(())
+    }
+
+    /// Send `message` to peer `id` as is.
+    pub fn send_to_peer(&self, id: &str, message: &SignallerMessage) -> Result<()> {
+        let peer = self
+            .peers
+            .get(id)
+            .ok_or_else(|| format_err!("Peer does not exist"))?;
+        peer.sender
+            .unbounded_send(Message::Text(serde_json::to_string(message)?))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,330 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use data_encoding::HEXLOWER;
+use failure::{format_err, Error};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::account::now_secs;
+use crate::models::history::EndReason;
+use crate::models::state::State;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// The file entries are appended to. Rotated files are renamed to
+/// `audit-<seq of their last entry>.jsonl`.
+pub const AUDIT_FILE: &str = "audit.jsonl";
+/// `prev_hash` of the very first entry.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const HASH_FIELD: &str = ",\"hash\":\"";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JoinOutcome {
+    Admitted,
+    Queued,
+    Declined,
+}
+
+/// A security-relevant event.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    RoomStarted {
+        room: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        owner: Option<String>,
+    },
+    RoomStopped {
+        room: String,
+        reason: EndReason,
+    },
+    JoinAttempt {
+        room: String,
+        viewer: String,
+        outcome: JoinOutcome,
+        /// Why a join was declined.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+        invite: bool,
+    },
+    Kick {
+        room: String,
+        viewer: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    Ban {
+        room: String,
+        target: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        duration_secs: Option<u64>,
+    },
+    ControlGranted {
+        room: String,
+        viewer: String,
+    },
+    ControlRevoked {
+        room: String,
+        viewer: String,
+    },
+    /// Something done through an admin API.
+    AdminAction {
+        account: String,
+        action: String,
+        target: String,
+    },
+    IceServersIssued {
+        peer: String,
+        servers: usize,
+    },
+}
+
+impl AuditEvent {
+    pub fn admin_action(account: &str, action: &str, target: &str) -> Self {
+        AuditEvent::AdminAction {
+            account: account.to_string(),
+            action: action.to_string(),
+            target: target.to_string(),
+        }
+    }
+}
+
+/// An entry as written, without its own hash.
+#[derive(Serialize)]
+struct UnsignedEntry<'a> {
+    seq: u64,
+    /// Unix time, in seconds.
+    time: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ip: Option<IpAddr>,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+    prev_hash: &'a str,
+}
+
+/// The fields the verifier needs of an entry.
+#[derive(Deserialize)]
+struct ChainLink {
+    seq: u64,
+    prev_hash: String,
+    hash: String,
+}
+
+fn sha256_hex(text: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(text.as_bytes()))
+}
+
+/// Split a written line into the JSON that was hashed and the hash.
+fn parse_line(line: &str) -> Result<(String, ChainLink)> {
+    let link: ChainLink = serde_json::from_str(line)?;
+    let index = line
+        .rfind(HASH_FIELD)
+        .ok_or_else(|| format_err!("Entry has no hash"))?;
+    Ok((format!("{}}}", &line[..index]), link))
+}
+
+/// When the current file is rotated.
+#[derive(Clone, Debug)]
+pub struct Rotation {
+    pub max_bytes: u64,
+    pub max_age: Duration,
+}
+
+/// Append-only JSON lines log of security-relevant events. Each entry
+/// carries the SHA-256 of the previous one, so removing or changing an entry
+/// breaks the chain, also across rotated files.
+pub struct AuditLog {
+    dir: PathBuf,
+    rotation: Rotation,
+    file: File,
+    size: u64,
+    opened_at: SystemTime,
+    next_seq: u64,
+    last_hash: String,
+}
+
+/// Audit files of `dir` in the order they were written: rotated files
+/// first, then the current one.
+pub fn audit_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut rotated: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("audit-") && name.ends_with(".jsonl"))
+        })
+        .collect();
+    rotated.sort();
+    let current = dir.join(AUDIT_FILE);
+    if current.exists() {
+        rotated.push(current);
+    }
+    Ok(rotated)
+}
+
+/// Last entry of the newest file that has one.
+fn last_link(dir: &Path) -> Result<Option<ChainLink>> {
+    for path in audit_files(dir)?.iter().rev() {
+        let last = BufReader::new(File::open(path)?)
+            .lines()
+            .map_while(|line| line.ok())
+            .filter(|line| !line.trim().is_empty())
+            .last();
+        if let Some(line) = last {
+            return Ok(Some(parse_line(&line)?.1));
+        }
+    }
+    Ok(None)
+}
+
+impl AuditLog {
+    /// Open the log in `dir`, continuing the chain of the entries already
+    /// there.
+    pub fn open<P: AsRef<Path>>(dir: P, rotation: Rotation) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let (next_seq, last_hash) = match last_link(&dir)? {
+            Some(link) => (link.seq + 1, link.hash),
+            None => (0, GENESIS_HASH.to_string()),
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(AUDIT_FILE))?;
+        let size = file.metadata()?.len();
+        let opened_at = file
+            .metadata()?
+            .created()
+            .ok()
+            .filter(|_| size > 0)
+            .unwrap_or_else(SystemTime::now);
+        Ok(AuditLog {
+            dir,
+            rotation,
+            file,
+            size,
+            opened_at,
+            next_seq,
+            last_hash,
+        })
+    }
+
+    pub fn append(&mut self, ip: Option<IpAddr>, event: &AuditEvent) -> Result<()> {
+        if self.size > 0
+            && (self.size >= self.rotation.max_bytes
+                || self.opened_at.elapsed().unwrap_or_default() >= self.rotation.max_age)
+        {
+            self.rotate()?;
+        }
+        let unsigned = serde_json::to_string(&UnsignedEntry {
+            seq: self.next_seq,
+            time: now_secs(),
+            ip,
+            event,
+            prev_hash: &self.last_hash,
+        })?;
+        let hash = sha256_hex(&unsigned);
+        let line = format!(
+            "{}{}{}\"}}\n",
+            &unsigned[..unsigned.len() - 1],
+            HASH_FIELD,
+            hash
+        );
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()?;
+        self.size += line.len() as u64;
+        self.next_seq += 1;
+        self.last_hash = hash;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        // Only non-empty files are rotated, so the names are unique
+        fs::rename(
+            self.dir.join(AUDIT_FILE),
+            self.dir.join(format!(
+                "audit-{:020}.jsonl",
+                self.next_seq.saturating_sub(1)
+            )),
+        )?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(AUDIT_FILE))?;
+        self.size = 0;
+        self.opened_at = SystemTime::now();
+        Ok(())
+    }
+}
+
+/// Outcome of verifying an audit log.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AuditSummary {
+    pub entries: u64,
+    /// Sequence number of the oldest entry left. Not 0 if old rotated files
+    /// were deleted.
+    pub first_seq: Option<u64>,
+    pub last_hash: String,
+}
+
+/// Check the hash chain of every audit file in `dir`. Fails at the first
+/// entry that was changed, removed or reordered.
+pub fn verify_audit_log<P: AsRef<Path>>(dir: P) -> Result<AuditSummary> {
+    let mut first_seq = None;
+    let mut expected_seq = None;
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut count = 0;
+    for path in audit_files(dir.as_ref())? {
+        for (index, line) in BufReader::new(File::open(&path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let location = format!("{}:{}", path.display(), index + 1);
+            let (unsigned, link) =
+                parse_line(&line).map_err(|e| format_err!("{}: {}", location, e))?;
+            // Old rotated files may have been deleted, so the chain can
+            // start anywhere
+            if expected_seq.is_some_and(|seq| link.seq != seq)
+                || (expected_seq.is_some() || link.seq == 0) && link.prev_hash != prev_hash
+            {
+                return Err(format_err!(
+                    "{}: entry {} breaks the chain",
+                    location,
+                    link.seq
+                ));
+            }
+            if sha256_hex(&unsigned) != link.hash {
+                return Err(format_err!("{}: entry {} was modified", location, link.seq));
+            }
+            first_seq = first_seq.or(Some(link.seq));
+            expected_seq = Some(link.seq + 1);
+            prev_hash = link.hash;
+            count += 1;
+        }
+    }
+    Ok(AuditSummary {
+        entries: count,
+        first_seq,
+        last_hash: prev_hash,
+    })
+}
+
+impl State {
+    /// Write an event to the audit log, if one is configured, with the real
+    /// IP of the connection it came from.
+    pub fn audit(&mut self, ip: Option<IpAddr>, event: AuditEvent) {
+        let Some(audit_log) = &mut self.audit_log else {
+            return;
+        };
+        if let Err(e) = audit_log.append(ip, &event) {
+            warn!("Failed to write audit entry {:?}: {}", event, e);
+        }
+    }
+}
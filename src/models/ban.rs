@@ -0,0 +1,140 @@
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use axum::extract::ws::Message;
+use failure::{format_err, Error};
+use log::info;
+
+use crate::models::peer::PeerType;
+use crate::models::rtc::SignallerMessage;
+use crate::models::state::State;
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BanTarget {
+    Peer(String),
+    Ip(IpAddr),
+}
+
+#[derive(Clone, Debug)]
+pub struct Ban {
+    pub target: BanTarget,
+    /// `None` bans for as long as the server runs.
+    pub expires_at: Option<SystemTime>,
+}
+
+impl Ban {
+    fn is_active(&self, now: SystemTime) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    fn matches(&self, id: &str, ip: Option<IpAddr>) -> bool {
+        match &self.target {
+            BanTarget::Peer(peer) => peer == id,
+            BanTarget::Ip(banned) => ip == Some(*banned),
+        }
+    }
+}
+
+impl State {
+    /// Fail if `id` or `ip` is banned from `room`.
+    pub fn check_banned(&mut self, room: &str, id: &str, ip: Option<IpAddr>) -> Result<()> {
+        let now = SystemTime::now();
+        if let Some(bans) = self.room_bans.get_mut(room) {
+            bans.retain(|ban| ban.is_active(now));
+            if bans.iter().any(|ban| ban.matches(id, ip)) {
+                return Err(format_err!("You are banned from this room"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a viewer from the host's room and tell it why.
+    pub fn kick_viewer(&mut self, room: &str, viewer: &str, reason: Option<String>) -> Result<()> {
+        let peer = self
+            .peers
+            .get(viewer)
+            .filter(|peer| peer.room == room && matches!(peer.peer_type, PeerType::Viewer { .. }))
+            .ok_or_else(|| format_err!("Viewer is not in this room"))?;
+        let _ = peer
+            .sender
+            .unbounded_send(Message::Text(serde_json::to_string(
+                &SignallerMessage::Kicked {
+                    to: viewer.to_string(),
+                    room: room.to_string(),
+                    reason: reason.clone(),
+                },
+            )?));
+        info!("Kicked {} from room {}: {:?}", viewer, room, reason);
+        self.leave_session(viewer.to_string())
+    }
+
+    /// Ban a peer ID, or an IP address, from `room` and kick whoever matches.
+    /// Banning a connected peer also bans the IP it connected from.
+    pub fn ban(
+        &mut self,
+        room: &str,
+        viewer_or_ip: &str,
+        duration: Option<Duration>,
+    ) -> Result<()> {
+        let expires_at = duration.map(|duration| SystemTime::now() + duration);
+        let mut targets = vec![];
+        match viewer_or_ip.parse::<IpAddr>() {
+            Ok(ip) => targets.push(BanTarget::Ip(ip)),
+            Err(_) => {
+                targets.push(BanTarget::Peer(viewer_or_ip.to_string()));
+                if let Some(ip) = self
+                    .peers
+                    .get(viewer_or_ip)
+                    .filter(|peer| peer.room == room)
+                    .and_then(|peer| peer.ip)
+                {
+                    targets.push(BanTarget::Ip(ip));
+                }
+            }
+        }
+
+        let session = self
+            .sessions
+            .get(room)
+            .ok_or_else(|| format_err!("Device is offline"))?;
+        let bans: Vec<Ban> = targets
+            .into_iter()
+            .map(|target| Ban { target, expires_at })
+            .collect();
+        let kicked: Vec<String> = session
+            .viewers
+            .iter()
+            .filter(|viewer| {
+                let ip = self.peers.get(*viewer).and_then(|peer| peer.ip);
+                bans.iter().any(|ban| ban.matches(viewer, ip))
+            })
+            .cloned()
+            .collect();
+        let mut dequeued = false;
+        if let Some(session) = self.sessions.get_mut(room) {
+            let waiting = session.waiting.len();
+            session
+                .waiting
+                .retain(|queued| !bans.iter().any(|ban| ban.matches(&queued.id, queued.ip)));
+            dequeued = session.waiting.len() != waiting;
+        }
+        if dequeued {
+            self.notify_queue_positions(room);
+        }
+        info!(
+            "Banned {} from room {} until {:?}",
+            viewer_or_ip, room, expires_at
+        );
+        self.room_bans
+            .entry(room.to_string())
+            .or_default()
+            .extend(bans);
+
+        for viewer in kicked {
+            self.kick_viewer(room, &viewer, Some("Banned".to_string()))?;
+        }
+        Ok(())
+    }
+}
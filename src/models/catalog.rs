@@ -0,0 +1,509 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
+
+use crate::models::filter::{AnyOf, RoomFilter};
+use crate::models::listing::{sort_value, SortKey, SortOrder, SortValue};
+use crate::models::room::Visibility;
+use crate::models::search::searchable_text;
+use crate::models::selector::{Requirement, TagSelector};
+use crate::models::session::Session;
+use crate::models::state::State;
+
+const SORT_KEYS: [SortKey; 6] = [
+    SortKey::Name,
+    SortKey::ViewerCount,
+    SortKey::Os,
+    SortKey::Version,
+    SortKey::Uptime,
+    SortKey::StartTime,
+];
+
+/// Below this share of the fleet, matching rooms are sorted directly instead
+/// of walking a sort index and skipping the rooms that do not match.
+const SELECTIVE_FRACTION: usize = 16;
+
+/// Normalized copy of the listed fields of a room.
+struct CatalogEntry {
+    name: String,
+    os: String,
+    version: String,
+    server: String,
+    control: bool,
+    owner: Option<String>,
+    tags: BTreeMap<String, String>,
+    visibility: Visibility,
+    groups: BTreeSet<String>,
+    sort_values: HashMap<SortKey, SortValue>,
+    /// Trigrams of every field `search_rooms` looks at.
+    search_trigrams: HashSet<String>,
+}
+
+/// Rooms indexed for listing, so that a page costs about as much as its
+/// size instead of a scan of every session. Kept in sync with the sessions
+/// through `State::index_room`.
+#[derive(Default)]
+pub struct RoomCatalog {
+    entries: HashMap<String, CatalogEntry>,
+    by_os: HashMap<String, HashSet<String>>,
+    by_version: HashMap<String, HashSet<String>>,
+    by_server: HashMap<String, HashSet<String>>,
+    by_control: HashMap<bool, HashSet<String>>,
+    by_owner: HashMap<String, HashSet<String>>,
+    by_tag_key: HashMap<String, HashSet<String>>,
+    by_tag: HashMap<(String, String), HashSet<String>>,
+    /// Lowercased group names, including the groups above the ones listing
+    /// the room.
+    by_group: HashMap<String, HashSet<String>>,
+    /// Rooms not listed to everyone.
+    unlisted: HashSet<String>,
+    /// Lowercased name trigrams, for substring search.
+    by_trigram: HashMap<String, HashSet<String>>,
+    /// Folded trigrams of all searchable fields, see `search_candidates`.
+    by_search_trigram: HashMap<String, HashSet<String>>,
+    sorted: HashMap<SortKey, BTreeSet<(SortValue, String)>>,
+}
+
+/// Overlapping three-character windows of an already lowercased string.
+fn trigrams(text: &str) -> HashSet<String> {
+    let chars: Vec<char> = text.chars().collect();
+    chars
+        .windows(3)
+        .map(|window| window.iter().collect())
+        .collect()
+}
+
+fn insert_into(index: &mut HashMap<String, HashSet<String>>, key: &str, room: &str) {
+    index
+        .entry(key.to_string())
+        .or_default()
+        .insert(room.to_string());
+}
+
+fn remove_from<K: std::hash::Hash + Eq>(
+    index: &mut HashMap<K, HashSet<String>>,
+    key: &K,
+    room: &str,
+) {
+    if let Some(rooms) = index.get_mut(key) {
+        rooms.remove(room);
+        if rooms.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+/// An index entry, or no rooms if there is none.
+fn rooms_in(rooms: Option<&HashSet<String>>) -> Cow<'_, HashSet<String>> {
+    rooms.map_or_else(|| Cow::Owned(HashSet::new()), Cow::Borrowed)
+}
+
+/// Rooms in any of the index entries for `values`, `None` if unfiltered.
+/// Only a filter on several values builds a new set.
+fn any_of<'a>(
+    index: &'a HashMap<String, HashSet<String>>,
+    values: &AnyOf<String>,
+) -> Option<Cow<'a, HashSet<String>>> {
+    match &values.0[..] {
+        [] => None,
+        [value] => Some(rooms_in(index.get(&value.to_lowercase()))),
+        values => Some(Cow::Owned(
+            values
+                .iter()
+                .filter_map(|value| index.get(&value.to_lowercase()))
+                .flatten()
+                .cloned()
+                .collect(),
+        )),
+    }
+}
+
+/// The rooms a listing is narrowed to: those in every one of `sets` that
+/// pass the selector and name checks and are listed to `account`. Built by
+/// `RoomCatalog::candidates`, borrowing the indexes rather than copying
+/// them.
+pub struct Candidates<'a> {
+    catalog: &'a RoomCatalog,
+    sets: Vec<Cow<'a, HashSet<String>>>,
+    selector: Option<&'a TagSelector>,
+    /// Lowercased name substrings, any of which has to match.
+    names: Vec<String>,
+    account: Option<&'a str>,
+}
+
+impl Candidates<'_> {
+    fn is_filtered(&self) -> bool {
+        !self.sets.is_empty() || self.selector.is_some() || !self.names.is_empty()
+    }
+
+    fn smallest(&self) -> Option<&HashSet<String>> {
+        self.sets
+            .iter()
+            .map(|rooms| rooms.as_ref())
+            .min_by_key(|rooms| rooms.len())
+    }
+
+    fn matches(&self, room: &str) -> bool {
+        let Some(entry) = self.catalog.entries.get(room) else {
+            return false;
+        };
+        entry
+            .visibility
+            .is_listed_for(entry.owner.as_deref(), self.account)
+            && self.sets.iter().all(|rooms| rooms.contains(room))
+            && self
+                .selector
+                .is_none_or(|selector| selector.matches(&entry.tags))
+            && (self.names.is_empty()
+                || self
+                    .names
+                    .iter()
+                    .any(|name| entry.name.contains(name.as_str())))
+    }
+
+    /// Number of matching rooms. Walks the smallest index set, or nothing
+    /// at all when unfiltered.
+    pub fn count(&self) -> usize {
+        if !self.is_filtered() {
+            return self.catalog.len() - self.catalog.hidden_count(self.account);
+        }
+        match self.smallest() {
+            Some(rooms) => rooms.iter().filter(|room| self.matches(room)).count(),
+            None => self
+                .catalog
+                .entries
+                .keys()
+                .filter(|room| self.matches(room))
+                .count(),
+        }
+    }
+
+    /// Up to `limit` matching rooms in listing order, starting after `after`
+    /// and skipping `skip` rooms.
+    pub fn page(
+        &self,
+        sort_by: SortKey,
+        sort: SortOrder,
+        after: Option<(SortValue, String)>,
+        skip: usize,
+        limit: usize,
+    ) -> Vec<(SortValue, String)> {
+        let catalog = self.catalog;
+        if let Some(rooms) = self
+            .smallest()
+            .filter(|rooms| rooms.len() * SELECTIVE_FRACTION < catalog.len())
+        {
+            let mut rooms: Vec<(SortValue, String)> = rooms
+                .iter()
+                .filter(|room| self.matches(room))
+                .filter_map(|room| {
+                    let value = catalog.entries.get(room)?.sort_values.get(&sort_by)?;
+                    Some((value.clone(), room.clone()))
+                })
+                .filter(|key| match (&after, sort) {
+                    (None, _) => true,
+                    (Some(after), SortOrder::Asc) => key > after,
+                    (Some(after), SortOrder::Desc) => key < after,
+                })
+                .collect();
+            rooms.sort();
+            if sort == SortOrder::Desc {
+                rooms.reverse();
+            }
+            return rooms.into_iter().skip(skip).take(limit).collect();
+        }
+
+        // Walk the sort index, which stops as soon as the page is full
+        let Some(sorted) = catalog.sorted.get(&sort_by) else {
+            return vec![];
+        };
+        let range: Box<dyn Iterator<Item = &(SortValue, String)>> = match (&after, sort) {
+            (None, SortOrder::Asc) => Box::new(sorted.iter()),
+            (None, SortOrder::Desc) => Box::new(sorted.iter().rev()),
+            (Some(after), SortOrder::Asc) => {
+                Box::new(sorted.range((Bound::Excluded(after), Bound::Unbounded)))
+            }
+            (Some(after), SortOrder::Desc) => Box::new(
+                sorted
+                    .range((Bound::Unbounded, Bound::Excluded(after)))
+                    .rev(),
+            ),
+        };
+        range
+            .filter(|(_, room)| self.matches(room))
+            .skip(skip)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+impl RoomCatalog {
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Add a room, or re-index it after its session or groups changed.
+    pub fn upsert(&mut self, room: &str, session: &Session, groups: BTreeSet<String>) {
+        self.remove(room);
+        let entry = CatalogEntry {
+            name: session.name.to_lowercase(),
+            os: session.os.to_lowercase(),
+            version: session.version.to_lowercase(),
+            server: session.server.to_lowercase(),
+            control: session.control,
+            owner: session.owner.clone(),
+            tags: session.tags.clone(),
+            visibility: session.visibility,
+            groups: groups.iter().map(|group| group.to_lowercase()).collect(),
+            sort_values: SORT_KEYS
+                .iter()
+                .map(|key| (*key, sort_value(session, *key)))
+                .collect(),
+            search_trigrams: searchable_text(room, session)
+                .iter()
+                .flat_map(|text| trigrams(text))
+                .collect(),
+        };
+
+        insert_into(&mut self.by_os, &entry.os, room);
+        insert_into(&mut self.by_version, &entry.version, room);
+        insert_into(&mut self.by_server, &entry.server, room);
+        self.by_control
+            .entry(entry.control)
+            .or_default()
+            .insert(room.to_string());
+        if let Some(owner) = &entry.owner {
+            insert_into(&mut self.by_owner, owner, room);
+        }
+        if entry.visibility != Visibility::Public {
+            self.unlisted.insert(room.to_string());
+        }
+        for (key, value) in &entry.tags {
+            insert_into(&mut self.by_tag_key, key, room);
+            self.by_tag
+                .entry((key.clone(), value.clone()))
+                .or_default()
+                .insert(room.to_string());
+        }
+        for group in &entry.groups {
+            insert_into(&mut self.by_group, group, room);
+        }
+        for trigram in trigrams(&entry.name) {
+            insert_into(&mut self.by_trigram, &trigram, room);
+        }
+        for trigram in &entry.search_trigrams {
+            insert_into(&mut self.by_search_trigram, trigram, room);
+        }
+        for (key, value) in &entry.sort_values {
+            self.sorted
+                .entry(*key)
+                .or_default()
+                .insert((value.clone(), room.to_string()));
+        }
+        self.entries.insert(room.to_string(), entry);
+    }
+
+    pub fn remove(&mut self, room: &str) {
+        let Some(entry) = self.entries.remove(room) else {
+            return;
+        };
+        remove_from(&mut self.by_os, &entry.os, room);
+        remove_from(&mut self.by_version, &entry.version, room);
+        remove_from(&mut self.by_server, &entry.server, room);
+        remove_from(&mut self.by_control, &entry.control, room);
+        if let Some(owner) = &entry.owner {
+            remove_from(&mut self.by_owner, owner, room);
+        }
+        self.unlisted.remove(room);
+        for (key, value) in entry.tags {
+            remove_from(&mut self.by_tag_key, &key, room);
+            remove_from(&mut self.by_tag, &(key, value), room);
+        }
+        for group in &entry.groups {
+            remove_from(&mut self.by_group, group, room);
+        }
+        for trigram in trigrams(&entry.name) {
+            remove_from(&mut self.by_trigram, &trigram, room);
+        }
+        for trigram in &entry.search_trigrams {
+            remove_from(&mut self.by_search_trigram, trigram, room);
+        }
+        for (key, value) in entry.sort_values {
+            if let Some(sorted) = self.sorted.get_mut(&key) {
+                sorted.remove(&(value, room.to_string()));
+            }
+        }
+    }
+
+    /// Update a single sort key of `room`, for changes such as a viewer
+    /// joining that leave everything else as it was.
+    pub fn update_sort_value(&mut self, room: &str, key: SortKey, value: SortValue) {
+        let Some(entry) = self.entries.get_mut(room) else {
+            return;
+        };
+        let old = entry.sort_values.insert(key, value.clone());
+        let sorted = self.sorted.entry(key).or_default();
+        if let Some(old) = old {
+            sorted.remove(&(old, room.to_string()));
+        }
+        sorted.insert((value, room.to_string()));
+    }
+
+    /// Rooms that may contain one of the lowercased `names`: for each name
+    /// the rooms with its rarest trigram. `None` when a name is too short for
+    /// the index.
+    fn named(&self, names: &[String]) -> Option<Cow<'_, HashSet<String>>> {
+        let mut rarest = vec![];
+        for name in names {
+            let rooms = trigrams(name)
+                .iter()
+                .map(|gram| rooms_in(self.by_trigram.get(gram)))
+                .min_by_key(|rooms| rooms.len())?;
+            rarest.push(rooms);
+        }
+        if rarest.len() <= 1 {
+            return rarest.pop();
+        }
+        Some(Cow::Owned(
+            rarest
+                .iter()
+                .flat_map(|rooms| rooms.iter())
+                .cloned()
+                .collect(),
+        ))
+    }
+
+    /// Rooms worth scoring first for the folded search `terms`: for every
+    /// term the rooms sharing one of its trigrams, which include every room
+    /// the term matches exactly or as a substring. Terms no room shares a
+    /// trigram with do not narrow, neither do terms shorter than three
+    /// characters. `None` if no term narrows.
+    pub fn search_candidates(&self, terms: &[Vec<char>]) -> Option<HashSet<&str>> {
+        let mut candidates: Option<HashSet<&str>> = None;
+        for term in terms {
+            let term: String = term.iter().collect();
+            let rooms: HashSet<&str> = trigrams(&term)
+                .iter()
+                .filter_map(|gram| self.by_search_trigram.get(gram))
+                .flatten()
+                .map(String::as_str)
+                .collect();
+            if rooms.is_empty() {
+                continue;
+            }
+            candidates = Some(match candidates {
+                None => rooms,
+                Some(candidates) => candidates
+                    .into_iter()
+                    .filter(|room| rooms.contains(room))
+                    .collect(),
+            });
+        }
+        candidates
+    }
+
+    /// Rooms not listed to the caller logged in as `account`. Hidden rooms
+    /// are only listed to their owner, so only the owner's rooms are looked
+    /// at.
+    fn hidden_count(&self, account: Option<&str>) -> usize {
+        let listed = account
+            .and_then(|account| self.by_owner.get(account))
+            .map_or(0, |rooms| {
+                rooms
+                    .iter()
+                    .filter(|room| {
+                        self.unlisted.contains(*room)
+                            && self.entries.get(*room).is_some_and(|entry| {
+                                entry
+                                    .visibility
+                                    .is_listed_for(entry.owner.as_deref(), account)
+                            })
+                    })
+                    .count()
+            });
+        self.unlisted.len() - listed
+    }
+
+    /// Rooms matching `filter` and `owner` that are listed to `account`.
+    pub fn candidates<'a>(
+        &'a self,
+        filter: &'a RoomFilter,
+        owner: Option<&str>,
+        account: Option<&'a str>,
+    ) -> Candidates<'a> {
+        let mut sets = vec![];
+        for (index, values) in [
+            (&self.by_os, &filter.os),
+            (&self.by_version, &filter.version),
+            (&self.by_server, &filter.server),
+            (&self.by_group, &filter.group),
+        ] {
+            sets.extend(any_of(index, values));
+        }
+        if let Some(control) = filter.control {
+            sets.push(rooms_in(self.by_control.get(&control)));
+        }
+        if let Some(owner) = owner {
+            sets.push(rooms_in(self.by_owner.get(owner)));
+        }
+        for (key, value) in &filter.tags {
+            sets.push(rooms_in(if value.is_empty() {
+                self.by_tag_key.get(key)
+            } else {
+                self.by_tag.get(&(key.clone(), value.clone()))
+            }));
+        }
+        if let Some(selector) = &filter.selector {
+            // Narrow by the requirements the indexes can answer, the whole
+            // selector is checked per room
+            for requirement in &selector.requirements {
+                match requirement {
+                    Requirement::Exists(key) => sets.push(rooms_in(self.by_tag_key.get(key))),
+                    Requirement::In(key, values) => sets.push(Cow::Owned(
+                        values
+                            .iter()
+                            .filter_map(|value| self.by_tag.get(&(key.clone(), value.clone())))
+                            .flatten()
+                            .cloned()
+                            .collect(),
+                    )),
+                    _ => {}
+                }
+            }
+        }
+        let names: Vec<String> = filter
+            .name
+            .0
+            .iter()
+            .map(|name| name.to_lowercase())
+            .collect();
+        sets.extend(self.named(&names));
+        Candidates {
+            catalog: self,
+            sets,
+            selector: filter.selector.as_ref(),
+            names,
+            account,
+        }
+    }
+}
+
+impl State {
+    /// Refresh the catalog entry of `room` after its session changed.
+    pub fn index_room(&mut self, room: &str) {
+        let groups = self.room_groups(room);
+        if let Some(session) = self.sessions.get(room) {
+            self.catalog.upsert(room, session, groups);
+        }
+    }
+
+    /// Refresh only the viewer count of `room` in the catalog.
+    pub fn index_viewer_count(&mut self, room: &str) {
+        if let Some(session) = self.sessions.get(room) {
+            let value = sort_value(session, SortKey::ViewerCount);
+            self.catalog
+                .update_sort_value(room, SortKey::ViewerCount, value);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::extract::ws::Message;
+use futures_channel::mpsc::UnboundedSender;
+use log::info;
+
+use crate::models::account::AccountRole;
+use crate::models::history::EndReason;
+use crate::models::state::State;
+
+type Tx = UnboundedSender<Message>;
+
+/// An open WebSocket, whether it hosts a room, joined one or only browses
+/// the lobby.
+pub struct Connection {
+    pub id: String,
+    pub sender: Tx,
+    /// Client address reported by the `real_ip` middleware.
+    pub ip: Option<IpAddr>,
+    /// Session token the connection authenticated with, if any.
+    pub token: Option<String>,
+    /// Role of the account behind `token`, so fanning out events does not
+    /// read the account store per connection. Updated when the role changes.
+    pub role: Option<AccountRole>,
+}
+
+impl State {
+    /// Register a new socket and return its connection ID.
+    pub fn register_connection(
+        &mut self,
+        socket_addr: SocketAddr,
+        sender: Tx,
+        ip: Option<IpAddr>,
+    ) -> String {
+        self.next_connection_id += 1;
+        let id = format!("conn-{}", self.next_connection_id);
+        info!("Registered connection {} for {}", id, socket_addr);
+        self.connections.insert(
+            socket_addr,
+            Connection {
+                id: id.clone(),
+                sender,
+                ip,
+                token: None,
+                role: None,
+            },
+        );
+        id
+    }
+
+    /// ID of the connection at `socket_addr`, registering it on first use.
+    pub fn connection_id(&mut self, socket_addr: SocketAddr, sender: &Tx) -> String {
+        match self.connections.get(&socket_addr) {
+            Some(connection) => connection.id.clone(),
+            None => self.register_connection(socket_addr, sender.clone(), None),
+        }
+    }
+
+    /// ID of the connection sending through `sender`.
+    pub fn connection_of(&self, sender: &Tx) -> Option<String> {
+        self.connections
+            .values()
+            .find(|connection| connection.sender.same_receiver(sender))
+            .map(|connection| connection.id.clone())
+    }
+
+    pub fn connection_ip(&self, socket_addr: &SocketAddr) -> Option<IpAddr> {
+        self.connections
+            .get(socket_addr)
+            .and_then(|connection| connection.ip)
+    }
+
+    /// Drop a closed connection together with its subscriptions, the room it
+    /// hosted and the rooms and queues it joined.
+    pub fn on_disconnect(&mut self, socket_addr: &SocketAddr) {
+        if let Some(room) = self.server_socket_addr_to_room.get(socket_addr) {
+            self.remove_session(&room.clone(), EndReason::Disconnected);
+        }
+        let Some(connection) = self.connections.remove(socket_addr) else {
+            return;
+        };
+        info!("Connection {} closed", connection.id);
+        self.room_update_subscribers.remove(&connection.id);
+
+        let mut joined: Vec<String> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.sender.same_receiver(&connection.sender))
+            .map(|(id, _)| id.clone())
+            .collect();
+        joined.extend(self.sessions.values().flat_map(|session| {
+            session
+                .waiting
+                .iter()
+                .filter(|queued| queued.sender.same_receiver(&connection.sender))
+                .map(|queued| queued.id.clone())
+        }));
+        for id in joined {
+            let _ = self.leave_session(id);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use axum::extract::ws::Message;
+use failure::{format_err, Error};
+use log::info;
+
+use crate::models::peer::{PeerType, ViewerRole};
+use crate::models::rtc::SignallerMessage;
+use crate::models::state::State;
+
+type Result<T> = std::result::Result<T, Error>;
+
+impl State {
+    /// Room of `viewer`, provided it may take input control there.
+    fn controllable_room(&self, viewer: &str) -> Result<String> {
+        let peer = self
+            .peers
+            .get(viewer)
+            .ok_or_else(|| format_err!("Peer does not exist"))?;
+        match peer.peer_type {
+            PeerType::Viewer {
+                role: ViewerRole::Control,
+            } => {}
+            PeerType::Viewer { .. } => return Err(format_err!("Viewer is view-only")),
+            PeerType::Server {} => return Err(format_err!("Peer is not a viewer")),
+        }
+        let session = self
+            .sessions
+            .get(&peer.room)
+            .ok_or_else(|| format_err!("Device is offline"))?;
+        if !session.control {
+            return Err(format_err!("Device does not allow remote control"));
+        }
+        Ok(peer.room.clone())
+    }
+
+    /// Check that `viewer` may ask for control and return the room whose
+    /// host should decide.
+    pub fn request_control(&self, viewer: &str) -> Result<String> {
+        let room = self.controllable_room(viewer)?;
+        if self.sessions[&room].controller.as_deref() == Some(viewer) {
+            return Err(format_err!("Viewer already holds control"));
+        }
+        Ok(room)
+    }
+
+    /// Hand input control of `room` to `viewer`, taking it from whoever held
+    /// it before.
+    pub fn grant_control(&mut self, room: &str, viewer: &str) -> Result<()> {
+        if self.controllable_room(viewer)? != room {
+            return Err(format_err!("Viewer is not in this room"));
+        }
+        self.set_controller(room, Some(viewer.to_string()));
+        Ok(())
+    }
+
+    pub fn revoke_control(&mut self, room: &str, viewer: &str) -> Result<()> {
+        let session = self
+            .sessions
+            .get(room)
+            .ok_or_else(|| format_err!("Device is offline"))?;
+        if session.controller.as_deref() != Some(viewer) {
+            return Err(format_err!("Viewer does not hold control"));
+        }
+        self.set_controller(room, None);
+        Ok(())
+    }
+
+    pub fn release_control(&mut self, viewer: &str) -> Result<()> {
+        let room = self
+            .peers
+            .get(viewer)
+            .map(|peer| peer.room.clone())
+            .ok_or_else(|| format_err!("Peer does not exist"))?;
+        self.revoke_control(&room, viewer)
+    }
+
+    /// Record the new controller and tell the whole room about it.
+    pub fn set_controller(&mut self, room: &str, controller: Option<String>) {
+        let Some(session) = self.sessions.get_mut(room) else {
+            return;
+        };
+        if session.controller == controller {
+            return;
+        }
+        info!("Control of room {} is now held by {:?}", room, controller);
+        session.controller = controller.clone();
+        self.broadcast_to_room(
+            room,
+            &SignallerMessage::ControlChanged {
+                room: room.to_string(),
+                controller,
+            },
+        );
+    }
+
+    /// Send `msg` to the host and every viewer of `room`.
+    pub fn broadcast_to_room(&self, room: &str, msg: &SignallerMessage) {
+        let Some(session) = self.sessions.get(room) else {
+            return;
+        };
+        let text = serde_json::to_string(msg).unwrap();
+        for id in std::iter::once(&session.server).chain(session.viewers.iter()) {
+            if let Some(peer) = self.peers.get(id) {
+                let _ = peer.sender.unbounded_send(Message::Text(text.clone()));
+            }
+        }
+    }
+}
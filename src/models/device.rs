@@ -0,0 +1,208 @@
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::extract::ws::Message;
+use data_encoding::HEXLOWER;
+use failure::{format_err, Error};
+use futures_channel::mpsc::UnboundedSender;
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::account::{now_secs, random_token};
+use crate::models::rtc::SignallerMessage;
+use crate::models::state::State;
+
+type Result<T> = std::result::Result<T, Error>;
+type Tx = UnboundedSender<Message>;
+
+pub const DEVICES_TREE: &str = "devices";
+
+/// How long a pairing code can be claimed after it was issued.
+pub const PAIRING_CODE_TTL: Duration = Duration::from_secs(10 * 60);
+const PAIRING_CODE_LENGTH: usize = 6;
+// No 0/O, 1/I/L so codes survive being read out loud
+const PAIRING_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+/// Failed claims an account may make within `PAIRING_GUESS_WINDOW`.
+pub const MAX_PAIRING_GUESSES: u32 = 5;
+pub const PAIRING_GUESS_WINDOW: Duration = PAIRING_CODE_TTL;
+/// Device secrets are stored as a plain SHA-256, which is only safe for
+/// random secrets, so short ones are refused.
+pub const MIN_DEVICE_SECRET_LENGTH: usize = 32;
+
+/// A host bound to an account through the pairing flow.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Device {
+    pub room: String,
+    /// SHA-256 of the secret the host presents in `start`.
+    pub secret_hash: String,
+    pub owner: String,
+    pub name: Option<String>,
+    pub paired_at: u64,
+}
+
+/// A host waiting for its pairing code to be claimed.
+pub struct PendingPairing {
+    pub secret_hash: String,
+    pub sender: Tx,
+    pub socket_addr: SocketAddr,
+    pub expires_at: SystemTime,
+}
+
+/// Failed pairing claims of one account.
+pub struct PairingGuesses {
+    pub count: u32,
+    pub window_ends: SystemTime,
+}
+
+pub fn hash_device_secret(secret: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(secret.as_bytes()))
+}
+
+fn generate_pairing_code() -> String {
+    (0..PAIRING_CODE_LENGTH)
+        .map(|_| {
+            let index = OsRng.next_u32() as usize % PAIRING_CODE_ALPHABET.len();
+            PAIRING_CODE_ALPHABET[index] as char
+        })
+        .collect()
+}
+
+fn normalize_pairing_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+impl State {
+    pub fn get_device(&self, room: &str) -> Result<Option<Device>> {
+        self.store.get(DEVICES_TREE, room)
+    }
+
+    pub fn devices_for_owner(&self, owner: &str) -> Result<Vec<Device>> {
+        Ok(self
+            .store
+            .values::<Device>(DEVICES_TREE)?
+            .into_iter()
+            .filter(|device| device.owner == owner)
+            .collect())
+    }
+
+    /// Issue a pairing code for an unpaired host. Any code previously issued
+    /// to the same connection is replaced.
+    pub fn request_pairing_code(
+        &mut self,
+        device_secret: &str,
+        sender: Tx,
+        socket_addr: SocketAddr,
+    ) -> Result<String> {
+        if device_secret.trim().len() < MIN_DEVICE_SECRET_LENGTH {
+            return Err(format_err!(
+                "Device secret must be at least {} characters",
+                MIN_DEVICE_SECRET_LENGTH
+            ));
+        }
+        let now = SystemTime::now();
+        self.pending_pairings
+            .retain(|_, pending| pending.expires_at > now && pending.socket_addr != socket_addr);
+
+        let mut code = generate_pairing_code();
+        while self.pending_pairings.contains_key(&code) {
+            code = generate_pairing_code();
+        }
+        self.pending_pairings.insert(
+            code.clone(),
+            PendingPairing {
+                secret_hash: hash_device_secret(device_secret),
+                sender,
+                socket_addr,
+                expires_at: now + PAIRING_CODE_TTL,
+            },
+        );
+        info!("Issued pairing code to {}", socket_addr);
+        Ok(code)
+    }
+
+    /// Bind the host that requested `code` to `owner` under a fresh room ID
+    /// and tell the host which room it was assigned. Accounts get
+    /// `MAX_PAIRING_GUESSES` wrong codes per `PAIRING_GUESS_WINDOW`.
+    pub fn claim_pairing_code(
+        &mut self,
+        code: &str,
+        owner: &str,
+        name: Option<String>,
+    ) -> Result<Device> {
+        let now = SystemTime::now();
+        self.pairing_guesses
+            .retain(|_, guesses| guesses.window_ends > now);
+        if self
+            .pairing_guesses
+            .get(owner)
+            .is_some_and(|guesses| guesses.count >= MAX_PAIRING_GUESSES)
+        {
+            return Err(format_err!("Too many pairing attempts, try again later"));
+        }
+        let pending = match self.pending_pairings.remove(&normalize_pairing_code(code)) {
+            Some(pending) => pending,
+            None => {
+                self.pairing_guesses
+                    .entry(owner.to_string())
+                    .or_insert(PairingGuesses {
+                        count: 0,
+                        window_ends: now + PAIRING_GUESS_WINDOW,
+                    })
+                    .count += 1;
+                return Err(format_err!("Invalid pairing code"));
+            }
+        };
+        if pending.expires_at <= SystemTime::now() {
+            return Err(format_err!("Pairing code has expired"));
+        }
+
+        let mut room = random_token(12);
+        while self.sessions.contains_key(&room) || self.get_device(&room)?.is_some() {
+            room = random_token(12);
+        }
+        let device = Device {
+            room,
+            secret_hash: pending.secret_hash,
+            owner: owner.to_string(),
+            name,
+            paired_at: now_secs(),
+        };
+        self.store.put(DEVICES_TREE, &device.room, &device)?;
+        info!("Paired device {} to {}", device.room, owner);
+
+        let _ = pending
+            .sender
+            .unbounded_send(Message::Text(serde_json::to_string(
+                &SignallerMessage::Paired {
+                    room: device.room.clone(),
+                    owner: device.owner.clone(),
+                },
+            )?));
+        Ok(device)
+    }
+
+    /// Resolve the owner of a room being started. Paired rooms must present
+    /// the secret they were paired with; unpaired rooms have no owner.
+    pub fn check_device_secret(
+        &self,
+        room: &str,
+        device_secret: Option<&str>,
+    ) -> Result<Option<String>> {
+        match self.get_device(room)? {
+            Some(device)
+                if device_secret.map(hash_device_secret).as_ref() == Some(&device.secret_hash) =>
+            {
+                Ok(Some(device.owner))
+            }
+            Some(_) => Err(format_err!(
+                "Device secret does not match the paired device"
+            )),
+            None => Ok(None),
+        }
+    }
+}
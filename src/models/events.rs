@@ -0,0 +1,256 @@
+use axum::extract::ws::Message;
+use futures_channel::mpsc::UnboundedSender;
+
+use crate::models::connection::Connection;
+use crate::models::filter::RoomFilter;
+use crate::models::room::RoomChanges;
+use crate::models::rtc::SignallerMessage;
+use crate::models::state::{RoomInfo, State};
+use crate::models::view::{Audience, Caller, Rendered};
+
+type Tx = UnboundedSender<Message>;
+
+/// How many past room events are kept for subscribers catching up.
+pub const ROOM_EVENT_HISTORY: usize = 1024;
+/// Name of a subscription the client did not name.
+pub const DEFAULT_SUBSCRIPTION: &str = "default";
+
+/// A published room event, kept for replay.
+pub struct RoomEvent {
+    pub version: u64,
+    pub texts: Rendered,
+    /// State of the room the event is about, before and after the event.
+    /// A subscription is interested if its filter matches either.
+    pub rooms: Vec<RoomInfo>,
+}
+
+fn is_listed(info: &RoomInfo, caller: &Caller) -> bool {
+    info.visibility
+        .is_listed_for(info.owner.as_deref(), caller.account())
+}
+
+/// How `caller` sees the room an event is about.
+fn audience(rooms: &[RoomInfo], caller: &Caller) -> Audience {
+    caller.audience(rooms.last().and_then(|info| info.owner.as_deref()))
+}
+
+impl State {
+    /// Add or replace the subscription `name` of a connection.
+    pub fn subscribe_room_updates(&mut self, connection: String, name: String, filter: RoomFilter) {
+        self.room_update_subscribers
+            .entry(connection)
+            .or_default()
+            .insert(name, filter);
+    }
+
+    /// Cancel one subscription, or all of them if `name` is `None`.
+    pub fn unsubscribe_room_updates(&mut self, connection: &str, name: Option<&str>) {
+        let Some(name) = name else {
+            self.room_update_subscribers.remove(connection);
+            return;
+        };
+        if let Some(subscriptions) = self.room_update_subscribers.get_mut(connection) {
+            subscriptions.remove(name);
+            if subscriptions.is_empty() {
+                self.room_update_subscribers.remove(connection);
+            }
+        }
+    }
+
+    /// Whether any subscription of `connection` matches any of `rooms` that
+    /// is listed to `caller`.
+    pub fn is_interested(
+        &self,
+        connection: &Connection,
+        caller: &Caller,
+        rooms: &[RoomInfo],
+    ) -> bool {
+        self.room_update_subscribers
+            .get(&connection.id)
+            .is_some_and(|subscriptions| {
+                subscriptions.values().any(|filter| {
+                    rooms
+                        .iter()
+                        .any(|info| is_listed(info, caller) && filter.matches(info))
+                })
+            })
+    }
+
+    /// Send every subscribed connection interested in `rooms`, except the
+    /// connections in `skip`, the text for its audience.
+    pub fn send_to_subscribers(&self, texts: &Rendered, rooms: &[RoomInfo], skip: &[String]) {
+        for connection in self.connections.values() {
+            if skip.contains(&connection.id)
+                || !self.room_update_subscribers.contains_key(&connection.id)
+            {
+                continue;
+            }
+            let caller = self.caller_of(connection);
+            if !self.is_interested(connection, &caller, rooms) {
+                continue;
+            }
+            let text = texts.get(audience(rooms, &caller));
+            let _ = connection
+                .sender
+                .unbounded_send(Message::Text(text.to_string()));
+        }
+    }
+
+    /// Stamp the next version onto an event, keep it for replay and send it
+    /// to the interested subscribers, except the connections in `skip`.
+    /// `event` renders the event for each audience. Returns the event as
+    /// anonymous callers see it.
+    fn publish_room_event(
+        &mut self,
+        rooms: Vec<RoomInfo>,
+        skip: &[String],
+        event: impl Fn(u64, Audience) -> SignallerMessage,
+    ) -> String {
+        self.room_event_version += 1;
+        let version = self.room_event_version;
+        let texts =
+            Rendered::new(|audience| serde_json::to_string(&event(version, audience)).unwrap());
+        self.send_to_subscribers(&texts, &rooms, skip);
+        if self.room_events.len() == ROOM_EVENT_HISTORY {
+            self.room_events.pop_front();
+        }
+        let text = texts.get(Audience::Anonymous).to_string();
+        self.room_events.push_back(RoomEvent {
+            version,
+            texts,
+            rooms,
+        });
+        text
+    }
+
+    pub fn publish_room_added(&mut self, room: &str) {
+        let Some(info) = self.room_info(room) else {
+            return;
+        };
+        let views = Audience::ALL.map(|audience| self.room_view(room, audience));
+        self.publish_room_event(vec![info], &[], |version, audience| {
+            SignallerMessage::RoomAdded {
+                version,
+                room: room.to_string(),
+                info: views[audience.index()].clone().unwrap(),
+            }
+        });
+    }
+
+    /// `info` is the state of the room right before it was removed.
+    pub fn publish_room_removed(&mut self, room: &str, info: RoomInfo) {
+        self.publish_room_event(vec![info], &[], |version, _| {
+            SignallerMessage::RoomRemoved {
+                version,
+                room: room.to_string(),
+            }
+        });
+    }
+
+    pub fn publish_viewer_count(&mut self, room: &str) {
+        if let Some(info) = self.room_info(room) {
+            let viewer_count = info.viewer_count;
+            self.publish_room_event(vec![info], &[], |version, _| {
+                SignallerMessage::ViewerCountChanged {
+                    version,
+                    room: room.to_string(),
+                    viewer_count,
+                }
+            });
+        }
+    }
+
+    /// Publish changed metadata. The room's own peers are always told, while
+    /// other subscribers only if the room matched their filter before or
+    /// after the change.
+    pub fn publish_room_updated(&mut self, room: &str, before: RoomInfo, changes: RoomChanges) {
+        let (Some(after), Some(session)) = (self.room_info(room), self.sessions.get(room)) else {
+            return;
+        };
+        let members: Vec<String> = std::iter::once(session.server.clone())
+            .chain(session.viewers.iter().cloned())
+            .collect();
+        let member_connections: Vec<String> = self
+            .connections
+            .values()
+            .filter(|connection| {
+                members.iter().any(|id| {
+                    self.peers
+                        .get(id)
+                        .is_some_and(|peer| peer.sender.same_receiver(&connection.sender))
+                })
+            })
+            .map(|connection| connection.id.clone())
+            .collect();
+        let text =
+            self.publish_room_event(vec![before, after], &member_connections, |version, _| {
+                SignallerMessage::RoomUpdated {
+                    version,
+                    room: room.to_string(),
+                    changes: changes.clone(),
+                }
+            });
+        for id in &members {
+            if let Some(peer) = self.peers.get(id) {
+                let _ = peer.sender.unbounded_send(Message::Text(text.clone()));
+            }
+        }
+    }
+
+    /// Bring a (re)subscribing client logged in as `account` up to date:
+    /// replay the events after version `since` that match `filter` if they
+    /// are all still kept, otherwise send a snapshot of the matching rooms.
+    pub fn send_room_events_since(
+        &self,
+        since: Option<u64>,
+        filter: &RoomFilter,
+        account: Option<&str>,
+        tx: &Tx,
+    ) {
+        let caller = self.caller_for(account);
+        let replayable = since.filter(|since| {
+            *since <= self.room_event_version
+                && self
+                    .room_events
+                    .front()
+                    .map_or(*since == self.room_event_version, |oldest| {
+                        oldest.version <= since + 1
+                    })
+        });
+        match replayable {
+            Some(since) => {
+                for event in self.room_events.iter().filter(|event| {
+                    event.version > since
+                        && event
+                            .rooms
+                            .iter()
+                            .any(|info| is_listed(info, &caller) && filter.matches(info))
+                }) {
+                    let text = event.texts.get(audience(&event.rooms, &caller));
+                    let _ = tx.unbounded_send(Message::Text(text.to_string()));
+                }
+            }
+            None => {
+                let mut rooms: Vec<RoomInfo> = self
+                    .sessions
+                    .keys()
+                    .filter_map(|room| self.room_info(room))
+                    .filter(|info| is_listed(info, &caller) && filter.matches(info))
+                    .filter_map(|info| {
+                        let audience = caller.audience(info.owner.as_deref());
+                        self.room_view(&info.room, audience)
+                    })
+                    .collect();
+                // In the default order of room listings
+                rooms.sort_by_cached_key(|info| (info.name.to_lowercase(), info.room.clone()));
+                let _ = tx.unbounded_send(Message::Text(
+                    serde_json::to_string(&SignallerMessage::RoomSnapshot {
+                        version: self.room_event_version,
+                        rooms,
+                    })
+                    .unwrap(),
+                ));
+            }
+        }
+    }
+}
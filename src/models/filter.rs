@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::selector::TagSelector;
+use crate::models::state::RoomInfo;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+/// Filter values of which any may match. Accepts a single value or a list,
+/// so `"os": "windows"` and `"os": ["windows", "linux"]` both work. Empty
+/// matches everything.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Option<OneOrMany<T>>")]
+pub struct AnyOf<T>(pub Vec<T>);
+
+impl<T> Default for AnyOf<T> {
+    fn default() -> Self {
+        AnyOf(vec![])
+    }
+}
+
+impl<T> From<Option<OneOrMany<T>>> for AnyOf<T> {
+    fn from(values: Option<OneOrMany<T>>) -> Self {
+        match values {
+            None => AnyOf(vec![]),
+            Some(OneOrMany::One(value)) => AnyOf(vec![value]),
+            Some(OneOrMany::Many(values)) => AnyOf(values),
+        }
+    }
+}
+
+impl<T> AnyOf<T> {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl AnyOf<String> {
+    fn matches(&self, actual: &str, matches: impl Fn(&str, &str) -> bool) -> bool {
+        if self.0.is_empty() {
+            return true;
+        }
+        let actual = actual.to_lowercase();
+        self.0
+            .iter()
+            .any(|expected| matches(&actual, &expected.to_lowercase()))
+    }
+}
+
+/// Room criteria shared by room listings and room update subscriptions.
+/// Unset fields match every room; text comparisons ignore case.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomFilter {
+    #[serde(default, skip_serializing_if = "AnyOf::is_empty")]
+    pub os: AnyOf<String>,
+    /// Substrings of the room name.
+    #[serde(default, skip_serializing_if = "AnyOf::is_empty")]
+    pub name: AnyOf<String>,
+    #[serde(default, skip_serializing_if = "AnyOf::is_empty")]
+    pub version: AnyOf<String>,
+    #[serde(default, skip_serializing_if = "AnyOf::is_empty")]
+    pub server: AnyOf<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control: Option<bool>,
+    /// Every tag must be present with this value. An empty value only
+    /// requires the key.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// Groups of which the room must be in any, directly or through a
+    /// subgroup.
+    #[serde(default, skip_serializing_if = "AnyOf::is_empty")]
+    pub group: AnyOf<String>,
+    /// Tag requirements such as `site=berlin,floor in (1, 2),!retired`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector: Option<TagSelector>,
+}
+
+/// The fields of a room a filter looks at, borrowed from wherever the room
+/// is kept.
+pub struct RoomFields<'a> {
+    pub name: &'a str,
+    pub os: &'a str,
+    pub version: &'a str,
+    pub server: Option<&'a str>,
+    pub control: bool,
+    pub tags: &'a BTreeMap<String, String>,
+}
+
+impl<'a> From<&'a RoomInfo> for RoomFields<'a> {
+    fn from(info: &'a RoomInfo) -> Self {
+        RoomFields {
+            name: &info.name,
+            os: &info.os,
+            version: &info.version,
+            server: info.server.as_deref(),
+            control: info.control,
+            tags: &info.tags,
+        }
+    }
+}
+
+impl RoomFilter {
+    pub fn matches(&self, info: &RoomInfo) -> bool {
+        self.matches_fields(&RoomFields::from(info), || info.groups.clone())
+    }
+
+    /// Match a room without building its `RoomInfo`. `groups` is only
+    /// called when the filter asks for a group.
+    pub fn matches_fields(&self, room: &RoomFields, groups: impl FnOnce() -> Vec<String>) -> bool {
+        self.os.matches(room.os, |actual, os| actual == os)
+            && self
+                .version
+                .matches(room.version, |actual, version| actual == version)
+            && self
+                .server
+                .matches(room.server.unwrap_or_default(), |actual, server| {
+                    actual == server
+                })
+            && self
+                .name
+                .matches(room.name, |actual, name| actual.contains(name))
+            && self.control.is_none_or(|control| room.control == control)
+            && self.tags.iter().all(|(key, value)| {
+                room.tags
+                    .get(key)
+                    .is_some_and(|actual| value.is_empty() || actual == value)
+            })
+            && (self.group.is_empty()
+                || groups()
+                    .iter()
+                    .any(|group| self.group.matches(group, |actual, group| actual == group)))
+            && self
+                .selector
+                .as_ref()
+                .is_none_or(|selector| selector.matches(room.tags))
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeSet, HashMap};
+
+use failure::{format_err, Error};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::models::state::State;
+
+type Result<T> = std::result::Result<T, Error>;
+
+pub const GROUPS_TREE: &str = "groups";
+pub const MAX_GROUP_NAME_LENGTH: usize = 64;
+/// How deeply groups can be nested, such as site, floor, kiosk.
+pub const MAX_GROUP_DEPTH: usize = 8;
+
+/// A named set of rooms maintained by admins. Groups can be nested, and a
+/// room belongs to every group above the ones listing it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceGroup {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub rooms: BTreeSet<String>,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// The groups listing each room directly, the reverse of
+/// `DeviceGroup::rooms`.
+pub fn groups_by_room<'a>(
+    groups: impl Iterator<Item = &'a DeviceGroup>,
+) -> HashMap<String, BTreeSet<String>> {
+    let mut by_room: HashMap<String, BTreeSet<String>> = HashMap::new();
+    for group in groups {
+        for room in &group.rooms {
+            by_room
+                .entry(room.clone())
+                .or_default()
+                .insert(group.name.clone());
+        }
+    }
+    by_room
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_GROUP_NAME_LENGTH {
+        return Err(format_err!(
+            "Group names must be between 1 and {} characters",
+            MAX_GROUP_NAME_LENGTH
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        return Err(format_err!(
+            "Group names may only contain letters, digits, '.', '_' and '-'"
+        ));
+    }
+    Ok(())
+}
+
+impl State {
+    /// The group and every group above it, innermost first.
+    pub fn group_ancestors(&self, name: &str) -> Vec<String> {
+        let mut ancestors = vec![];
+        let mut next = Some(name.to_string());
+        while let Some(name) = next {
+            let Some(group) = self.groups.get(&name) else {
+                break;
+            };
+            next = group.parent.clone();
+            ancestors.push(name);
+            if ancestors.len() > MAX_GROUP_DEPTH {
+                break;
+            }
+        }
+        ancestors
+    }
+
+    /// Every group `room` belongs to, directly or through a subgroup.
+    pub fn room_groups(&self, room: &str) -> BTreeSet<String> {
+        self.groups_by_room
+            .get(room)
+            .into_iter()
+            .flatten()
+            .flat_map(|group| self.group_ancestors(group))
+            .collect()
+    }
+
+    /// All groups, listing only the rooms listed to the caller logged in as
+    /// `account`.
+    pub fn list_groups(&self, account: Option<&str>) -> Vec<DeviceGroup> {
+        let mut groups: Vec<DeviceGroup> = self
+            .groups
+            .values()
+            .map(|group| DeviceGroup {
+                rooms: group
+                    .rooms
+                    .iter()
+                    .filter(|room| self.is_room_listed_for(room, account))
+                    .cloned()
+                    .collect(),
+                ..group.clone()
+            })
+            .collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        groups
+    }
+
+    /// Whether `room` is listed to `account`, judged by its running session
+    /// or else its last one. Rooms never seen cannot be hidden.
+    fn is_room_listed_for(&self, room: &str, account: Option<&str>) -> bool {
+        let (visibility, owner) = match (self.sessions.get(room), self.known_devices.get(room)) {
+            (Some(session), _) => (session.visibility, session.owner.as_deref()),
+            (None, Some(device)) => (device.visibility, device.owner.as_deref()),
+            (None, None) => return true,
+        };
+        visibility.is_listed_for(owner, account)
+    }
+
+    /// Create or replace a group. The parent must exist, and the group must
+    /// not end up below itself or nested too deeply.
+    pub fn put_group(&mut self, group: DeviceGroup) -> Result<()> {
+        validate_name(&group.name)?;
+        if let Some(parent) = &group.parent {
+            if !self.groups.contains_key(parent) {
+                return Err(format_err!("Parent group {} does not exist", parent));
+            }
+            let ancestors = self.group_ancestors(parent);
+            if ancestors.contains(&group.name) {
+                return Err(format_err!("A group cannot be nested below itself"));
+            }
+            let depth_below = self.subgroup_depth(&group.name);
+            if ancestors.len() + 1 + depth_below > MAX_GROUP_DEPTH {
+                return Err(format_err!(
+                    "Groups can be nested at most {} levels deep",
+                    MAX_GROUP_DEPTH
+                ));
+            }
+        }
+        self.store.put(GROUPS_TREE, &group.name, &group)?;
+        info!("Saved group {}", group.name);
+        let mut changed: BTreeSet<String> = group.rooms.clone();
+        if let Some(old) = self.groups.get(&group.name) {
+            changed.extend(old.rooms.iter().cloned());
+            // Moving a group changes the ancestors of every room below it
+            if old.parent != group.parent {
+                changed.extend(self.rooms_below(&group.name));
+            }
+        }
+        self.unlink_group(&group.name);
+        for room in &group.rooms {
+            self.groups_by_room
+                .entry(room.clone())
+                .or_default()
+                .insert(group.name.clone());
+        }
+        self.groups.insert(group.name.clone(), group);
+        self.reindex_rooms(changed);
+        Ok(())
+    }
+
+    /// Delete a group that has no subgroups.
+    pub fn delete_group(&mut self, name: &str) -> Result<()> {
+        if !self.groups.contains_key(name) {
+            return Err(format_err!("Group {} does not exist", name));
+        }
+        if self
+            .groups
+            .values()
+            .any(|group| group.parent.as_deref() == Some(name))
+        {
+            return Err(format_err!("Group {} still has subgroups", name));
+        }
+        self.store.remove(GROUPS_TREE, name)?;
+        info!("Deleted group {}", name);
+        self.unlink_group(name);
+        if let Some(group) = self.groups.remove(name) {
+            self.reindex_rooms(group.rooms);
+        }
+        Ok(())
+    }
+
+    /// Levels of subgroups below `name`.
+    fn subgroup_depth(&self, name: &str) -> usize {
+        self.groups
+            .values()
+            .filter(|group| group.parent.as_deref() == Some(name))
+            .map(|group| 1 + self.subgroup_depth(&group.name))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Rooms of `name` and of every group below it.
+    fn rooms_below(&self, name: &str) -> BTreeSet<String> {
+        let mut rooms = BTreeSet::new();
+        for group in self.groups.values() {
+            if self.group_ancestors(&group.name).iter().any(|g| g == name) {
+                rooms.extend(group.rooms.iter().cloned());
+            }
+        }
+        rooms
+    }
+
+    /// Drop `name` from `groups_by_room` for the rooms it currently lists.
+    fn unlink_group(&mut self, name: &str) {
+        let Some(group) = self.groups.get(name) else {
+            return;
+        };
+        for room in &group.rooms {
+            if let Some(groups) = self.groups_by_room.get_mut(room) {
+                groups.remove(name);
+                if groups.is_empty() {
+                    self.groups_by_room.remove(room);
+                }
+            }
+        }
+    }
+
+    /// Re-index the running rooms among `rooms` after their group
+    /// memberships changed.
+    fn reindex_rooms(&mut self, rooms: BTreeSet<String>) {
+        for room in rooms {
+            self.index_room(&room);
+        }
+    }
+}
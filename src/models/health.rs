@@ -0,0 +1,88 @@
+use std::time::{Duration, Instant};
+
+use failure::{format_err, Error};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::models::account::now_secs;
+use crate::models::state::State;
+use crate::models::store::Store;
+
+type Result<T> = std::result::Result<T, Error>;
+
+pub const HEALTH_TREE: &str = "health";
+/// How long a readiness probe of the ICE servers is reused, so frequent
+/// probes do not send a STUN request each time.
+pub const ICE_CHECK_TTL: Duration = Duration::from_secs(30);
+
+/// Whether the server should receive new traffic. Only readiness depends on
+/// it; connected clients are served either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerMode {
+    #[default]
+    Serving,
+    /// Set by an admin, until cleared.
+    Maintenance,
+    /// Shutting down, set on SIGTERM or Ctrl+C.
+    Draining,
+}
+
+/// Outcome of probing the configured ICE servers.
+#[derive(Clone, Debug)]
+pub struct IceCheck {
+    pub checked_at: Instant,
+    pub result: std::result::Result<(), String>,
+}
+
+impl State {
+    pub fn uptime_secs(&self) -> u64 {
+        self.started_at.elapsed().as_secs()
+    }
+
+    /// Enter or leave maintenance mode. A draining server stays draining.
+    pub fn set_maintenance(&mut self, enabled: bool) -> Result<()> {
+        if self.mode == ServerMode::Draining {
+            return Err(format_err!("Server is shutting down"));
+        }
+        self.mode = if enabled {
+            ServerMode::Maintenance
+        } else {
+            ServerMode::Serving
+        };
+        Ok(())
+    }
+
+    /// Counters reported by the health endpoints, a summary of `/metrics`.
+    pub fn health_counters(&self) -> Value {
+        let metrics = &self.metrics;
+        json!({
+            "sessions": self.sessions.len(),
+            "viewers": self.viewer_count(),
+            "connections": self.connections.len(),
+            "subscribers": self.room_update_subscribers.len(),
+            "connections_total": metrics.connections_total,
+            "messages_received": metrics.messages_received.values().sum::<u64>(),
+            "forward_failures": metrics.forward_failures,
+            "join_declines": metrics.join_declines.values().sum::<u64>(),
+            "ice_requests": metrics.ice_requests,
+        })
+    }
+
+    /// The last ICE check, unless it is older than `ICE_CHECK_TTL`.
+    pub fn recent_ice_check(&self) -> Option<&IceCheck> {
+        self.ice_check
+            .as_ref()
+            .filter(|check| check.checked_at.elapsed() < ICE_CHECK_TTL)
+    }
+}
+
+/// Write and read back a record, to check the store is usable.
+pub fn check_store(store: &Store) -> Result<()> {
+    let now = now_secs();
+    store.put(HEALTH_TREE, "probe", &now)?;
+    match store.get::<u64>(HEALTH_TREE, "probe")? {
+        Some(read) if read == now => Ok(()),
+        _ => Err(format_err!("Store did not return the probe record")),
+    }
+}
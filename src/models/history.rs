@@ -0,0 +1,383 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use failure::{format_err, Error};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::models::account::now_secs;
+use crate::models::session::Session;
+use crate::models::state::State;
+use crate::models::store::Store;
+use crate::models::view::Caller;
+
+type Result<T> = std::result::Result<T, Error>;
+
+pub const SESSION_HISTORY_TREE: &str = "session_history";
+/// Keys of `SESSION_HISTORY_TREE` by owner, so an owner's history is a key
+/// range of its own.
+pub const OWNER_HISTORY_TREE: &str = "session_history_by_owner";
+/// Facts about the history as a whole, see `LONGEST_SESSION_KEY`.
+pub const HISTORY_STATS_TREE: &str = "session_history_stats";
+/// Length in seconds of the longest recorded session. Sessions are keyed by
+/// start time, so this bounds how far before a range a session that
+/// overlaps it can have started.
+const LONGEST_SESSION_KEY: &str = "longest_session_secs";
+pub const DEFAULT_HISTORY_LIMIT: usize = 100;
+pub const MAX_HISTORY_LIMIT: usize = 1000;
+/// Visits remembered per session, so a busy long-running room does not grow
+/// without bound.
+pub const MAX_SESSION_VISITS: usize = 1000;
+/// How long ended sessions are kept unless `--history-retention-days` says
+/// otherwise.
+pub const DEFAULT_HISTORY_RETENTION: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+/// Why a session ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    /// The host sent `leave`.
+    HostLeft,
+    /// The host's connection closed.
+    Disconnected,
+    /// An admin closed the room.
+    Closed,
+}
+
+/// One stay of a viewer in a session.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewerVisit {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// Only shown to admins.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+    /// Unix times, in seconds.
+    pub joined_at: u64,
+    pub left_at: u64,
+}
+
+/// A session that ended, with everyone who was admitted to it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub room: String,
+    pub name: String,
+    pub host: String,
+    /// Only shown to admins.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_ip: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Unix times, in seconds.
+    pub started_at: u64,
+    pub ended_at: u64,
+    pub end_reason: EndReason,
+    pub viewers: Vec<ViewerVisit>,
+    /// Earlier visits left out of `viewers`, see `MAX_SESSION_VISITS`.
+    #[serde(default)]
+    pub dropped_visits: u64,
+}
+
+/// Which sessions to return. Sessions match a time range if they overlap
+/// it.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct HistoryQuery {
+    pub room: Option<String>,
+    /// Viewer peer ID or account.
+    pub viewer: Option<String>,
+    /// Unix times, in seconds.
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+/// Connected time of one device over a time range.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceUsage {
+    pub room: String,
+    pub sessions: usize,
+    /// How long the device was hosting, clipped to the range.
+    pub connected_secs: u64,
+    /// Sum of the time each viewer was admitted, clipped to the range.
+    pub viewer_secs: u64,
+    pub unique_viewers: usize,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Sessions are keyed by start time so that time ranges are key ranges.
+fn history_key(started_at: u64, room: &str) -> String {
+    format!("{:020}/{}", started_at, room)
+}
+
+/// Key of a session in `OWNER_HISTORY_TREE`.
+fn owner_history_key(owner: &str, history_key: &str) -> String {
+    format!("{}/{}", owner, history_key)
+}
+
+/// Seconds of `[start, end)` within `[from, to)`.
+fn overlap(start: u64, end: u64, from: u64, to: u64) -> u64 {
+    end.min(to).saturating_sub(start.max(from))
+}
+
+impl HistoryQuery {
+    fn from(&self) -> u64 {
+        self.from.unwrap_or(0)
+    }
+
+    fn to(&self) -> u64 {
+        self.to.unwrap_or(u64::MAX)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.from() > self.to() {
+            return Err(format_err!("from must not be after to"));
+        }
+        Ok(())
+    }
+
+    /// Whether `visit` is of the requested viewer, or any if none is.
+    fn is_viewer(&self, visit: &ViewerVisit) -> bool {
+        self.viewer.as_ref().is_none_or(|viewer| {
+            visit.id == *viewer || visit.account.as_deref() == Some(viewer.as_str())
+        })
+    }
+
+    fn matches(&self, record: &SessionRecord) -> bool {
+        self.room.as_ref().is_none_or(|room| record.room == *room)
+            && (self.viewer.is_none() || record.viewers.iter().any(|visit| self.is_viewer(visit)))
+            && record.ended_at >= self.from()
+            && record.started_at <= self.to()
+    }
+}
+
+impl State {
+    /// Remember that viewer `id` is leaving its room, before its peer goes
+    /// away.
+    pub fn record_visit(&mut self, id: &str) {
+        let Some(peer) = self.peers.get(id) else {
+            return;
+        };
+        let visit = ViewerVisit {
+            id: id.to_string(),
+            account: peer.account.clone(),
+            ip: peer.ip,
+            joined_at: unix_secs(peer.joined_at),
+            left_at: now_secs(),
+        };
+        if let Some(session) = self.sessions.get_mut(&peer.room) {
+            if session.visits.len() >= MAX_SESSION_VISITS {
+                session.visits.remove(0);
+                session.dropped_visits += 1;
+            }
+            session.visits.push(visit);
+        }
+    }
+
+    /// Write a session that was just removed to the history.
+    pub fn record_session(
+        &mut self,
+        room: &str,
+        session: Session,
+        host_ip: Option<IpAddr>,
+        end_reason: EndReason,
+    ) {
+        let record = SessionRecord {
+            room: room.to_string(),
+            name: session.name,
+            host: session.server,
+            host_ip,
+            owner: session.owner,
+            started_at: unix_secs(session.start_time),
+            ended_at: now_secs(),
+            end_reason,
+            viewers: session.visits,
+            dropped_visits: session.dropped_visits,
+        };
+        if let Err(e) = save_record(&self.store, &record) {
+            warn!("Failed to record session history of {}: {}", room, e);
+        }
+        self.prune_history();
+    }
+
+    /// Drop sessions that started longer than `history_retention` ago.
+    pub fn prune_history(&self) {
+        let Some(retention) = self.history_retention else {
+            return;
+        };
+        let cutoff = history_key(now_secs().saturating_sub(retention.as_secs()), "");
+        match prune_records(&self.store, cutoff) {
+            Ok(0) => {}
+            Ok(removed) => info!("Pruned {} sessions from the history", removed),
+            Err(e) => warn!("Failed to prune session history: {}", e),
+        }
+    }
+}
+
+/// Build the owner index and the longest session length of a history
+/// recorded before they were kept. Does nothing once they exist.
+pub fn index_history(store: &Store) -> Result<()> {
+    if store
+        .get::<u64>(HISTORY_STATS_TREE, LONGEST_SESSION_KEY)?
+        .is_some()
+    {
+        return Ok(());
+    }
+    let records = store.values::<SessionRecord>(SESSION_HISTORY_TREE)?;
+    for record in &records {
+        save_record(store, record)?;
+    }
+    store.put(
+        HISTORY_STATS_TREE,
+        LONGEST_SESSION_KEY,
+        &longest_session(store)?,
+    )?;
+    info!("Indexed {} sessions of the history", records.len());
+    Ok(())
+}
+
+fn save_record(store: &Store, record: &SessionRecord) -> Result<()> {
+    let key = history_key(record.started_at, &record.room);
+    store.put(SESSION_HISTORY_TREE, &key, record)?;
+    if let Some(owner) = &record.owner {
+        store.put(OWNER_HISTORY_TREE, &owner_history_key(owner, &key), &key)?;
+    }
+    let length = record.ended_at.saturating_sub(record.started_at);
+    if length > longest_session(store)? {
+        store.put(HISTORY_STATS_TREE, LONGEST_SESSION_KEY, &length)?;
+    }
+    Ok(())
+}
+
+/// Remove the sessions keyed before `cutoff`, with their owner index
+/// entries.
+fn prune_records(store: &Store, cutoff: String) -> Result<usize> {
+    for record in store.range_rev::<SessionRecord, _>(SESSION_HISTORY_TREE, ..cutoff.clone())? {
+        let record = record?;
+        if let Some(owner) = &record.owner {
+            let key = history_key(record.started_at, &record.room);
+            store.remove(OWNER_HISTORY_TREE, &owner_history_key(owner, &key))?;
+        }
+    }
+    store.remove_range(SESSION_HISTORY_TREE, ..cutoff)
+}
+
+fn longest_session(store: &Store) -> Result<u64> {
+    Ok(store
+        .get(HISTORY_STATS_TREE, LONGEST_SESSION_KEY)?
+        .unwrap_or_default())
+}
+
+/// Ended sessions matching `query` that `caller` may see, newest first: all
+/// of them for admins, otherwise those of rooms paired to the caller's
+/// account. IPs are only shown to admins. Only reads the store, so callers
+/// need not hold the state lock.
+pub fn session_history(
+    store: &Store,
+    query: &HistoryQuery,
+    caller: &Caller,
+) -> Result<Vec<SessionRecord>> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .clamp(1, MAX_HISTORY_LIMIT);
+    visible_history(store, query, caller)?.take(limit).collect()
+}
+
+/// Connected time per device of the sessions matching `query`, clipped to
+/// its time range. With a viewer given, only its visits count towards the
+/// viewer time.
+pub fn session_usage(
+    store: &Store,
+    query: &HistoryQuery,
+    caller: &Caller,
+) -> Result<Vec<DeviceUsage>> {
+    let (from, to) = (query.from(), query.to());
+    let mut usage: BTreeMap<String, (DeviceUsage, BTreeSet<String>)> = BTreeMap::new();
+    for record in visible_history(store, query, caller)? {
+        let record = record?;
+        let (device, viewers) = usage.entry(record.room.clone()).or_insert_with(|| {
+            (
+                DeviceUsage {
+                    room: record.room.clone(),
+                    sessions: 0,
+                    connected_secs: 0,
+                    viewer_secs: 0,
+                    unique_viewers: 0,
+                },
+                BTreeSet::new(),
+            )
+        });
+        device.sessions += 1;
+        device.connected_secs += overlap(record.started_at, record.ended_at, from, to);
+        for visit in record.viewers.iter().filter(|visit| query.is_viewer(visit)) {
+            device.viewer_secs += overlap(visit.joined_at, visit.left_at, from, to);
+            viewers.insert(visit.account.clone().unwrap_or_else(|| visit.id.clone()));
+        }
+    }
+    Ok(usage
+        .into_values()
+        .map(|(mut device, viewers)| {
+            device.unique_viewers = viewers.len();
+            device
+        })
+        .collect())
+}
+
+/// Matching sessions newest first, projected for `caller`. Records are read
+/// as the iterator is advanced, and only those that started late enough to
+/// overlap the range and, unless the caller is an admin, belong to the
+/// caller are read at all.
+fn visible_history<'a>(
+    store: &'a Store,
+    query: &'a HistoryQuery,
+    caller: &'a Caller,
+) -> Result<Box<dyn Iterator<Item = Result<SessionRecord>> + 'a>> {
+    query.validate()?;
+    // Sessions starting after the range cannot overlap it, nor can those
+    // starting longer before it than any session lasted
+    let start = history_key(query.from().saturating_sub(longest_session(store)?), "");
+    let end = history_key(query.to().saturating_add(1), "");
+    let records: Box<dyn Iterator<Item = Result<SessionRecord>>> = match &caller.account {
+        _ if caller.admin => Box::new(store.range_rev(SESSION_HISTORY_TREE, start..end)?),
+        Some(owner) => Box::new(
+            store
+                .range_rev::<String, _>(
+                    OWNER_HISTORY_TREE,
+                    owner_history_key(owner, &start)..owner_history_key(owner, &end),
+                )?
+                .filter_map(move |key| {
+                    key.and_then(|key| store.get(SESSION_HISTORY_TREE, &key))
+                        .transpose()
+                }),
+        ),
+        None => Box::new(std::iter::empty()),
+    };
+    Ok(Box::new(
+        records
+            .filter(move |record| {
+                record.as_ref().map_or(true, |record| {
+                    query.matches(record)
+                        && (caller.admin
+                            || (caller.account.is_some() && record.owner == caller.account))
+                })
+            })
+            .map(move |record| {
+                record.map(|mut record| {
+                    if !caller.admin {
+                        record.host_ip = None;
+                        for visit in &mut record.viewers {
+                            visit.ip = None;
+                        }
+                    }
+                    record
+                })
+            }),
+    ))
+}
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use failure::{format_err, Error};
+use hmac::{Hmac, Mac};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::models::account::{now_secs, random_token};
+use crate::models::peer::ViewerRole;
+use crate::models::state::State;
+
+type Result<T> = std::result::Result<T, Error>;
+type HmacSha256 = Hmac<Sha256>;
+
+pub const DEFAULT_INVITE_TTL: Duration = Duration::from_secs(30 * 60);
+pub const MAX_INVITE_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Signed payload of an invite token.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InviteClaims {
+    pub id: String,
+    pub room: String,
+    pub role: ViewerRole,
+    /// Expiry as seconds since the Unix epoch.
+    pub exp: u64,
+    pub max_uses: u32,
+}
+
+/// Server-side bookkeeping of an invite that was issued or redeemed.
+pub struct InviteRecord {
+    pub claims: InviteClaims,
+    pub uses: u32,
+    pub revoked: bool,
+}
+
+/// Secret used to sign invites. Falls back to a random per-process secret,
+/// which invalidates outstanding invites on restart.
+pub fn invite_secret() -> Vec<u8> {
+    std::env::var("INVITE_SECRET")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| random_token(32))
+        .into_bytes()
+}
+
+fn sign(secret: &[u8], payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+/// Encode claims as `<base64url(json)>.<base64url(hmac-sha256)>`.
+pub fn encode_invite(secret: &[u8], claims: &InviteClaims) -> Result<String> {
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?);
+    let signature = URL_SAFE_NO_PAD.encode(sign(secret, &payload).finalize().into_bytes());
+    Ok(format!("{}.{}", payload, signature))
+}
+
+/// Check the signature of a token and return its claims. Expiry and usage
+/// limits are checked by `State::verify_invite`.
+pub fn decode_invite(secret: &[u8], token: &str) -> Result<InviteClaims> {
+    let (payload, signature) = token
+        .split_once('.')
+        .ok_or_else(|| format_err!("Malformed invite"))?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| format_err!("Malformed invite"))?;
+    sign(secret, payload)
+        .verify_slice(&signature)
+        .map_err(|_| format_err!("Invalid invite signature"))?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|_| format_err!("Malformed invite"))?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+impl State {
+    /// Mint an invite for a live room. Records of expired invites are
+    /// dropped here, their tokens are refused on expiry alone.
+    pub fn create_invite(
+        &mut self,
+        room: &str,
+        role: ViewerRole,
+        ttl: Duration,
+        max_uses: u32,
+    ) -> Result<(InviteClaims, String)> {
+        if !self.sessions.contains_key(room) {
+            return Err(format_err!("Device is offline"));
+        }
+        if max_uses == 0 {
+            return Err(format_err!("max_uses must be at least 1"));
+        }
+        if ttl > MAX_INVITE_TTL {
+            return Err(format_err!(
+                "Invites can be valid for at most {} days",
+                MAX_INVITE_TTL.as_secs() / (24 * 60 * 60)
+            ));
+        }
+        let now = now_secs();
+        self.invites.retain(|_, record| record.claims.exp > now);
+        let claims = InviteClaims {
+            id: random_token(12),
+            room: room.to_string(),
+            role,
+            exp: now.saturating_add(ttl.as_secs()),
+            max_uses,
+        };
+        let token = encode_invite(&self.invite_secret, &claims)?;
+        self.invites.insert(
+            claims.id.clone(),
+            InviteRecord {
+                claims: claims.clone(),
+                uses: 0,
+                revoked: false,
+            },
+        );
+        info!("Created invite {} for room {}", claims.id, room);
+        Ok((claims, token))
+    }
+
+    /// Validate an invite for `room` without consuming a use.
+    pub fn verify_invite(&self, token: &str, room: &str) -> Result<InviteClaims> {
+        let claims = decode_invite(&self.invite_secret, token)?;
+        if claims.room != room {
+            return Err(format_err!("Invite is not valid for this room"));
+        }
+        if claims.exp <= now_secs() {
+            return Err(format_err!("Invite has expired"));
+        }
+        if let Some(record) = self.invites.get(&claims.id) {
+            if record.revoked {
+                return Err(format_err!("Invite has been revoked"));
+            }
+            if record.uses >= claims.max_uses {
+                return Err(format_err!("Invite has already been used"));
+            }
+        }
+        Ok(claims)
+    }
+
+    /// Count one use of an invite that passed `verify_invite`.
+    pub fn redeem_invite(&mut self, claims: &InviteClaims) {
+        self.invites
+            .entry(claims.id.clone())
+            .or_insert_with(|| InviteRecord {
+                claims: claims.clone(),
+                uses: 0,
+                revoked: false,
+            })
+            .uses += 1;
+    }
+
+    /// Revoke an invite. When `room` is given the invite must belong to it.
+    pub fn revoke_invite(&mut self, id: &str, room: Option<&str>) -> Result<()> {
+        let record = self
+            .invites
+            .get_mut(id)
+            .ok_or_else(|| format_err!("Invite does not exist"))?;
+        if room.is_some_and(|room| record.claims.room != room) {
+            return Err(format_err!("Invite does not belong to this room"));
+        }
+        record.revoked = true;
+        info!("Revoked invite {}", id);
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+use std::time::UNIX_EPOCH;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use failure::{format_err, Error};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::models::filter::RoomFilter;
+use crate::models::session::Session;
+use crate::models::state::{RoomInfo, State};
+
+type Result<T> = std::result::Result<T, Error>;
+
+pub const DEFAULT_PAGE_SIZE: usize = 6;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    Name,
+    ViewerCount,
+    Os,
+    Version,
+    /// How long the room has been running.
+    Uptime,
+    /// When the room was started, the order used when none is given.
+    #[default]
+    StartTime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Parameters of a room listing.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RoomQuery {
+    #[serde(flatten)]
+    pub filter: RoomFilter,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_by: Option<SortKey>,
+    /// Defaults to newest first for `start_time` and ascending otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SortOrder>,
+    /// `next_cursor` of the previous page. Takes precedence over `page`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<usize>,
+    /// Only list rooms paired to the caller's account.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owned: Option<bool>,
+    /// Also list a page of the known devices that are offline, on the first
+    /// page of rooms or when `offline_cursor` is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_offline: Option<bool>,
+    /// `next_offline_cursor` of the previous page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offline_cursor: Option<String>,
+}
+
+pub struct RoomPage {
+    pub rooms: Vec<RoomInfo>,
+    /// Matching rooms across all pages.
+    pub total_count: usize,
+    pub next_cursor: Option<String>,
+    /// Matching devices that are offline, if requested.
+    pub offline_rooms: Vec<RoomInfo>,
+    pub next_offline_cursor: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SortValue {
+    Number(u64),
+    Text(String),
+}
+
+/// Position after the last room of a page. Rooms are ordered by sort value,
+/// then room ID, both reversed for descending order, so the next page starts
+/// at the same place however rooms were added or removed in between.
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort_by: SortKey,
+    sort: SortOrder,
+    value: SortValue,
+    room: String,
+}
+
+/// Position after the last offline device of a page. Offline devices are
+/// ordered by when they were last seen, newest first, then room ID.
+#[derive(Serialize, Deserialize)]
+pub struct OfflineCursor {
+    pub last_seen: u64,
+    pub room: String,
+}
+
+fn encode_cursor<T: Serialize>(cursor: &T) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(cursor).unwrap())
+}
+
+fn decode_cursor<T: DeserializeOwned>(cursor: &str) -> Result<T> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| format_err!("Invalid cursor"))
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        encode_cursor(self)
+    }
+
+    fn decode(cursor: &str) -> Result<Self> {
+        decode_cursor(cursor)
+    }
+}
+
+impl OfflineCursor {
+    pub fn encode(&self) -> String {
+        encode_cursor(self)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self> {
+        decode_cursor(cursor)
+    }
+}
+
+fn start_micros(session: &Session) -> u64 {
+    session
+        .start_time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+pub fn sort_value(session: &Session, sort_by: SortKey) -> SortValue {
+    match sort_by {
+        SortKey::Name => SortValue::Text(session.name.to_lowercase()),
+        SortKey::ViewerCount => SortValue::Number(session.viewers.len() as u64),
+        SortKey::Os => SortValue::Text(session.os.to_lowercase()),
+        SortKey::Version => SortValue::Text(session.version.to_lowercase()),
+        SortKey::Uptime => SortValue::Number(u64::MAX - start_micros(session)),
+        SortKey::StartTime => SortValue::Number(start_micros(session)),
+    }
+}
+
+impl RoomQuery {
+    pub fn sort_by(&self) -> SortKey {
+        self.sort_by.unwrap_or_default()
+    }
+
+    pub fn sort(&self) -> SortOrder {
+        self.sort.unwrap_or(match self.sort_by() {
+            SortKey::StartTime => SortOrder::Desc,
+            _ => SortOrder::Asc,
+        })
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.per_page
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    /// Where the requested page starts: after a cursor position, or after
+    /// skipping a number of rooms for page-based requests.
+    fn start(&self) -> Result<PageStart> {
+        match &self.cursor {
+            Some(cursor) => {
+                let cursor = Cursor::decode(cursor)?;
+                if cursor.sort_by != self.sort_by() || cursor.sort != self.sort() {
+                    return Err(format_err!("Cursor does not match the sort order"));
+                }
+                Ok(PageStart::After(cursor.value, cursor.room))
+            }
+            None => Ok(PageStart::Skip(
+                self.page
+                    .unwrap_or(1)
+                    .saturating_sub(1)
+                    .saturating_mul(self.page_size()),
+            )),
+        }
+    }
+}
+
+enum PageStart {
+    After(SortValue, String),
+    Skip(usize),
+}
+
+impl State {
+    /// One page of the rooms matching `query` that are listed to the caller
+    /// logged in as `account`, as that caller may see them.
+    pub fn get_available_rooms(
+        &self,
+        query: &RoomQuery,
+        account: Option<&str>,
+    ) -> Result<RoomPage> {
+        let owner = match query.owned {
+            Some(true) => {
+                Some(account.ok_or_else(|| format_err!("Login required to list owned rooms"))?)
+            }
+            _ => None,
+        };
+        let caller = self.caller_for(account);
+        let sort_by = query.sort_by();
+        let sort = query.sort();
+        let candidates = self.catalog.candidates(&query.filter, owner, account);
+        let total_count = candidates.count();
+
+        let (after, skip) = match query.start()? {
+            PageStart::After(value, room) => (Some((value, room)), 0),
+            PageStart::Skip(skip) => (None, skip),
+        };
+        let page_size = query.page_size();
+        let mut page = candidates.page(sort_by, sort, after, skip, page_size + 1);
+        let next_cursor = if page.len() > page_size {
+            page.truncate(page_size);
+            page.last().map(|(value, room)| {
+                Cursor {
+                    sort_by,
+                    sort,
+                    value: value.clone(),
+                    room: room.clone(),
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
+
+        let first_page = query.cursor.is_none() && query.page.unwrap_or(1) <= 1;
+        let (offline_rooms, next_offline_cursor) = if query.include_offline == Some(true)
+            && (first_page || query.offline_cursor.is_some())
+        {
+            let after = query
+                .offline_cursor
+                .as_deref()
+                .map(OfflineCursor::decode)
+                .transpose()?;
+            self.offline_rooms(&query.filter, owner, account, after, page_size)
+        } else {
+            (vec![], None)
+        };
+
+        Ok(RoomPage {
+            rooms: page
+                .into_iter()
+                .filter_map(|(_, room)| {
+                    let owner = self.sessions.get(&room)?.owner.as_deref();
+                    self.room_view(&room, caller.audience(owner))
+                })
+                .collect(),
+            total_count,
+            next_cursor,
+            offline_rooms,
+            next_offline_cursor,
+        })
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Instant;
+
+use tokio::sync::MutexGuard;
+
+use crate::models::peer::PeerType;
+use crate::models::rtc::message_type;
+use crate::models::state::{State, StateType};
+
+/// Upper bounds of the session duration buckets, in seconds.
+pub const SESSION_DURATION_BUCKETS: &[f64] = &[
+    60.0, 300.0, 900.0, 1800.0, 3600.0, 14400.0, 43200.0, 86400.0,
+];
+/// Upper bounds of the state lock wait buckets, in seconds.
+pub const LOCK_WAIT_BUCKETS: &[f64] = &[0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+/// A Prometheus histogram with fixed buckets.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// Cumulative count per bucket.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            buckets: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        for (bound, bucket) in self.bounds.iter().zip(&mut self.buckets) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    #[cfg(test)]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        header(out, name, help, "histogram");
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, bucket);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count);
+    }
+}
+
+/// Counters kept since startup. Gauges are read off the state when
+/// rendering.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    pub connections_total: u64,
+    /// Messages handled, by `SignallerMessage` type.
+    pub messages_received: BTreeMap<String, u64>,
+    /// Messages that could not be forwarded to their peer.
+    pub forward_failures: u64,
+    /// Declined joins, by reason. Reasons are fixed error messages, so
+    /// there are few of them.
+    pub join_declines: BTreeMap<String, u64>,
+    pub ice_requests: u64,
+    pub session_duration: Histogram,
+    pub lock_wait: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            connections_total: 0,
+            messages_received: BTreeMap::new(),
+            forward_failures: 0,
+            join_declines: BTreeMap::new(),
+            ice_requests: 0,
+            session_duration: Histogram::new(SESSION_DURATION_BUCKETS),
+            lock_wait: Histogram::new(LOCK_WAIT_BUCKETS),
+        }
+    }
+}
+
+impl Metrics {
+    /// Count a message that parsed as a `SignallerMessage`.
+    pub fn message_received(&mut self, raw_payload: &str) {
+        if let Some(kind) = message_type(raw_payload) {
+            *self.messages_received.entry(kind.to_string()).or_default() += 1;
+        }
+    }
+
+    pub fn join_declined(&mut self, reason: &str) {
+        *self.join_declines.entry(reason.to_string()).or_default() += 1;
+    }
+}
+
+/// Lock the state, recording how long that took.
+pub async fn lock_state(state: &StateType) -> MutexGuard<'_, State> {
+    let started = Instant::now();
+    let mut locked_state = state.lock().await;
+    locked_state
+        .metrics
+        .lock_wait
+        .observe(started.elapsed().as_secs_f64());
+    locked_state
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn single(out: &mut String, name: &str, help: &str, kind: &str, value: impl std::fmt::Display) {
+    header(out, name, help, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn labelled(out: &mut String, name: &str, help: &str, label: &str, values: &BTreeMap<String, u64>) {
+    header(out, name, help, "counter");
+    for (value, count) in values {
+        let _ = writeln!(
+            out,
+            "{}{{{}=\"{}\"}} {}",
+            name,
+            label,
+            escape_label(value),
+            count
+        );
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl State {
+    /// Admitted viewers of all rooms.
+    pub fn viewer_count(&self) -> usize {
+        self.peers
+            .values()
+            .filter(|peer| matches!(peer.peer_type, PeerType::Viewer { .. }))
+            .count()
+    }
+
+    /// Current metrics in the Prometheus text format.
+    pub fn render_metrics(&self) -> String {
+        let metrics = &self.metrics;
+        let mut out = String::new();
+        single(
+            &mut out,
+            "remo_sessions",
+            "Active sessions.",
+            "gauge",
+            self.sessions.len(),
+        );
+        single(
+            &mut out,
+            "remo_viewers",
+            "Admitted viewers.",
+            "gauge",
+            self.viewer_count(),
+        );
+        single(
+            &mut out,
+            "remo_connections",
+            "Open WebSocket connections.",
+            "gauge",
+            self.connections.len(),
+        );
+        single(
+            &mut out,
+            "remo_subscribers",
+            "Connections subscribed to room updates.",
+            "gauge",
+            self.room_update_subscribers.len(),
+        );
+        single(
+            &mut out,
+            "remo_connections_total",
+            "WebSocket connections accepted.",
+            "counter",
+            metrics.connections_total,
+        );
+        labelled(
+            &mut out,
+            "remo_messages_received_total",
+            "Signalling messages received, by type.",
+            "type",
+            &metrics.messages_received,
+        );
+        single(
+            &mut out,
+            "remo_forward_failures_total",
+            "Messages that could not be forwarded to their peer.",
+            "counter",
+            metrics.forward_failures,
+        );
+        labelled(
+            &mut out,
+            "remo_join_declines_total",
+            "Declined joins, by reason.",
+            "reason",
+            &metrics.join_declines,
+        );
+        single(
+            &mut out,
+            "remo_ice_requests_total",
+            "ICE server requests.",
+            "counter",
+            metrics.ice_requests,
+        );
+        metrics.session_duration.render(
+            &mut out,
+            "remo_session_duration_seconds",
+            "Length of ended sessions.",
+        );
+        metrics.lock_wait.render(
+            &mut out,
+            "remo_state_lock_wait_seconds",
+            "Time spent waiting for the state lock.",
+        );
+        out
+    }
+}
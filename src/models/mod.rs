@@ -1,4 +1,29 @@
+pub mod account;
+pub mod admin;
+pub mod audit;
+pub mod ban;
+pub mod catalog;
+pub mod connection;
+pub mod control;
+pub mod device;
+pub mod events;
+pub mod filter;
+pub mod group;
+pub mod health;
+pub mod history;
+pub mod invite;
+pub mod listing;
+pub mod metrics;
+pub mod oidc;
 pub mod peer;
+pub mod queue;
+pub mod registry;
+pub mod room;
 pub mod rtc;
+pub mod search;
+pub mod selector;
 pub mod session;
 pub mod state;
+pub mod store;
+pub mod totp;
+pub mod view;
@@ -0,0 +1,83 @@
+use std::time::{Duration, SystemTime};
+
+use failure::{format_err, Error};
+
+use crate::models::account::random_token;
+use crate::models::state::State;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// How long a user has to complete the login at the identity provider.
+pub const OIDC_LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// OpenID Connect relying party configuration, read from `OIDC_*` variables.
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub scopes: String,
+    /// Claim suggesting the name of a new account, falling back to `sub`.
+    pub username_claim: String,
+    /// Claim inspected for the role mapping, e.g. `groups` or `roles`.
+    pub role_claim: Option<String>,
+    /// Values of `role_claim` that map to the admin role.
+    pub admin_values: Vec<String>,
+}
+
+impl OidcConfig {
+    /// `None` unless issuer, client ID and redirect URI are all set.
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+        Some(OidcConfig {
+            issuer: var("OIDC_ISSUER")?.trim_end_matches('/').to_string(),
+            client_id: var("OIDC_CLIENT_ID")?,
+            client_secret: var("OIDC_CLIENT_SECRET").unwrap_or_default(),
+            redirect_uri: var("OIDC_REDIRECT_URI")?,
+            scopes: var("OIDC_SCOPES").unwrap_or_else(|| "openid profile email".to_string()),
+            username_claim: var("OIDC_USERNAME_CLAIM")
+                .unwrap_or_else(|| "preferred_username".to_string()),
+            role_claim: var("OIDC_ROLE_CLAIM"),
+            admin_values: var("OIDC_ADMIN_VALUES")
+                .map(|values| values.split(',').map(|v| v.trim().to_string()).collect())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// An authorization request waiting for the provider to redirect back.
+pub struct PendingOidcLogin {
+    pub nonce: String,
+    pub expires_at: SystemTime,
+}
+
+impl State {
+    /// Start an authorization request, returning its `state` and `nonce`.
+    pub fn begin_oidc_login(&mut self) -> (String, String) {
+        let now = SystemTime::now();
+        self.oidc_logins.retain(|_, login| login.expires_at > now);
+        let (state, nonce) = (random_token(24), random_token(24));
+        self.oidc_logins.insert(
+            state.clone(),
+            PendingOidcLogin {
+                nonce: nonce.clone(),
+                expires_at: now + OIDC_LOGIN_TTL,
+            },
+        );
+        (state, nonce)
+    }
+
+    /// Consume the authorization request identified by `state`, returning
+    /// the nonce the ID token must carry.
+    pub fn finish_oidc_login(&mut self, state: &str) -> Result<String> {
+        let login = self
+            .oidc_logins
+            .remove(state)
+            .ok_or_else(|| format_err!("Unknown login state"))?;
+        if login.expires_at <= SystemTime::now() {
+            return Err(format_err!("Login has expired"));
+        }
+        Ok(login.nonce)
+    }
+}
@@ -1,9 +1,15 @@
 use axum::extract::ws::Message;
+use failure::{format_err, Error};
 #[allow(unused_imports)]
 #[allow(unused_variables)]
 #[allow(dead_code)]
 use futures_channel::mpsc::UnboundedSender;
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+use crate::models::room::{MAX_NAME_LENGTH, MAX_VERSION_LENGTH};
+use crate::models::state::State;
 
 type Tx = UnboundedSender<Message>;
 
@@ -12,11 +18,92 @@ pub struct Peer {
     pub sender: Tx,
     #[allow(dead_code)]
     pub peer_type: PeerType,
+    /// Account of the connection that created this peer, if it logged in.
+    pub account: Option<String>,
+    /// Real IP of the connection, when known.
+    pub ip: Option<IpAddr>,
+    /// When the peer started hosting or was admitted to the room.
+    pub joined_at: SystemTime,
+    /// What a viewer reported about itself in `Join`.
+    pub client: ClientInfo,
+    /// ID of the connection the peer belongs to, when it was registered.
+    pub connection: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum PeerType {
     Server {},
-    Viewer {},
+    Viewer { role: ViewerRole },
+}
+
+/// What an admitted viewer is allowed to do in a room.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViewerRole {
+    ViewOnly,
+    Control,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceType {
+    Desktop,
+    Mobile,
+    Tablet,
+    Tv,
+    Web,
+    #[serde(other)]
+    Other,
+}
+
+/// What a viewer reports about itself when joining. Every field is optional.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_os: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_type: Option<DeviceType>,
+}
+
+impl ClientInfo {
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(display_name) = &self.display_name {
+            if display_name.trim().is_empty() || display_name.len() > MAX_NAME_LENGTH {
+                return Err(format_err!(
+                    "Display name must be between 1 and {} characters",
+                    MAX_NAME_LENGTH
+                ));
+            }
+        }
+        for value in [&self.client_os, &self.app_version].into_iter().flatten() {
+            if value.len() > MAX_VERSION_LENGTH {
+                return Err(format_err!(
+                    "Client OS and app version must be at most {} characters",
+                    MAX_VERSION_LENGTH
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl State {
+    /// Attach what a joining viewer reported about itself, whether it was
+    /// admitted to `room` or is waiting in its queue.
+    pub fn bind_client_info(&mut self, room: &str, id: &str, client: ClientInfo) {
+        if let Some(peer) = self.peers.get_mut(id).filter(|peer| peer.room == room) {
+            peer.client = client;
+        } else if let Some(queued) = self
+            .sessions
+            .get_mut(room)
+            .and_then(|session| session.waiting.iter_mut().find(|queued| queued.id == id))
+        {
+            queued.client = client;
+        }
+    }
 }
@@ -0,0 +1,159 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::SystemTime;
+
+use axum::extract::ws::Message;
+use futures_channel::mpsc::UnboundedSender;
+use log::info;
+
+use crate::models::peer::{ClientInfo, Peer, PeerType, ViewerRole};
+use crate::models::rtc::SignallerMessage;
+use crate::models::state::State;
+
+type Tx = UnboundedSender<Message>;
+
+/// Outcome of a join request.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Admission {
+    Admitted,
+    /// The room is full; 1-based position in its waiting queue.
+    Queued {
+        position: usize,
+    },
+}
+
+/// A viewer waiting for a free slot in a full room.
+pub struct QueuedViewer {
+    pub id: String,
+    pub sender: Tx,
+    pub role: ViewerRole,
+    pub ip: Option<IpAddr>,
+    pub account: Option<String>,
+    pub client: ClientInfo,
+}
+
+impl State {
+    /// Drop a viewer from the waiting queue of `room`. Returns whether it
+    /// was queued there.
+    pub fn remove_from_queue(&mut self, room: &str, id: &str) -> bool {
+        let Some(session) = self.sessions.get_mut(room) else {
+            return false;
+        };
+        let before = session.waiting.len();
+        session.waiting.retain(|queued| queued.id != id);
+        let removed = session.waiting.len() != before;
+        if removed {
+            self.notify_queue_positions(room);
+        }
+        removed
+    }
+
+    /// Attach the connection's account to a queued viewer, the counterpart of
+    /// `bind_peer_account` for viewers that are not admitted yet.
+    pub fn bind_queued_account(&mut self, room: &str, id: &str, socket_addr: &SocketAddr) {
+        let account = self.connection_account(socket_addr);
+        if let Some(queued) = self
+            .sessions
+            .get_mut(room)
+            .and_then(|session| session.waiting.iter_mut().find(|queued| queued.id == id))
+        {
+            queued.account = account;
+        }
+    }
+
+    /// Room whose waiting queue holds `id`, if any.
+    pub fn queued_room(&self, id: &str) -> Option<String> {
+        self.sessions
+            .iter()
+            .find(|(_, session)| session.waiting.iter().any(|queued| queued.id == id))
+            .map(|(room, _)| room.clone())
+    }
+
+    /// Whether `id` is waiting in a queue on behalf of the connection
+    /// sending on `tx`.
+    pub fn is_queued_connection(&self, id: &str, tx: &Tx) -> bool {
+        self.sessions.values().any(|session| {
+            session
+                .waiting
+                .iter()
+                .any(|queued| queued.id == id && queued.sender.same_receiver(tx))
+        })
+    }
+
+    /// Admit queued viewers while `room` has free slots. Viewers whose
+    /// connection went away or whose ID was taken in the meantime are
+    /// skipped.
+    pub fn promote_waiting(&mut self, room: &str) {
+        let mut promoted = false;
+        loop {
+            let Some(session) = self.sessions.get_mut(room) else {
+                return;
+            };
+            if session.is_full() {
+                break;
+            }
+            let Some(queued) = session.waiting.pop_front() else {
+                break;
+            };
+            if queued.sender.is_closed() {
+                continue;
+            }
+            if self.check_peer_id(&queued.id, &queued.sender).is_err() {
+                info!(
+                    "{} left the queue of room {}, its ID is taken",
+                    queued.id, room
+                );
+                continue;
+            }
+            let connection = self.connection_of(&queued.sender);
+            let session = self.sessions.get_mut(room).unwrap();
+            promoted = true;
+            session.viewers.insert(queued.id.clone());
+            info!("{} promoted from the queue of room {}", queued.id, room);
+            self.peers.insert(
+                queued.id.clone(),
+                Peer {
+                    room: room.to_string(),
+                    sender: queued.sender,
+                    peer_type: PeerType::Viewer { role: queued.role },
+                    account: queued.account,
+                    ip: queued.ip,
+                    joined_at: SystemTime::now(),
+                    client: queued.client.clone(),
+                    connection,
+                },
+            );
+            // Same as a direct join: the host starts negotiating on Join
+            if let Some(host) = self.peers.get(room) {
+                let _ = host.sender.unbounded_send(Message::Text(
+                    serde_json::to_string(&SignallerMessage::Join {
+                        from: queued.id,
+                        room: room.to_string(),
+                        invite: None,
+                        client: queued.client,
+                    })
+                    .unwrap(),
+                ));
+            }
+        }
+        if promoted {
+            self.notify_queue_positions(room);
+        }
+    }
+
+    /// Tell every queued viewer of `room` its current position.
+    pub fn notify_queue_positions(&self, room: &str) {
+        let Some(session) = self.sessions.get(room) else {
+            return;
+        };
+        for (index, queued) in session.waiting.iter().enumerate() {
+            let _ = queued.sender.unbounded_send(Message::Text(
+                serde_json::to_string(&SignallerMessage::Queued {
+                    to: queued.id.clone(),
+                    room: room.to_string(),
+                    position: index + 1,
+                })
+                .unwrap(),
+            ));
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::ops::Bound;
+use std::time::Duration;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::models::account::now_secs;
+use crate::models::filter::{RoomFields, RoomFilter};
+use crate::models::listing::OfflineCursor;
+use crate::models::room::Visibility;
+use crate::models::session::Session;
+use crate::models::state::{RoomInfo, State};
+use crate::models::view::{Audience, RoomField};
+
+pub const DEVICE_REGISTRY_TREE: &str = "device_registry";
+/// How long a device that is not paired to an account is remembered after
+/// it was last seen. Paired devices are kept.
+pub const UNPAIRED_DEVICE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Expired unpaired devices are looked for at most this often.
+const REGISTRY_PRUNE_INTERVAL: u64 = 60 * 60;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceStatus {
+    #[default]
+    Online,
+    Offline,
+}
+
+/// A host that started a session at some point, remembered across sessions
+/// and restarts.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownDevice {
+    pub room: String,
+    pub name: String,
+    pub os: String,
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    #[serde(default)]
+    pub description: String,
+    /// Unix time of the first session, in seconds.
+    pub first_seen: u64,
+    /// Unix time the device was last online, in seconds.
+    pub last_seen: u64,
+    /// Real IP of the last session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_ip: Option<IpAddr>,
+    /// Length of the last session that ended.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_session_secs: Option<u64>,
+}
+
+impl KnownDevice {
+    fn from_session(room: &str, session: &Session, ip: Option<IpAddr>) -> Self {
+        let now = now_secs();
+        KnownDevice {
+            room: room.to_string(),
+            name: session.name.clone(),
+            os: session.os.clone(),
+            version: session.version.clone(),
+            owner: session.owner.clone(),
+            visibility: session.visibility,
+            tags: session.tags.clone(),
+            description: session.description.clone(),
+            first_seen: now,
+            last_seen: now,
+            last_ip: ip,
+            last_session_secs: None,
+        }
+    }
+
+    fn fields(&self) -> RoomFields<'_> {
+        RoomFields {
+            name: &self.name,
+            os: &self.os,
+            version: &self.version,
+            server: None,
+            control: false,
+            tags: &self.tags,
+        }
+    }
+}
+
+impl State {
+    /// Record that the host of `room` is online, keeping when it was first
+    /// seen and how long its last session lasted.
+    pub fn remember_device(&mut self, room: &str) {
+        let Some(session) = self.sessions.get(room) else {
+            return;
+        };
+        let ip = self.peers.get(&session.server).and_then(|peer| peer.ip);
+        let device = KnownDevice::from_session(room, session, ip);
+        self.save_device(device);
+    }
+
+    /// Record the end of a session that was just removed.
+    pub fn remember_session_end(&mut self, room: &str, session: &Session, ip: Option<IpAddr>) {
+        let mut device = KnownDevice::from_session(room, session, ip);
+        device.last_session_secs = Some(session.start_time.elapsed().unwrap_or_default().as_secs());
+        self.save_device(device);
+    }
+
+    fn save_device(&mut self, mut device: KnownDevice) {
+        if let Some(known) = self.known_devices.get(&device.room) {
+            self.devices_by_last_seen
+                .remove(&(Reverse(known.last_seen), device.room.clone()));
+            device.first_seen = known.first_seen;
+            device.last_session_secs = device.last_session_secs.or(known.last_session_secs);
+            device.last_ip = device.last_ip.or(known.last_ip);
+        }
+        if let Err(e) = self.store.put(DEVICE_REGISTRY_TREE, &device.room, &device) {
+            warn!("Failed to record device {}: {}", device.room, e);
+        }
+        self.devices_by_last_seen
+            .insert((Reverse(device.last_seen), device.room.clone()));
+        self.known_devices.insert(device.room.clone(), device);
+        self.prune_unpaired_devices();
+    }
+
+    /// Forget offline devices without an owner that were last seen more than
+    /// `UNPAIRED_DEVICE_TTL` ago, so anonymous hosts do not pile up.
+    fn prune_unpaired_devices(&mut self) {
+        let now = now_secs();
+        if now
+            < self
+                .registry_pruned_at
+                .saturating_add(REGISTRY_PRUNE_INTERVAL)
+        {
+            return;
+        }
+        self.registry_pruned_at = now;
+        let cutoff = now.saturating_sub(UNPAIRED_DEVICE_TTL.as_secs());
+        let expired: Vec<String> = self
+            .known_devices
+            .values()
+            .filter(|device| {
+                device.owner.is_none()
+                    && device.last_seen < cutoff
+                    && !self.sessions.contains_key(&device.room)
+            })
+            .map(|device| device.room.clone())
+            .collect();
+        for room in expired {
+            if let Err(e) = self.store.remove(DEVICE_REGISTRY_TREE, &room) {
+                warn!("Failed to forget device {}: {}", room, e);
+            }
+            if let Some(device) = self.known_devices.remove(&room) {
+                self.devices_by_last_seen
+                    .remove(&(Reverse(device.last_seen), room));
+            }
+        }
+    }
+
+    /// An offline device as `audience` may see it.
+    fn offline_view(&self, device: &KnownDevice, audience: Audience) -> RoomInfo {
+        let shows = |field| self.projection.shows(audience, field);
+        RoomInfo {
+            room: device.room.clone(),
+            server: None,
+            server_ip: device.last_ip.filter(|_| shows(RoomField::Ips)),
+            viewer_count: 0,
+            viewers: None,
+            os: device.os.clone(),
+            version: device.version.clone(),
+            name: device.name.clone(),
+            control: false,
+            owner: device.owner.clone().filter(|_| shows(RoomField::Owner)),
+            controller: None,
+            max_viewers: None,
+            queue_length: 0,
+            tags: device.tags.clone(),
+            description: device.description.clone(),
+            visibility: device.visibility,
+            groups: self.room_groups(&device.room).into_iter().collect(),
+            start_time: device
+                .last_seen
+                .saturating_sub(device.last_session_secs.unwrap_or_default()),
+            uptime_secs: 0,
+            status: DeviceStatus::Offline,
+            last_seen: Some(device.last_seen),
+            last_session_secs: device.last_session_secs,
+        }
+    }
+
+    /// One page of the known devices without a running session that match
+    /// `filter` and are listed to the caller logged in as `account`, most
+    /// recently seen first, starting after `after`. Only devices paired to
+    /// `owner` if given. Returns the cursor of the next page, if any.
+    pub fn offline_rooms(
+        &self,
+        filter: &RoomFilter,
+        owner: Option<&str>,
+        account: Option<&str>,
+        after: Option<OfflineCursor>,
+        limit: usize,
+    ) -> (Vec<RoomInfo>, Option<String>) {
+        let start = match after {
+            Some(cursor) => Bound::Excluded((Reverse(cursor.last_seen), cursor.room)),
+            None => Bound::Unbounded,
+        };
+        let mut devices: Vec<&KnownDevice> = self
+            .devices_by_last_seen
+            .range((start, Bound::Unbounded))
+            .filter_map(|(_, room)| self.known_devices.get(room))
+            .filter(|device| !self.sessions.contains_key(&device.room))
+            .filter(|device| owner.is_none() || device.owner.as_deref() == owner)
+            .filter(|device| {
+                device
+                    .visibility
+                    .is_listed_for(device.owner.as_deref(), account)
+            })
+            .filter(|device| {
+                filter.matches_fields(&device.fields(), || {
+                    self.room_groups(&device.room).into_iter().collect()
+                })
+            })
+            .take(limit + 1)
+            .collect();
+        let next_cursor = if devices.len() > limit {
+            devices.truncate(limit);
+            devices.last().map(|device| {
+                OfflineCursor {
+                    last_seen: device.last_seen,
+                    room: device.room.clone(),
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
+        let caller = self.caller_for(account);
+        let rooms = devices
+            .into_iter()
+            .map(|device| self.offline_view(device, caller.audience(device.owner.as_deref())))
+            .collect();
+        (rooms, next_cursor)
+    }
+}
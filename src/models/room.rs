@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+
+use failure::{format_err, Error};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::models::state::State;
+
+type Result<T> = std::result::Result<T, Error>;
+
+pub const MAX_NAME_LENGTH: usize = 64;
+pub const MAX_VERSION_LENGTH: usize = 32;
+pub const MAX_DESCRIPTION_LENGTH: usize = 512;
+pub const MAX_TAGS: usize = 32;
+pub const MAX_TAG_KEY_LENGTH: usize = 32;
+pub const MAX_TAG_VALUE_LENGTH: usize = 64;
+
+/// Room metadata a host can change while the session is running. Only the
+/// fields that are set are applied, and `RoomUpdated` carries just the ones
+/// that actually changed.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomChanges {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Replaces the whole tag set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl RoomChanges {
+    pub fn is_empty(&self) -> bool {
+        *self == RoomChanges::default()
+    }
+}
+
+/// Who can find a room in listings, searches and room updates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    #[default]
+    Public,
+    /// Listed only to the owner, but anyone can join with the room ID or an
+    /// invite.
+    Unlisted,
+    /// Listed only to the owner, and only the owner's connections or invited
+    /// viewers can join.
+    OwnerOnly,
+}
+
+impl Visibility {
+    /// Whether a room owned by `owner` is listed to the caller logged in as
+    /// `account`.
+    pub fn is_listed_for(self, owner: Option<&str>, account: Option<&str>) -> bool {
+        match self {
+            Visibility::Public => true,
+            Visibility::Unlisted | Visibility::OwnerOnly => account.is_some() && owner == account,
+        }
+    }
+}
+
+pub fn validate_tags(tags: &BTreeMap<String, String>) -> Result<()> {
+    if tags.len() > MAX_TAGS {
+        return Err(format_err!("A room can have at most {} tags", MAX_TAGS));
+    }
+    for (key, value) in tags {
+        if key.is_empty() || key.len() > MAX_TAG_KEY_LENGTH {
+            return Err(format_err!(
+                "Tag keys must be between 1 and {} characters",
+                MAX_TAG_KEY_LENGTH
+            ));
+        }
+        if !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '/'))
+        {
+            return Err(format_err!(
+                "Tag keys may only contain letters, digits, '.', '_', '-' and '/'"
+            ));
+        }
+        if value.len() > MAX_TAG_VALUE_LENGTH {
+            return Err(format_err!(
+                "Tag values must be at most {} characters",
+                MAX_TAG_VALUE_LENGTH
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(format_err!(
+            "Room name must be between 1 and {} characters",
+            MAX_NAME_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_version(version: &str) -> Result<()> {
+    if version.trim().is_empty() || version.len() > MAX_VERSION_LENGTH {
+        return Err(format_err!(
+            "Version must be between 1 and {} characters",
+            MAX_VERSION_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+fn validate_changes(changes: &RoomChanges) -> Result<()> {
+    if let Some(name) = &changes.name {
+        validate_name(name)?;
+    }
+    if let Some(version) = &changes.version {
+        validate_version(version)?;
+    }
+    if let Some(description) = &changes.description {
+        if description.len() > MAX_DESCRIPTION_LENGTH {
+            return Err(format_err!(
+                "Description must be at most {} characters",
+                MAX_DESCRIPTION_LENGTH
+            ));
+        }
+    }
+    if let Some(tags) = &changes.tags {
+        validate_tags(tags)?;
+    }
+    Ok(())
+}
+
+impl State {
+    /// Refuse joining an owner-only room without an invite from anyone but
+    /// its owner, without revealing that the room exists.
+    pub fn check_room_access(&self, room: &str, account: Option<&str>) -> Result<()> {
+        match self.sessions.get(room) {
+            Some(session)
+                if session.visibility == Visibility::OwnerOnly
+                    && !Visibility::OwnerOnly.is_listed_for(session.owner.as_deref(), account) =>
+            {
+                Err(format_err!("Device is offline"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Apply metadata changes to a running session and tell its peers and the
+    /// room update subscribers. Returns the fields that actually changed.
+    pub fn update_room(&mut self, room: &str, changes: RoomChanges) -> Result<RoomChanges> {
+        validate_changes(&changes)?;
+        let before = self
+            .room_info(room)
+            .ok_or_else(|| format_err!("Device is offline"))?;
+        let session = self.sessions.get_mut(room).unwrap();
+
+        let mut applied = RoomChanges::default();
+        if let Some(name) = changes.name.filter(|name| *name != session.name) {
+            session.name = name.clone();
+            applied.name = Some(name);
+        }
+        if let Some(control) = changes
+            .control
+            .filter(|control| *control != session.control)
+        {
+            session.control = control;
+            applied.control = Some(control);
+        }
+        if let Some(version) = changes
+            .version
+            .filter(|version| *version != session.version)
+        {
+            session.version = version.clone();
+            applied.version = Some(version);
+        }
+        if let Some(tags) = changes.tags.filter(|tags| *tags != session.tags) {
+            session.tags = tags.clone();
+            applied.tags = Some(tags);
+        }
+        if let Some(description) = changes
+            .description
+            .filter(|description| *description != session.description)
+        {
+            session.description = description.clone();
+            applied.description = Some(description);
+        }
+        if applied.is_empty() {
+            return Ok(applied);
+        }
+
+        // Turning remote control off takes it away from the current holder
+        let revoke_control = applied.control == Some(false) && session.controller.is_some();
+        info!("Updated room {}: {:?}", room, applied);
+        self.index_room(room);
+        if revoke_control {
+            self.set_controller(room, None);
+        }
+        self.publish_room_updated(room, before, applied.clone());
+        Ok(applied)
+    }
+}
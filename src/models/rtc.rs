@@ -1,7 +1,13 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
+use crate::models::account::AccountRole;
+use crate::models::filter::RoomFilter;
+use crate::models::listing::RoomQuery;
+use crate::models::peer::{ClientInfo, ViewerRole};
+use crate::models::room::{RoomChanges, Visibility};
+use crate::models::search::SearchHit;
 use crate::models::state::RoomInfo;
 
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
@@ -34,6 +40,11 @@ pub enum SignallerMessage {
     Join {
         from: String,
         room: String,
+        /// Signed invite token, see `CreateInvite`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        invite: Option<String>,
+        #[serde(flatten)]
+        client: ClientInfo,
     },
     JoinDeclined {
         to: String,
@@ -45,6 +56,18 @@ pub enum SignallerMessage {
         os: String,
         version: String,
         control: bool,
+        /// Secret the device was paired with. Required for paired rooms.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        device_secret: Option<String>,
+        /// Joins beyond this many viewers wait in a queue.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_viewers: Option<usize>,
+        /// Public unless set. Hidden rooms must be paired to an account.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        visibility: Option<Visibility>,
+        /// Initial tags, such as `site` or `floor`, to select rooms by.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        tags: BTreeMap<String, String>,
     },
     StartResponse {
         room: String,
@@ -64,24 +87,190 @@ pub enum SignallerMessage {
         ice_servers: Vec<IceServer>,
     },
     GetRoomList {
-        os: Option<String>,
-        name: Option<String>,
-        version: Option<String>,
-        server: Option<String>,
-        sort: Option<String>,
-        control: Option<bool>,
-        page: Option<usize>,
-        per_page: Option<usize>,
+        #[serde(flatten)]
+        query: RoomQuery,
     },
     RoomListResponse {
-        rooms: HashMap<String, RoomInfo>,
+        rooms: Vec<RoomInfo>,
         total_count: usize,
         page: Option<usize>,
         per_page: Option<usize>,
+        /// Pass as `cursor` to get the next page. Absent on the last page.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next_cursor: Option<String>,
+        /// Known devices that are offline, when `include_offline` is set.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        offline_rooms: Vec<RoomInfo>,
+        /// Pass as `offline_cursor` to get the next page of offline devices.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next_offline_cursor: Option<String>,
+    },
+    /// Rooms ranked by a fuzzy match of `query` against their name, room
+    /// ID, OS, tags and description.
+    SearchRooms {
+        query: String,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    SearchResults {
+        query: String,
+        results: Vec<SearchHit>,
+    },
+    SubscribeRoomUpdates {
+        /// Name of the subscription, to replace or cancel it later.
+        #[serde(default)]
+        subscription: Option<String>,
+        /// Last event version the client has seen. Newer events are
+        /// replayed, or a `RoomSnapshot` is sent if they are gone.
+        #[serde(default)]
+        since: Option<u64>,
+        /// Only rooms matching the filter are reported.
+        #[serde(flatten)]
+        filter: RoomFilter,
+    },
+    UnsubscribeRoomUpdates {
+        /// Subscription to cancel, all of them if omitted.
+        #[serde(default)]
+        subscription: Option<String>,
+    },
+    Login {
+        username: String,
+        password: String,
+        /// TOTP or recovery code for accounts with two-factor authentication.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        totp: Option<String>,
+    },
+    Authenticate {
+        token: String,
+    },
+    LoginResponse {
+        token: String,
+        username: String,
+        role: AccountRole,
+    },
+    LoginFailed {
+        reason: String,
+    },
+    Logout {},
+    CreateInvite {
+        room: String,
+        role: ViewerRole,
+        ttl_secs: Option<u64>,
+        max_uses: Option<u32>,
+    },
+    InviteCreated {
+        id: String,
+        room: String,
+        role: ViewerRole,
+        token: String,
+        expires_at: u64,
+    },
+    RevokeInvite {
+        id: String,
+    },
+    InviteRevoked {
+        id: String,
+    },
+    RequestPairingCode {
+        device_secret: String,
+    },
+    PairingCode {
+        code: String,
+        expires_in: u64,
+    },
+    ClaimPairingCode {
+        code: String,
+        name: Option<String>,
+    },
+    DevicePaired {
+        room: String,
+        name: Option<String>,
     },
-    SubscribeRoomUpdates {},
-    UnsubscribeRoomUpdates {},
-    NewRoomNotification {
+    Paired {
         room: String,
+        owner: String,
+    },
+    RequestControl {
+        from: String,
+    },
+    GrantControl {
+        to: String,
     },
+    RevokeControl {
+        to: String,
+    },
+    ReleaseControl {
+        from: String,
+    },
+    ControlDenied {
+        to: String,
+        reason: String,
+    },
+    ControlChanged {
+        room: String,
+        controller: Option<String>,
+    },
+    Kick {
+        viewer: String,
+        reason: Option<String>,
+    },
+    Ban {
+        viewer_or_ip: String,
+        /// Ban length in seconds, forever if omitted.
+        duration: Option<u64>,
+    },
+    Kicked {
+        to: String,
+        room: String,
+        reason: Option<String>,
+    },
+    Queued {
+        to: String,
+        room: String,
+        position: usize,
+    },
+    /// Sent by a host to change its room's metadata without restarting.
+    UpdateRoom {
+        #[serde(flatten)]
+        changes: RoomChanges,
+    },
+    RoomAdded {
+        version: u64,
+        room: String,
+        info: RoomInfo,
+    },
+    RoomUpdated {
+        version: u64,
+        room: String,
+        changes: RoomChanges,
+    },
+    RoomRemoved {
+        version: u64,
+        room: String,
+    },
+    ViewerCountChanged {
+        version: u64,
+        room: String,
+        viewer_count: usize,
+    },
+    /// Every room as of `version`, sent when events cannot be replayed.
+    /// Ordered by name, then room ID.
+    RoomSnapshot {
+        version: u64,
+        rooms: Vec<RoomInfo>,
+    },
+}
+
+/// Just the tag of a `SignallerMessage`.
+#[derive(Deserialize)]
+struct MessageType<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+}
+
+/// The `type` of a raw `SignallerMessage`, without parsing the rest of it.
+pub fn message_type(raw_payload: &str) -> Option<&str> {
+    serde_json::from_str::<MessageType>(raw_payload)
+        .ok()
+        .map(|message| message.kind)
 }
@@ -0,0 +1,331 @@
+use failure::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::models::listing::MAX_PAGE_SIZE;
+use crate::models::session::Session;
+use crate::models::state::{RoomInfo, State};
+
+type Result<T> = std::result::Result<T, Error>;
+
+pub const DEFAULT_SEARCH_LIMIT: usize = 10;
+pub const MAX_QUERY_LENGTH: usize = 128;
+
+// How much a match in each field counts towards the score.
+const NAME_WEIGHT: f64 = 1.0;
+const ROOM_WEIGHT: f64 = 0.9;
+const TAG_WEIGHT: f64 = 0.7;
+const OS_WEIGHT: f64 = 0.6;
+const DESCRIPTION_WEIGHT: f64 = 0.5;
+/// Score of a match with typos before the penalty per typo. Scattered
+/// subsequences score at most 0.5, so anything not matching exactly or as a
+/// substring scores below this.
+const TYPO_SCORE: f64 = 0.7;
+
+/// Where the query matched in one field of a room.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Highlight {
+    /// `name`, `room`, `os`, `description`, `tags` for a tag key or
+    /// `tags.<key>` for a tag value.
+    pub field: String,
+    pub text: String,
+    /// Matched `[start, end)` ranges of `text`, in characters.
+    pub spans: Vec<(usize, usize)>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SearchHit {
+    /// Relevance between 0 and 1, higher is better.
+    pub score: f64,
+    pub highlights: Vec<Highlight>,
+    pub info: RoomInfo,
+}
+
+struct Field {
+    name: String,
+    text: String,
+    weight: f64,
+}
+
+fn fields(room: &str, session: &Session) -> Vec<Field> {
+    let field = |name: &str, text: &str, weight| Field {
+        name: name.to_string(),
+        text: text.to_string(),
+        weight,
+    };
+    let mut fields = vec![
+        field("name", &session.name, NAME_WEIGHT),
+        field("room", room, ROOM_WEIGHT),
+        field("os", &session.os, OS_WEIGHT),
+        field("description", &session.description, DESCRIPTION_WEIGHT),
+    ];
+    for (key, value) in &session.tags {
+        fields.push(field("tags", key, TAG_WEIGHT));
+        fields.push(field(&format!("tags.{}", key), value, TAG_WEIGHT));
+    }
+    fields
+}
+
+/// Lowercases per character so that positions still refer to the original
+/// text.
+fn fold(text: &str) -> Vec<char> {
+    text.chars()
+        .map(|c| c.to_lowercase().next().unwrap_or(c))
+        .collect()
+}
+
+/// The folded text of every field a search looks at, for the catalog's
+/// trigram index.
+pub fn searchable_text(room: &str, session: &Session) -> Vec<String> {
+    fields(room, session)
+        .iter()
+        .map(|field| fold(&field.text).into_iter().collect())
+        .collect()
+}
+
+/// Edits tolerated for a term of the given length.
+fn max_typos(term_length: usize) -> usize {
+    match term_length {
+        0..=3 => 0,
+        4..=6 => 1,
+        _ => 2,
+    }
+}
+
+/// Scores how well `term` matches `text`, between 0 and 1, together with the
+/// matched character ranges. Exact, prefix and substring matches rank above
+/// matches with typos, which rank above scattered subsequences.
+fn match_term(term: &[char], text: &[char]) -> Option<(f64, Vec<(usize, usize)>)> {
+    let length = term.len();
+    if length == 0 || text.is_empty() {
+        return None;
+    }
+    if term == text {
+        return Some((1.0, vec![(0, length)]));
+    }
+    if let Some(start) = text.windows(length).position(|window| window == term) {
+        let score = if start == 0 {
+            0.9
+        } else if !text[start - 1].is_alphanumeric() {
+            0.85
+        } else {
+            0.75
+        };
+        return Some((score, vec![(start, start + length)]));
+    }
+
+    let typo = approximate_match(term, text)
+        .filter(|(typos, _, _)| *typos <= max_typos(length))
+        .map(|(typos, start, end)| {
+            (
+                TYPO_SCORE * (1.0 - typos as f64 / length as f64),
+                vec![(start, end)],
+            )
+        });
+    let subsequence = subsequence_match(term, text);
+    match (typo, subsequence) {
+        (Some(typo), Some(subsequence)) if subsequence.0 > typo.0 => Some(subsequence),
+        (Some(typo), _) => Some(typo),
+        (None, subsequence) => subsequence,
+    }
+}
+
+/// Smallest number of edits (insertions, deletions, substitutions and
+/// swaps of adjacent characters) that turn `term` into some part of `text`,
+/// with where that part starts and ends.
+fn approximate_match(term: &[char], text: &[char]) -> Option<(usize, usize, usize)> {
+    let (m, n) = (term.len(), text.len());
+    // distance[i][j] is the cost of matching term[..i] to text ending at j,
+    // starting at start[i][j]. Matches may start anywhere for free.
+    let mut distance = vec![vec![0; n + 1]; m + 1];
+    let mut start = vec![vec![0; n + 1]; m + 1];
+    start[0] = (0..=n).collect();
+    for i in 1..=m {
+        distance[i][0] = i;
+        for j in 1..=n {
+            let substitution = distance[i - 1][j - 1] + usize::from(term[i - 1] != text[j - 1]);
+            let mut best = (substitution, start[i - 1][j - 1]);
+            if distance[i - 1][j] + 1 < best.0 {
+                best = (distance[i - 1][j] + 1, start[i - 1][j]);
+            }
+            if distance[i][j - 1] + 1 < best.0 {
+                best = (distance[i][j - 1] + 1, start[i][j - 1]);
+            }
+            if i > 1
+                && j > 1
+                && term[i - 1] == text[j - 2]
+                && term[i - 2] == text[j - 1]
+                && distance[i - 2][j - 2] + 1 < best.0
+            {
+                best = (distance[i - 2][j - 2] + 1, start[i - 2][j - 2]);
+            }
+            (distance[i][j], start[i][j]) = best;
+        }
+    }
+    (1..=n)
+        .map(|j| (distance[m][j], start[m][j], j))
+        .filter(|(_, start, end)| start < end)
+        .min_by_key(|(typos, start, end)| (*typos, end - start))
+}
+
+/// Matches the characters of `term` in order, possibly with gaps, as when
+/// typing the initials of a hostname. Tighter matches score higher.
+fn subsequence_match(term: &[char], text: &[char]) -> Option<(f64, Vec<(usize, usize)>)> {
+    if term.len() < 2 {
+        return None;
+    }
+    let mut positions = Vec::with_capacity(term.len());
+    let mut chars = text.iter().enumerate();
+    for c in term {
+        let (position, _) = chars.by_ref().find(|(_, candidate)| *candidate == c)?;
+        positions.push(position);
+    }
+    let spread = positions[positions.len() - 1] - positions[0] + 1;
+    let score = 0.5 * term.len() as f64 / spread as f64;
+    Some((
+        score,
+        merge(positions.into_iter().map(|p| (p, p + 1)).collect()),
+    ))
+}
+
+fn merge(mut spans: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    spans.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(spans.len());
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Scores a room against every term, each of which must match some field.
+/// The score is the mean of each term's best weighted match.
+fn score_room(terms: &[Vec<char>], room: &str, session: &Session) -> Option<(f64, Vec<Highlight>)> {
+    let fields = fields(room, session);
+    let folded: Vec<Vec<char>> = fields.iter().map(|field| fold(&field.text)).collect();
+    let mut total = 0.0;
+    let mut highlights: Vec<Highlight> = vec![];
+    for term in terms {
+        let (score, index, spans) = folded
+            .iter()
+            .enumerate()
+            .filter_map(|(index, text)| {
+                match_term(term, text)
+                    .map(|(score, spans)| (score * fields[index].weight, index, spans))
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)))?;
+        total += score;
+        let field = &fields[index];
+        match highlights
+            .iter_mut()
+            .find(|highlight| highlight.field == field.name && highlight.text == field.text)
+        {
+            Some(highlight) => {
+                highlight.spans.extend(spans);
+                highlight.spans = merge(std::mem::take(&mut highlight.spans));
+            }
+            None => highlights.push(Highlight {
+                field: field.name.clone(),
+                text: field.text.clone(),
+                spans,
+            }),
+        }
+    }
+    let score = total / terms.len() as f64;
+    Some(((score * 1000.0).round() / 1000.0, highlights))
+}
+
+/// Scores the rooms listed to `account`, best first.
+fn score_rooms<'a>(
+    terms: &[Vec<char>],
+    rooms: impl Iterator<Item = (&'a String, &'a Session)>,
+    account: Option<&str>,
+) -> Vec<(f64, &'a String, Vec<Highlight>)> {
+    let mut scored: Vec<(f64, &String, Vec<Highlight>)> = rooms
+        .filter(|(_, session)| {
+            session
+                .visibility
+                .is_listed_for(session.owner.as_deref(), account)
+        })
+        .filter_map(|(room, session)| {
+            score_room(terms, room, session).map(|(score, highlights)| (score, room, highlights))
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(b.1)));
+    scored
+}
+
+/// Best score of a room that one of `terms` matches neither exactly nor as a
+/// substring, as for every room the trigram index leaves out. Each of the
+/// other terms can still score up to 1.
+fn inexact_score_bound(terms: usize) -> f64 {
+    (terms as f64 - 1.0 + TYPO_SCORE * NAME_WEIGHT) / terms as f64
+}
+
+impl State {
+    /// Rooms listed to `account` that match the whitespace separated terms
+    /// of `query`, best first.
+    pub fn search_rooms(
+        &self,
+        query: &str,
+        limit: Option<usize>,
+        account: Option<&str>,
+    ) -> Result<Vec<SearchHit>> {
+        if query.chars().count() > MAX_QUERY_LENGTH {
+            return Err(format_err!(
+                "Search query is longer than {} characters",
+                MAX_QUERY_LENGTH
+            ));
+        }
+        let mut terms: Vec<Vec<char>> = vec![];
+        for term in query.split_whitespace().map(fold) {
+            if !terms.contains(&term) {
+                terms.push(term);
+            }
+        }
+        if terms.is_empty() {
+            return Err(format_err!("Search query is empty"));
+        }
+
+        let limit = limit
+            .unwrap_or(DEFAULT_SEARCH_LIMIT)
+            .clamp(1, MAX_PAGE_SIZE);
+        // Score the rooms the trigram index leaves first. Rooms it leaves out
+        // can still match with typos or as a subsequence, so scan them all
+        // unless a full page beats anything they could score.
+        let narrowed = self.catalog.search_candidates(&terms).map(|candidates| {
+            score_rooms(
+                &terms,
+                candidates
+                    .into_iter()
+                    .filter_map(|room| self.sessions.get_key_value(room)),
+                account,
+            )
+        });
+        let mut scored = match narrowed {
+            Some(scored)
+                if scored
+                    .get(limit - 1)
+                    .is_some_and(|(score, _, _)| *score > inexact_score_bound(terms.len())) =>
+            {
+                scored
+            }
+            _ => score_rooms(&terms, self.sessions.iter(), account),
+        };
+        scored.truncate(limit);
+
+        let caller = self.caller_for(account);
+        Ok(scored
+            .into_iter()
+            .filter_map(|(score, room, highlights)| {
+                let audience = caller.audience(self.sessions.get(room)?.owner.as_deref());
+                self.room_view(room, audience).map(|info| SearchHit {
+                    score,
+                    highlights,
+                    info,
+                })
+            })
+            .collect())
+    }
+}
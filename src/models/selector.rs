@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use failure::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+/// One condition of a tag selector.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Requirement {
+    /// `key`
+    Exists(String),
+    /// `!key`
+    NotExists(String),
+    /// `key=value` or `key in (a, b)`
+    In(String, Vec<String>),
+    /// `key!=value` or `key notin (a, b)`. Also matches rooms without the
+    /// tag.
+    NotIn(String, Vec<String>),
+}
+
+impl Requirement {
+    pub fn matches(&self, tags: &BTreeMap<String, String>) -> bool {
+        match self {
+            Requirement::Exists(key) => tags.contains_key(key),
+            Requirement::NotExists(key) => !tags.contains_key(key),
+            Requirement::In(key, values) => {
+                tags.get(key).is_some_and(|value| values.contains(value))
+            }
+            Requirement::NotIn(key, values) => {
+                tags.get(key).is_none_or(|value| !values.contains(value))
+            }
+        }
+    }
+}
+
+/// Comma separated tag requirements that must all hold, such as
+/// `site=berlin,floor in (1, 2),!retired`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TagSelector {
+    pub requirements: Vec<Requirement>,
+}
+
+fn parse_key(key: &str) -> Result<String, Error> {
+    let key = key.trim();
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '/'))
+    {
+        return Err(format_err!("Invalid tag key in selector: '{}'", key));
+    }
+    Ok(key.to_string())
+}
+
+fn parse_set(set: &str) -> Result<Vec<String>, Error> {
+    let values = set
+        .trim()
+        .strip_prefix('(')
+        .and_then(|set| set.strip_suffix(')'))
+        .ok_or_else(|| format_err!("Expected a parenthesized value list in selector"))?;
+    Ok(values
+        .split(',')
+        .map(|value| value.trim().to_string())
+        .collect())
+}
+
+fn parse_requirement(text: &str) -> Result<Requirement, Error> {
+    let text = text.trim();
+    if let Some(key) = text.strip_prefix('!') {
+        return Ok(Requirement::NotExists(parse_key(key)?));
+    }
+    if let Some((key, value)) = text.split_once("!=") {
+        return Ok(Requirement::NotIn(
+            parse_key(key)?,
+            vec![value.trim().to_string()],
+        ));
+    }
+    if let Some((key, value)) = text.split_once('=') {
+        let value = value.strip_prefix('=').unwrap_or(value);
+        return Ok(Requirement::In(
+            parse_key(key)?,
+            vec![value.trim().to_string()],
+        ));
+    }
+    if let Some((key, set)) = text.split_once(" notin ") {
+        return Ok(Requirement::NotIn(parse_key(key)?, parse_set(set)?));
+    }
+    if let Some((key, set)) = text.split_once(" in ") {
+        return Ok(Requirement::In(parse_key(key)?, parse_set(set)?));
+    }
+    Ok(Requirement::Exists(parse_key(text)?))
+}
+
+impl TagSelector {
+    pub fn parse(selector: &str) -> Result<Self, Error> {
+        // Split on the commas outside of value lists
+        let mut parts = vec![];
+        let mut depth = 0;
+        let mut start = 0;
+        for (index, c) in selector.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    parts.push(&selector[start..index]);
+                    start = index + 1;
+                }
+                _ => {}
+            }
+        }
+        parts.push(&selector[start..]);
+        let requirements = parts
+            .into_iter()
+            .filter(|part| !part.trim().is_empty())
+            .map(parse_requirement)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(TagSelector { requirements })
+    }
+
+    pub fn matches(&self, tags: &BTreeMap<String, String>) -> bool {
+        self.requirements
+            .iter()
+            .all(|requirement| requirement.matches(tags))
+    }
+}
+
+impl TryFrom<String> for TagSelector {
+    type Error = Error;
+
+    fn try_from(selector: String) -> Result<Self, Error> {
+        TagSelector::parse(&selector)
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Requirement::Exists(key) => write!(f, "{}", key),
+            Requirement::NotExists(key) => write!(f, "!{}", key),
+            Requirement::In(key, values) if values.len() == 1 => {
+                write!(f, "{}={}", key, values[0])
+            }
+            Requirement::NotIn(key, values) if values.len() == 1 => {
+                write!(f, "{}!={}", key, values[0])
+            }
+            Requirement::In(key, values) => write!(f, "{} in ({})", key, values.join(", ")),
+            Requirement::NotIn(key, values) => {
+                write!(f, "{} notin ({})", key, values.join(", "))
+            }
+        }
+    }
+}
+
+impl From<TagSelector> for String {
+    fn from(selector: TagSelector) -> String {
+        selector
+            .requirements
+            .iter()
+            .map(Requirement::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
@@ -1,7 +1,11 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::net::SocketAddr;
 use std::time::SystemTime;
 
+use crate::models::history::ViewerVisit;
+use crate::models::queue::QueuedViewer;
+use crate::models::room::Visibility;
+
 pub struct Session {
     pub server: String,
     pub viewers: HashSet<String>,
@@ -11,6 +15,22 @@ pub struct Session {
     pub os: String,
     pub version: String,
     pub control: bool,
+    /// Account the device is paired to, if any.
+    pub owner: Option<String>,
+    /// Viewer currently holding input control. At most one at a time.
+    pub controller: Option<String>,
+    /// Viewer limit requested by the host, unlimited if `None`.
+    pub max_viewers: Option<usize>,
+    /// Viewers waiting for a slot, in arrival order.
+    pub waiting: VecDeque<QueuedViewer>,
+    pub tags: BTreeMap<String, String>,
+    pub description: String,
+    pub visibility: Visibility,
+    /// Viewers that already left, for the session history. At most
+    /// `MAX_SESSION_VISITS`, the oldest are dropped first.
+    pub visits: Vec<ViewerVisit>,
+    /// Visits dropped from `visits` to stay under the cap.
+    pub dropped_visits: u64,
 }
 
 impl Session {
@@ -31,6 +51,20 @@ impl Session {
             os,
             version,
             control,
+            owner: None,
+            controller: None,
+            max_viewers: None,
+            waiting: Default::default(),
+            tags: Default::default(),
+            description: String::new(),
+            visibility: Visibility::Public,
+            visits: vec![],
+            dropped_visits: 0,
         }
     }
+
+    pub fn is_full(&self) -> bool {
+        self.max_viewers
+            .is_some_and(|max_viewers| self.viewers.len() >= max_viewers)
+    }
 }
@@ -1,17 +1,38 @@
-use std::collections::{HashMap, HashSet};
-use std::net::SocketAddr;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use axum::extract::ws::Message;
 use failure::{format_err, Error};
 use futures_channel::mpsc::UnboundedSender;
-use log::info;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 
-use crate::models::peer::{Peer, PeerType};
+use crate::models::account::AuthToken;
+use crate::models::audit::{AuditEvent, AuditLog};
+use crate::models::ban::Ban;
+use crate::models::catalog::RoomCatalog;
+use crate::models::connection::Connection;
+use crate::models::device::{PairingGuesses, PendingPairing};
+use crate::models::events::RoomEvent;
+use crate::models::filter::RoomFilter;
+use crate::models::group::{groups_by_room, DeviceGroup, GROUPS_TREE};
+use crate::models::health::{IceCheck, ServerMode};
+use crate::models::history::{index_history, EndReason, DEFAULT_HISTORY_RETENTION};
+use crate::models::invite::{invite_secret, InviteRecord};
+use crate::models::metrics::Metrics;
+use crate::models::oidc::{OidcConfig, PendingOidcLogin};
+use crate::models::peer::{Peer, PeerType, ViewerRole};
+use crate::models::queue::{Admission, QueuedViewer};
+use crate::models::registry::{DeviceStatus, KnownDevice, DEVICE_REGISTRY_TREE};
+use crate::models::room::Visibility;
 use crate::models::rtc::{IceServer, SignallerMessage};
 use crate::models::session::Session;
+use crate::models::store::Store;
+use crate::models::view::{Projection, ViewerInfo};
 
 type Result<T> = std::result::Result<T, Error>;
 type Tx = UnboundedSender<Message>;
@@ -20,29 +41,157 @@ pub struct State {
     pub sessions: HashMap<String, Session>,
     pub server_socket_addr_to_room: HashMap<SocketAddr, String>,
     pub peers: HashMap<String, Peer>,
-    pub room_update_subscribers: HashSet<String>,
+    /// Named room update subscriptions, by connection ID.
+    pub room_update_subscribers: HashMap<String, HashMap<String, RoomFilter>>,
+    pub store: Store,
+    pub auth_tokens: HashMap<String, AuthToken>,
+    pub invite_secret: Vec<u8>,
+    pub invites: HashMap<String, InviteRecord>,
+    pub pending_pairings: HashMap<String, PendingPairing>,
+    pub pairing_guesses: HashMap<String, PairingGuesses>,
+    /// Ended sessions older than this are dropped from the history, `None`
+    /// keeps them forever.
+    pub history_retention: Option<Duration>,
+    pub oidc: Option<OidcConfig>,
+    pub oidc_logins: HashMap<String, PendingOidcLogin>,
+    pub connections: HashMap<SocketAddr, Connection>,
+    pub next_connection_id: u64,
+    pub room_bans: HashMap<String, Vec<Ban>>,
+    /// Version of the latest room event, see `publish_room_event`.
+    pub room_event_version: u64,
+    /// Recent room events with their version, oldest first.
+    pub room_events: VecDeque<RoomEvent>,
+    pub catalog: RoomCatalog,
+    /// Device groups by name, mirrored from the store.
+    pub groups: HashMap<String, DeviceGroup>,
+    /// Groups listing each room directly, kept in step with `groups`.
+    pub groups_by_room: HashMap<String, BTreeSet<String>>,
+    /// Hosts that started a session, by room, mirrored from the store.
+    /// Unpaired ones are forgotten after `UNPAIRED_DEVICE_TTL`.
+    pub known_devices: HashMap<String, KnownDevice>,
+    /// Rooms of `known_devices`, most recently seen first, for paging
+    /// through offline devices.
+    pub devices_by_last_seen: BTreeSet<(Reverse<u64>, String)>,
+    /// Unix time unpaired devices were last pruned from `known_devices`.
+    pub registry_pruned_at: u64,
+    /// Where security-relevant events go, if auditing is enabled.
+    pub audit_log: Option<AuditLog>,
+    pub metrics: Metrics,
+    pub mode: ServerMode,
+    pub started_at: Instant,
+    /// Last readiness probe of the ICE servers, reused for `ICE_CHECK_TTL`.
+    pub ice_check: Option<IceCheck>,
+    /// Static bearer token granting the admin API, from `ADMIN_TOKEN`.
+    pub admin_token: Option<String>,
+    /// Which identifying room fields each audience is shown.
+    pub projection: Projection,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A room as one caller sees it, see `State::room_view`. Identities are
+/// only filled in for the owner and admins, real IPs only for admins.
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct RoomInfo {
-    pub server: String,
+    pub room: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_ip: Option<IpAddr>,
     pub viewer_count: usize,
-    pub viewers: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub viewers: Option<Vec<ViewerInfo>>,
     pub os: String,
     pub version: String,
     pub name: String,
     pub control: bool,
+    pub owner: Option<String>,
+    pub controller: Option<String>,
+    pub max_viewers: Option<usize>,
+    pub queue_length: usize,
+    pub tags: BTreeMap<String, String>,
+    pub description: String,
+    pub visibility: Visibility,
+    /// Groups the room belongs to, directly or through a subgroup.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// Unix time the session started, in seconds.
+    #[serde(default)]
+    pub start_time: u64,
+    #[serde(default)]
+    pub uptime_secs: u64,
+    #[serde(default)]
+    pub status: DeviceStatus,
+    /// Unix time an offline device was last online, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<u64>,
+    /// Length of the last session of an offline device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_session_secs: Option<u64>,
 }
 
 pub type StateType = Arc<Mutex<State>>;
 
 impl State {
+    /// State backed by a temporary store, for the tests.
+    #[cfg(test)]
     pub fn new() -> StateType {
+        State::with_store(Store::temporary())
+    }
+
+    pub fn with_store(store: Store) -> StateType {
+        if let Err(e) = index_history(&store) {
+            warn!("Failed to index the session history: {}", e);
+        }
+        let groups: HashMap<String, DeviceGroup> = store
+            .values::<DeviceGroup>(GROUPS_TREE)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|group| (group.name.clone(), group))
+            .collect();
+        let groups_by_room = groups_by_room(groups.values());
+        let known_devices: HashMap<String, KnownDevice> = store
+            .values::<KnownDevice>(DEVICE_REGISTRY_TREE)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|device| (device.room.clone(), device))
+            .collect();
+        let devices_by_last_seen = known_devices
+            .values()
+            .map(|device| (Reverse(device.last_seen), device.room.clone()))
+            .collect();
         Arc::new(Mutex::new(State {
             sessions: Default::default(),
             server_socket_addr_to_room: Default::default(),
             peers: Default::default(),
             room_update_subscribers: Default::default(),
+            store,
+            auth_tokens: Default::default(),
+            invite_secret: invite_secret(),
+            invites: Default::default(),
+            pending_pairings: Default::default(),
+            pairing_guesses: Default::default(),
+            history_retention: Some(DEFAULT_HISTORY_RETENTION),
+            oidc: OidcConfig::from_env(),
+            oidc_logins: Default::default(),
+            connections: Default::default(),
+            next_connection_id: 0,
+            room_bans: Default::default(),
+            room_event_version: 0,
+            room_events: Default::default(),
+            catalog: Default::default(),
+            groups,
+            groups_by_room,
+            known_devices,
+            devices_by_last_seen,
+            registry_pruned_at: 0,
+            audit_log: None,
+            metrics: Default::default(),
+            mode: Default::default(),
+            started_at: Instant::now(),
+            ice_check: None,
+            admin_token: std::env::var("ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.trim().is_empty()),
+            projection: Default::default(),
         }))
     }
 
@@ -66,45 +215,107 @@ impl State {
         );
         self.server_socket_addr_to_room
             .insert(socket_addr, room.clone());
+        self.index_room(&room);
         self.peers.insert(
             room.clone(),
             Peer {
                 room,
                 sender,
                 peer_type: PeerType::Server {},
+                account: None,
+                ip: self.connection_ip(&socket_addr),
+                joined_at: SystemTime::now(),
+                client: Default::default(),
+                connection: self
+                    .connections
+                    .get(&socket_addr)
+                    .map(|connection| connection.id.clone()),
             },
         );
         Ok(())
     }
 
-    pub fn add_viewer(&mut self, id: String, room: String, sender: Tx) -> Result<()> {
+    /// Admit a viewer with the default role and no known IP.
+    #[cfg(test)]
+    pub fn add_viewer(&mut self, id: String, room: String, sender: Tx) -> Result<Admission> {
+        self.add_viewer_with_role(id, room, sender, ViewerRole::Control, None)
+    }
+
+    pub fn add_viewer_with_role(
+        &mut self,
+        id: String,
+        room: String,
+        sender: Tx,
+        role: ViewerRole,
+        ip: Option<IpAddr>,
+    ) -> Result<Admission> {
         if !self.sessions.contains_key(&room) {
             return Err(format_err!("Device is offline"));
         }
-        self.sessions
-            .get_mut(&room)
-            .unwrap()
-            .viewers
-            .insert(id.clone());
+        self.check_peer_id(&id, &sender)?;
+        self.check_banned(&room, &id, ip)?;
+        let session = self.sessions.get_mut(&room).unwrap();
+        if session.is_full() {
+            if session.waiting.iter().any(|queued| queued.id == id) {
+                return Err(format_err!("Already waiting for this room"));
+            }
+            session.waiting.push_back(QueuedViewer {
+                id,
+                sender,
+                role,
+                ip,
+                account: None,
+                client: Default::default(),
+            });
+            return Ok(Admission::Queued {
+                position: session.waiting.len(),
+            });
+        }
+        let connection = self.connection_of(&sender);
+        let session = self.sessions.get_mut(&room).unwrap();
+        session.viewers.insert(id.clone());
         self.peers.insert(
             id,
             Peer {
-                room,
+                room: room.clone(),
                 sender,
-                peer_type: PeerType::Viewer {},
+                peer_type: PeerType::Viewer { role },
+                account: None,
+                ip,
+                joined_at: SystemTime::now(),
+                client: Default::default(),
+                connection,
             },
         );
-        Ok(())
+        self.index_viewer_count(&room);
+        self.publish_viewer_count(&room);
+        Ok(Admission::Admitted)
+    }
+
+    /// Whether `socket_addr` is the connection hosting `room`.
+    pub fn is_room_server(&self, room: &str, socket_addr: &SocketAddr) -> bool {
+        self.server_socket_addr_to_room
+            .get(socket_addr)
+            .is_some_and(|r| r == room)
     }
 
-    fn remove_session(&mut self, room: &String) {
+    pub fn remove_session(&mut self, room: &String, reason: EndReason) {
         info!("Removing session {}", room);
+        let info = self.room_info(room).unwrap();
+        let viewers: Vec<String> = self.sessions[room].viewers.iter().cloned().collect();
+        for viewer in &viewers {
+            self.record_visit(viewer);
+        }
         let session = self.sessions.remove(room).unwrap();
+        self.catalog.remove(room);
         self.server_socket_addr_to_room
             .remove(&session.server_socket_addr);
         let duration_sec = session.start_time.elapsed().unwrap().as_secs_f64();
         info!("Ended session with duration: {}s", duration_sec);
-        for viewer in session.viewers {
+        self.metrics.session_duration.observe(duration_sec);
+        let ip = self.peers.get(&session.server).and_then(|peer| peer.ip);
+        self.remember_session_end(room, &session, ip);
+        for viewer in viewers {
             let _ = self.peers[&viewer].sender.unbounded_send(Message::Text(
                 serde_json::to_string(&SignallerMessage::ServerClosed {
                     to: viewer.clone(),
@@ -115,29 +326,76 @@ impl State {
             self.peers.remove(&viewer);
         }
         self.peers.remove(&session.server);
+        self.record_session(room, session, ip, reason);
+        self.audit(
+            ip,
+            AuditEvent::RoomStopped {
+                room: room.clone(),
+                reason,
+            },
+        );
+        self.publish_room_removed(room, info);
     }
 
     /// Leave a session. id is the id of the viewer or the server.
     pub fn leave_session(&mut self, id: String) -> Result<()> {
         if self.sessions.contains_key(&id) {
             // id is host. remove session
-            self.remove_session(&id);
+            self.remove_session(&id, EndReason::HostLeft);
+        } else if let Some(room) = self.queued_room(&id) {
+            self.remove_from_queue(&room, &id);
         } else {
             let peer = self
                 .peers
                 .get(&id)
                 .ok_or_else(|| format_err!("Peer does not exist"))?;
-            let session = self.sessions.get_mut(&peer.room).unwrap();
+            let room = peer.room.clone();
+            let session = self.sessions.get_mut(&room).unwrap();
+            let viewer_count = session.viewers.len();
+            if session.viewers.contains(&id) {
+                self.record_visit(&id);
+            }
+            let session = self.sessions.get_mut(&room).unwrap();
             session.viewers.remove(&id);
+            let held_control = session.controller.as_deref() == Some(id.as_str());
             self.peers.remove(&id);
+            if held_control {
+                self.set_controller(&room, None);
+            }
+            self.promote_waiting(&room);
+            if self.sessions[&room].viewers.len() != viewer_count {
+                self.index_viewer_count(&room);
+                self.publish_viewer_count(&room);
+            }
         }
         Ok(())
     }
 
-    pub fn on_disconnect(&mut self, socket_addr: &SocketAddr) {
-        if let Some(room) = self.server_socket_addr_to_room.get(socket_addr) {
-            self.remove_session(&room.clone());
+    /// Whether peer `id` was registered by the connection sending on `tx`.
+    pub fn is_peer_connection(&self, id: &str, tx: &Tx) -> bool {
+        self.peers
+            .get(id)
+            .is_some_and(|peer| peer.sender.same_receiver(tx))
+    }
+
+    /// Fail if `id` names a room or belongs to a peer of another connection,
+    /// so joining cannot take over someone else's sender.
+    pub fn check_peer_id(&self, id: &str, tx: &Tx) -> Result<()> {
+        let taken = self.sessions.contains_key(id)
+            || self
+                .peers
+                .get(id)
+                .is_some_and(|peer| !peer.sender.same_receiver(tx))
+            || self.sessions.values().any(|session| {
+                session
+                    .waiting
+                    .iter()
+                    .any(|queued| queued.id == id && !queued.sender.same_receiver(tx))
+            });
+        if taken {
+            return Err(format_err!("Peer ID is already in use"));
         }
+        Ok(())
     }
 
     pub async fn get_ice_servers(&self, id: String) -> Vec<IceServer> {
@@ -148,160 +406,61 @@ impl State {
                 return vec![];
             }
         }
-
-        let mut servers = Vec::new();
-
-        // Get STUN servers from environment variables
-        if let Ok(stun_servers) = std::env::var("STUN_SERVERS") {
-            let stun_server_list: Vec<IceServer> = stun_servers
-                .split(',')
-                .map(|url| IceServer {
-                    urls: vec![url.trim().to_string()],
-                    ..Default::default()
-                })
-                .collect();
-
-            servers.extend(stun_server_list);
-        }
-
-        // Get TURN servers with individual credentials
-        if let Ok(turn_server_config) = std::env::var("TURN_SERVER_CONFIGS") {
-            // Format: url|username|credential,url2|username2|credential2
-            for config in turn_server_config.split(',') {
-                let parts: Vec<&str> = config.split('|').collect();
-                if parts.len() >= 3 {
-                    servers.push(IceServer {
-                        urls: vec![parts[0].trim().to_string()],
-                        username: parts[1].trim().to_string(),
-                        credential: parts[2].trim().to_string(),
-                        credential_type: "password".to_string(),
-                    });
-                }
-            }
-        }
-
-        // Legacy support for common credential TURN servers
-        if let (Ok(turn_urls), Ok(turn_username), Ok(turn_credential)) = (
-            std::env::var("TURN_SERVERS"),
-            std::env::var("TURN_USERNAME"),
-            std::env::var("TURN_CREDENTIAL"),
-        ) {
-            let turn_server_list: Vec<IceServer> = turn_urls
-                .split(',')
-                .map(|url| IceServer {
-                    urls: vec![url.trim().to_string()],
-                    username: turn_username.clone(),
-                    credential: turn_credential.clone(),
-                    credential_type: "password".to_string(),
-                })
-                .collect();
-
-            servers.extend(turn_server_list);
-        }
-
-        servers
+        configured_ice_servers()
     }
+}
 
-    #[allow(clippy::too_many_arguments)]
-    pub fn get_available_rooms(
-        &self,
-        os: Option<&str>,
-        version: Option<&str>,
-        server: Option<&str>,
-        name: Option<&str>,
-        sort: Option<&str>,
-        control: Option<bool>,
-        page: Option<usize>,
-        per_page: Option<usize>,
-    ) -> (HashMap<String, RoomInfo>, usize) {
-        let mut sessions_vec: Vec<_> = self.sessions.iter().collect();
+/// STUN and TURN servers configured in the environment.
+pub fn configured_ice_servers() -> Vec<IceServer> {
+    let mut servers = Vec::new();
 
-        // Sort sessions by start_time
-        sessions_vec.sort_by(|a, b| {
-            match sort {
-                Some("asc") => a.1.start_time.cmp(&b.1.start_time), // Ascending order
-                _ => b.1.start_time.cmp(&a.1.start_time),           // Default to descending order
-            }
-        });
-
-        // Filter sessions based on provided criteria
-        let filtered_sessions: Vec<_> = sessions_vec
-            .into_iter()
-            .filter(|(_, session)| {
-                (os.is_none()
-                    || os
-                        .map(|os_val| session.os.to_lowercase() == os_val.to_lowercase())
-                        .unwrap())
-                    && (version.is_none()
-                        || version
-                            .map(|ver| session.version.to_lowercase() == ver.to_lowercase())
-                            .unwrap())
-                    && (server.is_none()
-                        || server
-                            .map(|srv| session.server.to_lowercase() == srv.to_lowercase())
-                            .unwrap())
-                    && (name.is_none()
-                        || name
-                            .map(|name_val| {
-                                session
-                                    .name
-                                    .to_lowercase()
-                                    .contains(&name_val.to_lowercase())
-                            })
-                            .unwrap())
-                    && (control.is_none() || control.map(|ctrl| session.control == ctrl).unwrap())
+    // Get STUN servers from environment variables
+    if let Ok(stun_servers) = std::env::var("STUN_SERVERS") {
+        let stun_server_list: Vec<IceServer> = stun_servers
+            .split(',')
+            .map(|url| IceServer {
+                urls: vec![url.trim().to_string()],
+                ..Default::default()
             })
             .collect();
 
-        let total_count = filtered_sessions.len();
+        servers.extend(stun_server_list);
+    }
 
-        // Apply pagination
-        let start = (page.unwrap_or(1) - 1) * per_page.unwrap_or(6);
-        let _end = start + per_page.unwrap_or(6);
-        let paginated_sessions = filtered_sessions
-            .into_iter()
-            .skip(start)
-            .take(per_page.unwrap_or(6));
+    // Get TURN servers with individual credentials
+    if let Ok(turn_server_config) = std::env::var("TURN_SERVER_CONFIGS") {
+        // Format: url|username|credential,url2|username2|credential2
+        for config in turn_server_config.split(',') {
+            let parts: Vec<&str> = config.split('|').collect();
+            if parts.len() >= 3 {
+                servers.push(IceServer {
+                    urls: vec![parts[0].trim().to_string()],
+                    username: parts[1].trim().to_string(),
+                    credential: parts[2].trim().to_string(),
+                    credential_type: "password".to_string(),
+                });
+            }
+        }
+    }
 
-        // Map filtered sessions to RoomInfo
-        let rooms = paginated_sessions
-            .map(|(room, session)| {
-                (
-                    room.clone(),
-                    RoomInfo {
-                        server: session.server.clone(),
-                        viewer_count: session.viewers.len(),
-                        viewers: session.viewers.iter().cloned().collect(),
-                        os: session.os.clone(),
-                        version: session.version.clone(),
-                        name: session.name.clone(),
-                        control: session.control,
-                    },
-                )
+    // Legacy support for common credential TURN servers
+    if let (Ok(turn_urls), Ok(turn_username), Ok(turn_credential)) = (
+        std::env::var("TURN_SERVERS"),
+        std::env::var("TURN_USERNAME"),
+        std::env::var("TURN_CREDENTIAL"),
+    ) {
+        let turn_server_list: Vec<IceServer> = turn_urls
+            .split(',')
+            .map(|url| IceServer {
+                urls: vec![url.trim().to_string()],
+                username: turn_username.clone(),
+                credential: turn_credential.clone(),
+                credential_type: "password".to_string(),
             })
             .collect();
 
-        (rooms, total_count)
+        servers.extend(turn_server_list);
     }
 
-    pub fn subscribe_room_updates(&mut self, peer_id: String) {
-        self.room_update_subscribers.insert(peer_id);
-    }
-
-    pub fn unsubscribe_room_updates(&mut self, peer_id: &str) {
-        self.room_update_subscribers.remove(peer_id);
-    }
-
-    pub fn notify_room_update(&self, room: &str) {
-        for subscriber in &self.room_update_subscribers {
-            if let Some(peer) = self.peers.get(subscriber) {
-                let _ = peer.sender.unbounded_send(Message::Text(
-                    serde_json::to_string(&SignallerMessage::NewRoomNotification {
-                        room: room.to_string(),
-                    })
-                    .unwrap(),
-                ));
-            }
-        }
-    }
+    servers
 }
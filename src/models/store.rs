@@ -0,0 +1,90 @@
+use std::ops::RangeBounds;
+use std::path::Path;
+
+use failure::Error;
+use serde::{de::DeserializeOwned, Serialize};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Local embedded key/value store. Every record kind lives in its own tree and
+/// is serialized as JSON.
+#[derive(Clone)]
+pub struct Store {
+    db: sled::Db,
+}
+
+impl Store {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Store {
+            db: sled::open(path)?,
+        })
+    }
+
+    /// In-memory store that is discarded when dropped, for `State::new`
+    /// in the tests.
+    #[cfg(test)]
+    pub fn temporary() -> Self {
+        Store {
+            db: sled::Config::new()
+                .temporary(true)
+                .open()
+                .expect("Failed to open temporary store"),
+        }
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, tree: &str, key: &str) -> Result<Option<T>> {
+        match self.db.open_tree(tree)?.get(key)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put<T: Serialize>(&self, tree: &str, key: &str, value: &T) -> Result<()> {
+        self.db
+            .open_tree(tree)?
+            .insert(key, serde_json::to_vec(value)?)?;
+        Ok(())
+    }
+
+    pub fn remove(&self, tree: &str, key: &str) -> Result<()> {
+        self.db.open_tree(tree)?.remove(key)?;
+        Ok(())
+    }
+
+    /// All records of a tree, in key order.
+    pub fn values<T: DeserializeOwned>(&self, tree: &str) -> Result<Vec<T>> {
+        self.db
+            .open_tree(tree)?
+            .iter()
+            .values()
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+
+    /// Records of a tree whose keys are in `range`, last key first. Decoded
+    /// lazily, so callers can stop early.
+    pub fn range_rev<T: DeserializeOwned, R: RangeBounds<String>>(
+        &self,
+        tree: &str,
+        range: R,
+    ) -> Result<impl Iterator<Item = Result<T>>> {
+        Ok(self
+            .db
+            .open_tree(tree)?
+            .range(range)
+            .values()
+            .rev()
+            .map(|value| Ok(serde_json::from_slice(&value?)?)))
+    }
+
+    /// Remove the records whose keys are in `range`, returning how many.
+    pub fn remove_range<R: RangeBounds<String>>(&self, tree: &str, range: R) -> Result<usize> {
+        let tree = self.db.open_tree(tree)?;
+        let mut removed = 0;
+        for key in tree.range(range).keys() {
+            tree.remove(key?)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
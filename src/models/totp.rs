@@ -0,0 +1,230 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use data_encoding::BASE32_NOPAD;
+use failure::{format_err, Error};
+use hmac::{Hmac, Mac};
+use log::info;
+use sha1::Sha1;
+
+use crate::models::account::{hash_password, now_secs, verify_password, Account};
+use crate::models::state::State;
+
+type Result<T> = std::result::Result<T, Error>;
+type HmacSha1 = Hmac<Sha1>;
+
+pub const TOTP_ISSUER: &str = "remo-auth";
+pub const TOTP_STEP_SECS: u64 = 30;
+pub const TOTP_DIGITS: u32 = 6;
+/// Steps accepted on either side of the current one to absorb clock skew.
+pub const TOTP_SKEW_STEPS: u64 = 1;
+pub const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+/// Characters in a recovery code, not counting the '-' in the middle.
+const RECOVERY_CODE_LEN: usize = 8;
+
+/// RFC 6238 code for the given time step (HMAC-SHA1, 6 digits).
+pub fn totp_code(secret: &[u8], step: u64) -> u32 {
+    let mut mac = HmacSha1::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    // Dynamic truncation, RFC 4226 section 5.3
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// The step matching `code` within the skew window around `now`, if any.
+pub fn verify_totp(secret: &[u8], code: &str, now: u64) -> Option<u64> {
+    let code: u32 = code.trim().parse().ok()?;
+    let current = now / TOTP_STEP_SECS;
+    (current.saturating_sub(TOTP_SKEW_STEPS)..=current + TOTP_SKEW_STEPS)
+        .find(|step| totp_code(secret, *step) == code)
+}
+
+pub fn generate_totp_secret() -> String {
+    let mut secret = [0u8; 20];
+    OsRng.fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// `otpauth://` URI for authenticator apps, usually rendered as a QR code.
+pub fn otpauth_uri(username: &str, secret: &str) -> String {
+    let label = format!("{}:{}", TOTP_ISSUER, username);
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        label.replace(':', "%3A").replace(' ', "%20"),
+        secret,
+        TOTP_ISSUER,
+        TOTP_DIGITS,
+        TOTP_STEP_SECS
+    )
+}
+
+fn generate_recovery_code() -> String {
+    let mut code: String = (0..RECOVERY_CODE_LEN)
+        .map(|_| {
+            let index = OsRng.next_u32() as usize % RECOVERY_CODE_ALPHABET.len();
+            RECOVERY_CODE_ALPHABET[index] as char
+        })
+        .collect();
+    code.insert(4, '-');
+    code
+}
+
+/// Whether `code` is shaped like a recovery code (`xxxx-xxxx`), so anything
+/// else is rejected without running argon2.
+pub fn is_recovery_code(code: &str) -> bool {
+    let code = code.trim().as_bytes();
+    code.len() == RECOVERY_CODE_LEN + 1
+        && code.iter().enumerate().all(|(i, c)| {
+            if i == RECOVERY_CODE_LEN / 2 {
+                *c == b'-'
+            } else {
+                RECOVERY_CODE_ALPHABET.contains(c)
+            }
+        })
+}
+
+/// Fresh recovery codes in plain text, with their hashes. This runs argon2
+/// per code, so call it without the state lock held.
+pub fn generate_recovery_codes() -> Result<(Vec<String>, Vec<String>)> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes = codes
+        .iter()
+        .map(|code| hash_password(code))
+        .collect::<Result<_>>()?;
+    Ok((codes, hashes))
+}
+
+/// The hash in `recovery_codes` matching `code`, if any. This runs argon2
+/// per hash, so call it without the state lock held.
+pub fn find_recovery_code(recovery_codes: &[String], code: &str) -> Option<String> {
+    if !is_recovery_code(code) {
+        return None;
+    }
+    recovery_codes
+        .iter()
+        .find(|hash| verify_password(code.trim(), hash))
+        .cloned()
+}
+
+fn decode_secret(secret: &str) -> Result<Vec<u8>> {
+    BASE32_NOPAD
+        .decode(secret.as_bytes())
+        .map_err(|_| format_err!("Corrupt TOTP secret"))
+}
+
+impl State {
+    /// Generate a new secret for an account without an active second factor.
+    /// It only takes effect once confirmed with `confirm_totp_enrollment`.
+    pub fn begin_totp_enrollment(&mut self, account: &mut Account) -> Result<(String, String)> {
+        if account.totp_enabled {
+            return Err(format_err!("Two-factor authentication is already enabled"));
+        }
+        let secret = generate_totp_secret();
+        account.totp_secret = Some(secret.clone());
+        self.save_account(account)?;
+        Ok((secret.clone(), otpauth_uri(&account.username, &secret)))
+    }
+
+    /// Enable the pending secret once the user proved they can generate codes,
+    /// storing the hashes of the recovery codes from `recovery_codes`. The
+    /// account is read again, as the password was checked without the lock.
+    pub fn confirm_totp_enrollment(
+        &mut self,
+        account: &mut Account,
+        code: &str,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<()> {
+        self.refresh_account(account)?;
+        if account.totp_enabled {
+            return Err(format_err!("Two-factor authentication is already enabled"));
+        }
+        let secret = account
+            .totp_secret
+            .as_deref()
+            .ok_or_else(|| format_err!("No two-factor enrollment in progress"))?;
+        let step = match verify_totp(&decode_secret(secret)?, code, now_secs()) {
+            Some(step) => step,
+            None => {
+                self.record_failed_login(account)?;
+                return Err(format_err!("Invalid two-factor code"));
+            }
+        };
+
+        account.recovery_codes = recovery_code_hashes;
+        account.totp_enabled = true;
+        account.totp_last_step = step;
+        self.save_account(account)?;
+        info!("Enabled two-factor authentication for {}", account.username);
+        Ok(())
+    }
+
+    pub fn disable_totp(&mut self, account: &mut Account) -> Result<()> {
+        if account.totp_required {
+            return Err(format_err!(
+                "Two-factor authentication is required for this account"
+            ));
+        }
+        account.totp_enabled = false;
+        account.totp_secret = None;
+        account.recovery_codes.clear();
+        self.save_account(account)?;
+        info!(
+            "Disabled two-factor authentication for {}",
+            account.username
+        );
+        Ok(())
+    }
+
+    pub fn set_totp_required(&mut self, username: &str, required: bool) -> Result<()> {
+        let mut account = self
+            .get_account(username)?
+            .ok_or_else(|| format_err!("Account does not exist"))?;
+        account.totp_required = required;
+        self.save_account(&account)
+    }
+
+    /// Accept `code` if it is an unused TOTP code. Cheap enough to run under
+    /// the lock, unlike recovery codes. The account is read again so a code
+    /// used by a concurrent login is not accepted twice.
+    pub fn accept_totp(&mut self, account: &mut Account, code: &str) -> Result<bool> {
+        self.refresh_account(account)?;
+        let secret = decode_secret(account.totp_secret.as_deref().unwrap_or_default())?;
+        match verify_totp(&secret, code, now_secs()) {
+            Some(step) if step > account.totp_last_step => {
+                account.totp_last_step = step;
+                account.failed_logins = 0;
+                self.save_account(account)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Remove the recovery code `hash` found by `find_recovery_code`, or
+    /// record a failed login if there was none. The account is read again so
+    /// a code redeemed concurrently is not accepted twice.
+    pub fn redeem_recovery_code(
+        &mut self,
+        account: &mut Account,
+        hash: Option<&str>,
+    ) -> Result<()> {
+        self.refresh_account(account)?;
+        let index = hash.and_then(|hash| account.recovery_codes.iter().position(|h| h == hash));
+        if let Some(index) = index {
+            account.recovery_codes.remove(index);
+            account.failed_logins = 0;
+            info!("{} used a recovery code", account.username);
+            return self.save_account(account);
+        }
+        self.record_failed_login(account)?;
+        Err(format_err!("Invalid two-factor code"))
+    }
+}
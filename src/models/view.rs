@@ -0,0 +1,235 @@
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::account::AccountRole;
+use crate::models::connection::Connection;
+use crate::models::peer::ClientInfo;
+use crate::models::registry::DeviceStatus;
+use crate::models::state::{RoomInfo, State};
+
+/// How much of a room a caller gets to see. The defaults are described
+/// below; `Projection` sets the identifying fields of each.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Audience {
+    /// Anyone who neither owns the room nor is an admin: metadata and
+    /// counts, but nobody's identity.
+    Anonymous,
+    /// Logged in as the account the room is paired to: also the host and
+    /// viewer identities with their display names.
+    Owner,
+    /// Everything, including real IPs.
+    Admin,
+}
+
+impl Audience {
+    pub const ALL: [Audience; 3] = [Audience::Anonymous, Audience::Owner, Audience::Admin];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Fields of a room that identify people or hosts, shown depending on the
+/// audience.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomField {
+    /// The viewers with their IDs, accounts and display names.
+    Viewers,
+    /// The host's peer ID.
+    Server,
+    Owner,
+    Controller,
+    /// Real IPs of the host and the viewers.
+    Ips,
+}
+
+impl FromStr for RoomField {
+    type Err = String;
+
+    fn from_str(field: &str) -> std::result::Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(field.to_string()))
+            .map_err(|_| format!("Unknown room field {}", field))
+    }
+}
+
+/// Which `RoomField`s each audience sees. By default anonymous callers see
+/// none, owners all but IPs and admins all.
+#[derive(Clone, Debug)]
+pub struct Projection([BTreeSet<RoomField>; 3]);
+
+impl Default for Projection {
+    fn default() -> Self {
+        use RoomField::*;
+        Projection([
+            BTreeSet::new(),
+            BTreeSet::from([Viewers, Server, Owner, Controller]),
+            BTreeSet::from([Viewers, Server, Owner, Controller, Ips]),
+        ])
+    }
+}
+
+impl Projection {
+    pub fn shows(&self, audience: Audience, field: RoomField) -> bool {
+        self.0[audience.index()].contains(&field)
+    }
+
+    /// Show `audience` exactly `fields`.
+    pub fn set_fields(&mut self, audience: Audience, fields: impl IntoIterator<Item = RoomField>) {
+        self.0[audience.index()] = fields.into_iter().collect();
+    }
+}
+
+/// The account behind a request and whether it is an admin.
+#[derive(Clone, Debug, Default)]
+pub struct Caller {
+    pub account: Option<String>,
+    pub admin: bool,
+}
+
+impl Caller {
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+
+    /// How the caller sees a room paired to `owner`.
+    pub fn audience(&self, owner: Option<&str>) -> Audience {
+        if self.admin {
+            Audience::Admin
+        } else if self.account.is_some() && self.account() == owner {
+            Audience::Owner
+        } else {
+            Audience::Anonymous
+        }
+    }
+}
+
+/// A viewer of a room, as shown to its owner and admins.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewerInfo {
+    pub id: String,
+    /// What the viewer reported in `Join`. The display name defaults to the
+    /// account the viewer logged in with.
+    #[serde(flatten)]
+    pub client: ClientInfo,
+    /// Account the viewer logged in with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// Unix time the viewer was admitted, in seconds.
+    pub joined_at: u64,
+    /// Only shown to admins.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+}
+
+/// A message serialized once for each audience.
+pub struct Rendered([String; 3]);
+
+impl Rendered {
+    pub fn new(render: impl Fn(Audience) -> String) -> Self {
+        Rendered(Audience::ALL.map(render))
+    }
+
+    pub fn get(&self, audience: Audience) -> &str {
+        &self.0[audience.index()]
+    }
+}
+
+pub fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl State {
+    /// Resolve a logged in account, if any, to a caller.
+    pub fn caller_for(&self, account: Option<&str>) -> Caller {
+        let admin = account
+            .and_then(|account| self.get_account(account).ok().flatten())
+            .is_some_and(|account| account.role == AccountRole::Admin);
+        Caller {
+            account: account.map(str::to_string),
+            admin,
+        }
+    }
+
+    /// The caller behind a connection, using the role cached when it
+    /// authenticated.
+    pub fn caller_of(&self, connection: &Connection) -> Caller {
+        let account = self.account_of(connection);
+        Caller {
+            account: account.map(str::to_string),
+            admin: account.is_some() && connection.role == Some(AccountRole::Admin),
+        }
+    }
+
+    /// A room as `audience` may see it, see `Projection`.
+    pub fn room_view(&self, room: &str, audience: Audience) -> Option<RoomInfo> {
+        let session = self.sessions.get(room)?;
+        let shows = |field| self.projection.shows(audience, field);
+        let ips = shows(RoomField::Ips);
+        let viewers = shows(RoomField::Viewers).then(|| {
+            let mut viewers: Vec<ViewerInfo> = session
+                .viewers
+                .iter()
+                .filter_map(|id| {
+                    let peer = self.peers.get(id)?;
+                    let mut client = peer.client.clone();
+                    if client.display_name.is_none() {
+                        client.display_name = peer.account.clone();
+                    }
+                    Some(ViewerInfo {
+                        id: id.clone(),
+                        client,
+                        account: peer.account.clone(),
+                        joined_at: unix_secs(peer.joined_at),
+                        ip: peer.ip.filter(|_| ips),
+                    })
+                })
+                .collect();
+            viewers.sort_by(|a, b| a.id.cmp(&b.id));
+            viewers
+        });
+        Some(RoomInfo {
+            room: room.to_string(),
+            server: shows(RoomField::Server).then(|| session.server.clone()),
+            server_ip: self
+                .peers
+                .get(&session.server)
+                .and_then(|peer| peer.ip)
+                .filter(|_| ips),
+            viewer_count: session.viewers.len(),
+            viewers,
+            os: session.os.clone(),
+            version: session.version.clone(),
+            name: session.name.clone(),
+            control: session.control,
+            owner: session.owner.clone().filter(|_| shows(RoomField::Owner)),
+            controller: session
+                .controller
+                .clone()
+                .filter(|_| shows(RoomField::Controller)),
+            max_viewers: session.max_viewers,
+            queue_length: session.waiting.len(),
+            tags: session.tags.clone(),
+            description: session.description.clone(),
+            visibility: session.visibility,
+            groups: self.room_groups(room).into_iter().collect(),
+            start_time: unix_secs(session.start_time),
+            uptime_secs: session.start_time.elapsed().unwrap_or_default().as_secs(),
+            status: DeviceStatus::Online,
+            last_seen: None,
+            last_session_secs: None,
+        })
+    }
+
+    /// Everything known about a room. Used to match filters and visibility,
+    /// never sent to anyone but admins.
+    pub fn room_info(&self, room: &str) -> Option<RoomInfo> {
+        self.room_view(room, Audience::Admin)
+    }
+}
@@ -0,0 +1,25 @@
+use axum::{
+    middleware::from_fn_with_state,
+    routing::{delete, get, post},
+    Router,
+};
+
+use crate::{
+    args::Args, controllers::admin, middleware::admin::admin_only, models::state::StateType,
+};
+
+/// Operator endpoints, nested under `/admin`. All of them require
+/// `ADMIN_TOKEN` or an admin account.
+pub fn admin_router(state: StateType) -> Router<(StateType, Args)> {
+    Router::new()
+        .route("/sessions", get(admin::list_sessions))
+        .route("/sessions/:room", delete(admin::close_room))
+        .route(
+            "/sessions/:room/viewers/:viewer/kick",
+            post(admin::kick_viewer),
+        )
+        .route("/connections", get(admin::list_connections))
+        .route("/peers", get(admin::list_peers))
+        .route("/peers/:id/messages", post(admin::send_message))
+        .route_layer(from_fn_with_state(state, admin_only))
+}
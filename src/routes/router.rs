@@ -1,17 +1,51 @@
-use axum::{middleware::from_fn, routing::get, Router};
+use axum::{
+    middleware::from_fn,
+    routing::{delete, get, post, put},
+    Router,
+};
 use tower_http::trace::TraceLayer;
 
 use crate::{
     args::Args,
-    controllers::{health::health_check, websocket::websocket_handler},
+    controllers::{
+        auth, device, group, health, history, invite, metrics::metrics, oidc, totp,
+        websocket::websocket_handler,
+    },
     middleware::ip::real_ip,
     models::state::StateType,
+    routes::admin::admin_router,
 };
 
 pub fn create_router(state: StateType, args: Args) -> Router {
     Router::new()
-        .route("/health", get(health_check))
+        .route("/health", get(health::health_check))
+        .route("/health/live", get(health::live))
+        .route("/health/ready", get(health::ready))
+        .route("/health/maintenance", put(health::set_maintenance))
+        .route("/metrics", get(metrics))
         .route("/", get(websocket_handler))
+        .route("/auth/register", post(auth::register))
+        .route("/auth/login", post(auth::login))
+        .route("/auth/logout", post(auth::logout))
+        .route("/auth/me", get(auth::me))
+        .route("/auth/totp/enroll", post(totp::enroll))
+        .route("/auth/totp/confirm", post(totp::confirm))
+        .route("/auth/totp/disable", post(totp::disable))
+        .route("/auth/accounts/:username/totp", put(totp::set_required))
+        .route("/auth/accounts/:username/role", put(auth::set_role))
+        .route("/auth/oidc/login", get(oidc::oidc_login))
+        .route("/auth/oidc/callback", get(oidc::oidc_callback))
+        .route("/devices", get(device::list_devices))
+        .route("/groups", get(group::list_groups))
+        .route(
+            "/groups/:name",
+            put(group::put_group).delete(group::delete_group),
+        )
+        .route("/history/sessions", get(history::list_sessions))
+        .route("/history/usage", get(history::usage))
+        .route("/invites", post(invite::create_invite))
+        .route("/invites/:id", delete(invite::revoke_invite))
+        .nest("/admin", admin_router(state.clone()))
         .layer(from_fn(real_ip))
         .layer(TraceLayer::new_for_http())
         .with_state((state, args))
@@ -0,0 +1,119 @@
+use std::sync::OnceLock;
+
+use failure::{format_err, Error};
+use log::info;
+
+use crate::models::account::{
+    hash_password, random_token, second_factor_code, validate_credentials, verify_password, Account,
+};
+use crate::models::metrics::lock_state;
+use crate::models::state::StateType;
+use crate::models::totp::{find_recovery_code, generate_recovery_codes};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Run argon2 work on the blocking pool, so it neither stalls the runtime
+/// nor holds the state lock.
+pub async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| format_err!("Password check failed: {}", e))
+}
+
+/// A password hash to verify against when there is no account to check.
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password(&random_token(16)).unwrap_or_default())
+}
+
+/// Register a new account, hashing the password without the lock held.
+pub async fn register_account(
+    state: &StateType,
+    username: &str,
+    password: &str,
+) -> Result<Account> {
+    validate_credentials(username, password)?;
+    lock_state(state).await.check_username_available(username)?;
+    let password = password.to_string();
+    let password_hash = blocking(move || hash_password(&password)).await??;
+    lock_state(state)
+        .await
+        .create_account(username, password_hash)
+}
+
+/// Check a username and password, enforcing the failed-login lockout. The
+/// hash is verified without the lock held. This is only the first factor,
+/// see `login`.
+pub async fn verify_credentials(
+    state: &StateType,
+    username: &str,
+    password: &str,
+) -> Result<Account> {
+    let account = lock_state(state).await.account_for_login(username);
+    let password = password.to_string();
+    let account = match account {
+        Ok(account) => account,
+        Err(e) => {
+            // Take as long as a real check, so timing does not tell unknown
+            // or locked accounts apart either
+            blocking(move || verify_password(&password, dummy_hash())).await?;
+            return Err(e);
+        }
+    };
+    let valid = blocking(move || verify_password(&password, &account.password_hash)).await?;
+    lock_state(state).await.finish_credentials(username, valid)
+}
+
+/// Log in with a password and, for accounts with two-factor authentication,
+/// a TOTP or recovery code. Returns a session token.
+pub async fn login(
+    state: &StateType,
+    username: &str,
+    password: &str,
+    totp: Option<&str>,
+) -> Result<String> {
+    let mut account = verify_credentials(state, username, password).await?;
+    if let Some(code) = second_factor_code(&account, totp)? {
+        verify_second_factor(state, &mut account, code).await?;
+    }
+    info!("{} logged in", account.username);
+    Ok(lock_state(state).await.issue_token(&account.username))
+}
+
+/// Check a TOTP or recovery code. Codes are single use: a TOTP step is never
+/// accepted twice and a recovery code is removed once redeemed. Failures
+/// count toward the login lockout. Recovery codes are tried without the lock
+/// held.
+pub async fn verify_second_factor(
+    state: &StateType,
+    account: &mut Account,
+    code: &str,
+) -> Result<()> {
+    if lock_state(state).await.accept_totp(account, code)? {
+        return Ok(());
+    }
+    let recovery_codes = account.recovery_codes.clone();
+    let code = code.to_string();
+    let hash = blocking(move || find_recovery_code(&recovery_codes, &code)).await?;
+    lock_state(state)
+        .await
+        .redeem_recovery_code(account, hash.as_deref())
+}
+
+/// Confirm a TOTP enrollment, hashing the new recovery codes without the lock
+/// held. Returns the recovery codes in plain text.
+pub async fn confirm_totp_enrollment(
+    state: &StateType,
+    account: &mut Account,
+    code: &str,
+) -> Result<Vec<String>> {
+    let (recovery_codes, hashes) = blocking(generate_recovery_codes).await??;
+    lock_state(state)
+        .await
+        .confirm_totp_enrollment(account, code, hashes)?;
+    Ok(recovery_codes)
+}
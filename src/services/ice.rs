@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use failure::{format_err, Error};
+use futures_util::future::join_all;
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+use crate::models::rtc::IceServer;
+
+type Result<T> = std::result::Result<T, Error>;
+
+pub const ICE_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+const STUN_MAGIC_COOKIE: [u8; 4] = [0x21, 0x12, 0xa4, 0x42];
+const STUN_BINDING_REQUEST: [u8; 2] = [0x00, 0x01];
+const STUN_BINDING_SUCCESS: [u8; 2] = [0x01, 0x01];
+const STUN_BINDING_ERROR: [u8; 2] = [0x01, 0x11];
+
+/// Where a `stun:`, `stuns:`, `turn:` or `turns:` URL points to.
+#[derive(Debug, PartialEq, Eq)]
+pub struct IceEndpoint {
+    pub host: String,
+    pub port: u16,
+    /// Secure URLs and `?transport=tcp` are reached over TCP.
+    pub tcp: bool,
+}
+
+pub fn parse_ice_url(url: &str) -> Result<IceEndpoint> {
+    let (scheme, rest) = url
+        .split_once(':')
+        .ok_or_else(|| format_err!("{} has no scheme", url))?;
+    let secure = match scheme {
+        "stun" | "turn" => false,
+        "stuns" | "turns" => true,
+        _ => return Err(format_err!("{} is not a STUN or TURN URL", url)),
+    };
+    let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let tcp = secure || query.split('&').any(|param| param == "transport=tcp");
+    let default_port = if secure { 5349 } else { 3478 };
+    let (host, port) = match address.strip_prefix('[') {
+        // [IPv6]:port
+        Some(address) => {
+            let (host, port) = address
+                .split_once(']')
+                .ok_or_else(|| format_err!("{} has an unterminated IPv6 address", url))?;
+            (host, port.strip_prefix(':'))
+        }
+        None => match address.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (address, None),
+        },
+    };
+    if host.is_empty() {
+        return Err(format_err!("{} has no host", url));
+    }
+    let port = match port {
+        Some(port) => port
+            .parse()
+            .map_err(|_| format_err!("{} has an invalid port", url))?,
+        None => default_port,
+    };
+    Ok(IceEndpoint {
+        host: host.to_string(),
+        port,
+        tcp,
+    })
+}
+
+/// Check that the server behind `url` answers: a STUN binding request over
+/// UDP, or a TCP connect for TCP transports.
+pub async fn probe_ice_url(url: &str, limit: Duration) -> Result<()> {
+    let endpoint = parse_ice_url(url)?;
+    timeout(limit, probe(&endpoint))
+        .await
+        .map_err(|_| format_err!("{} did not respond", url))?
+}
+
+async fn probe(endpoint: &IceEndpoint) -> Result<()> {
+    let address = lookup_host((endpoint.host.as_str(), endpoint.port))
+        .await?
+        .next()
+        .ok_or_else(|| format_err!("{} did not resolve", endpoint.host))?;
+    if endpoint.tcp {
+        TcpStream::connect(address).await?;
+        return Ok(());
+    }
+    let local = if address.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(address).await?;
+    let mut transaction = [0u8; 12];
+    OsRng.fill_bytes(&mut transaction);
+    let mut request = Vec::with_capacity(20);
+    request.extend_from_slice(&STUN_BINDING_REQUEST);
+    request.extend_from_slice(&[0, 0]);
+    request.extend_from_slice(&STUN_MAGIC_COOKIE);
+    request.extend_from_slice(&transaction);
+    socket.send(&request).await?;
+    let mut response = [0u8; 1024];
+    loop {
+        let len = socket.recv(&mut response).await?;
+        // Anything but our answer is ignored
+        if len >= 20
+            && (response[..2] == STUN_BINDING_SUCCESS || response[..2] == STUN_BINDING_ERROR)
+            && response[4..8] == STUN_MAGIC_COOKIE
+            && response[8..20] == transaction
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// Probe every URL of `servers` at once. Fine if there are none, or if any
+/// of them answers, as clients fall back to the others.
+pub async fn check_ice_servers(servers: &[IceServer], limit: Duration) -> Result<()> {
+    let urls: Vec<&String> = servers.iter().flat_map(|server| &server.urls).collect();
+    if urls.is_empty() {
+        return Ok(());
+    }
+    let results = join_all(urls.iter().map(|url| probe_ice_url(url, limit))).await;
+    if results.iter().any(|result| result.is_ok()) {
+        return Ok(());
+    }
+    let errors: Vec<String> = results
+        .into_iter()
+        .filter_map(|result| result.err().map(|e| e.to_string()))
+        .collect();
+    Err(format_err!(
+        "No ICE server responded: {}",
+        errors.join("; ")
+    ))
+}
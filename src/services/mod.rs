@@ -1 +1,4 @@
+pub mod auth;
+pub mod ice;
+pub mod oidc;
 pub mod websocket;
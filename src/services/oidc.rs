@@ -0,0 +1,154 @@
+use failure::{format_err, Error};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::models::{account::AccountRole, oidc::OidcConfig};
+
+type Result<T> = std::result::Result<T, Error>;
+pub type Claims = Map<String, Value>;
+
+/// The subset of the provider's discovery document we rely on.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+pub async fn discover(config: &OidcConfig) -> Result<ProviderMetadata> {
+    let url = format!("{}/.well-known/openid-configuration", config.issuer);
+    let metadata: ProviderMetadata = reqwest::get(&url).await?.error_for_status()?.json().await?;
+    if metadata.issuer.trim_end_matches('/') != config.issuer {
+        return Err(format_err!("Discovery document issuer does not match"));
+    }
+    Ok(metadata)
+}
+
+pub fn authorization_url(
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    state: &str,
+    nonce: &str,
+) -> Result<String> {
+    let url = Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", config.client_id.as_str()),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("scope", config.scopes.as_str()),
+            ("state", state),
+            ("nonce", nonce),
+        ],
+    )?;
+    Ok(url.into())
+}
+
+/// Redeem an authorization code for the ID token.
+pub async fn exchange_code(
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    code: &str,
+) -> Result<String> {
+    let response: TokenResponse = reqwest::Client::new()
+        .post(&metadata.token_endpoint)
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("client_id", config.client_id.as_str()),
+            ("client_secret", config.client_secret.as_str()),
+        ])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(response.id_token)
+}
+
+/// Verify the ID token's signature against the provider's JWKS, then its
+/// issuer, audience, expiry and nonce.
+pub async fn verify_id_token(
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    id_token: &str,
+    nonce: &str,
+) -> Result<Claims> {
+    let header = decode_header(id_token)?;
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err(format_err!(
+            "Symmetric ID token signatures are not accepted"
+        ));
+    }
+    let jwks: JwkSet = reqwest::get(&metadata.jwks_uri)
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let jwk = match header.kid.as_deref() {
+        Some(kid) => jwks.find(kid),
+        None => jwks.keys.first(),
+    }
+    .ok_or_else(|| format_err!("No matching key in JWKS"))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_issuer(&[&metadata.issuer]);
+    let claims = decode::<Claims>(id_token, &DecodingKey::from_jwk(jwk)?, &validation)?.claims;
+
+    if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+        return Err(format_err!("ID token nonce does not match"));
+    }
+    Ok(claims)
+}
+
+/// The provider's stable identifier for the user, which accounts are bound
+/// to together with the issuer.
+pub fn subject_from_claims(claims: &Claims) -> Result<String> {
+    claims
+        .get("sub")
+        .and_then(Value::as_str)
+        .filter(|sub| !sub.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| format_err!("ID token has no subject"))
+}
+
+/// Name for a new account, from the configured claim or else `sub`. The
+/// `oidc:` prefix keeps provider accounts apart from password accounts, which
+/// cannot contain ':'.
+pub fn suggested_username(config: &OidcConfig, claims: &Claims) -> Result<String> {
+    let name = claims
+        .get(&config.username_claim)
+        .and_then(Value::as_str)
+        .map_or_else(|| subject_from_claims(claims), |name| Ok(name.to_string()))?;
+    Ok(format!("oidc:{}", name))
+}
+
+/// Map the configured role claim, a string or an array of strings, to a role.
+pub fn map_role(config: &OidcConfig, claims: &Claims) -> AccountRole {
+    let values: Vec<&str> = match config.role_claim.as_ref().and_then(|c| claims.get(c)) {
+        Some(Value::String(value)) => vec![value.as_str()],
+        Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    };
+    if values
+        .iter()
+        .any(|value| config.admin_values.iter().any(|admin| admin == value))
+    {
+        AccountRole::Admin
+    } else {
+        AccountRole::User
+    }
+}
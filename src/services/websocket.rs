@@ -3,8 +3,22 @@ use futures_channel::mpsc::{unbounded, UnboundedSender};
 use futures_util::{future, pin_mut, stream::TryStreamExt, StreamExt};
 use log::info;
 use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
 
-use crate::{args::Args, models::rtc::SignallerMessage, models::state::StateType};
+use crate::{
+    args::Args,
+    models::audit::{AuditEvent, JoinOutcome},
+    models::device::PAIRING_CODE_TTL,
+    models::events::DEFAULT_SUBSCRIPTION,
+    models::invite::DEFAULT_INVITE_TTL,
+    models::metrics::lock_state,
+    models::peer::ViewerRole,
+    models::queue::Admission,
+    models::room::{validate_name, validate_tags, validate_version, Visibility},
+    models::rtc::{message_type, SignallerMessage},
+    models::state::StateType,
+    services::auth,
+};
 
 type Tx = UnboundedSender<Message>;
 
@@ -14,6 +28,7 @@ pub async fn handle_connection(
     websocket: WebSocket,
     socket_addr: SocketAddr,
     real_ip: Option<&IpAddr>,
+    token: Option<String>,
 ) {
     info!(
         "WebSocket connection established: {socket_addr}, real IP: {:?}",
@@ -21,6 +36,15 @@ pub async fn handle_connection(
     );
 
     let (tx, rx) = unbounded();
+    {
+        let mut locked_state = lock_state(&state).await;
+        locked_state.metrics.connections_total += 1;
+        locked_state.register_connection(socket_addr, tx.clone(), real_ip.copied());
+        if let Some(token) = token {
+            // Checked during the handshake already, but it may have expired
+            let _ = locked_state.authenticate_connection(socket_addr, &token);
+        }
+    }
     let (outgoing, incoming) = websocket.split();
 
     let handle_incoming =
@@ -32,7 +56,7 @@ pub async fn handle_connection(
     future::select(handle_incoming, receive_from_others).await;
 
     info!("{socket_addr} disconnected, real IP: {:?}", real_ip);
-    state.lock().await.on_disconnect(&socket_addr);
+    lock_state(&state).await.on_disconnect(&socket_addr);
 }
 
 pub async fn handle_message(
@@ -42,15 +66,23 @@ pub async fn handle_message(
     socket_addr: SocketAddr,
 ) -> Result<(), failure::Error> {
     let msg: SignallerMessage = serde_json::from_str(raw_payload)?;
+    state.metrics.message_received(raw_payload);
+    let connection = state.connection_id(socket_addr, tx);
     let forward_message =
-        |state: &crate::models::state::State, to: String| -> Result<(), failure::Error> {
-            let peer = state
+        |state: &mut crate::models::state::State, to: String| -> Result<(), failure::Error> {
+            let sent = state
                 .peers
                 .get(&to)
-                .ok_or_else(|| failure::format_err!("Peer does not exist"))?;
-            peer.sender
-                .unbounded_send(Message::Text(raw_payload.to_string()))?;
-            Ok(())
+                .ok_or_else(|| failure::format_err!("Peer does not exist"))
+                .and_then(|peer| {
+                    peer.sender
+                        .unbounded_send(Message::Text(raw_payload.to_string()))
+                        .map_err(Into::into)
+                });
+            if sent.is_err() {
+                state.metrics.forward_failures += 1;
+            }
+            sent
         };
 
     match msg {
@@ -60,7 +92,24 @@ pub async fn handle_message(
             os,
             version,
             control,
+            device_secret,
+            max_viewers,
+            visibility,
+            tags,
         } => {
+            if max_viewers == Some(0) {
+                return Err(failure::format_err!("max_viewers must be at least 1"));
+            }
+            validate_name(&name)?;
+            validate_version(&version)?;
+            validate_tags(&tags)?;
+            let owner = state.check_device_secret(&room, device_secret.as_deref())?;
+            let visibility = visibility.unwrap_or_default();
+            if visibility != Visibility::Public && owner.is_none() {
+                return Err(failure::format_err!(
+                    "Only rooms paired to an account can be unlisted or owner-only"
+                ));
+            }
             state.add_server(
                 room.clone(),
                 name,
@@ -70,20 +119,101 @@ pub async fn handle_message(
                 tx.clone(),
                 socket_addr,
             )?;
+            state.bind_peer_account(&room, &socket_addr);
+            if let Some(session) = state.sessions.get_mut(&room) {
+                session.owner = owner;
+                session.max_viewers = max_viewers;
+                session.visibility = visibility;
+                session.tags = tags;
+            }
+            state.index_room(&room);
+            state.remember_device(&room);
+            let owner = state.sessions[&room].owner.clone();
+            state.audit(
+                state.connection_ip(&socket_addr),
+                AuditEvent::RoomStarted {
+                    room: room.clone(),
+                    owner,
+                },
+            );
             tx.unbounded_send(Message::Text(serde_json::to_string(
                 &SignallerMessage::StartResponse { room: room.clone() },
             )?))?;
-            state.notify_room_update(&room);
+            state.publish_room_added(&room);
         }
-        SignallerMessage::Join { from, room } => {
+        SignallerMessage::Join {
+            from,
+            room,
+            invite,
+            client,
+        } => {
             info!("{} attempting to join room {}", from, room);
-            match state.add_viewer(from.clone(), room.clone(), tx.clone()) {
-                Ok(_) => {
+            let ip = state.connection_ip(&socket_addr);
+            let with_invite = invite.is_some();
+            let admitted = client.validate().and_then(|_| match invite {
+                Some(token) => state.verify_invite(&token, &room).and_then(|claims| {
+                    let admission = state.add_viewer_with_role(
+                        from.clone(),
+                        room.clone(),
+                        tx.clone(),
+                        claims.role,
+                        ip,
+                    )?;
+                    state.redeem_invite(&claims);
+                    Ok(admission)
+                }),
+                None => {
+                    let account = state.connection_account(&socket_addr);
+                    state
+                        .check_room_access(&room, account.as_deref())
+                        .and_then(|_| {
+                            state.add_viewer_with_role(
+                                from.clone(),
+                                room.clone(),
+                                tx.clone(),
+                                ViewerRole::Control,
+                                ip,
+                            )
+                        })
+                }
+            });
+            let (outcome, reason) = match &admitted {
+                Ok(Admission::Admitted) => (JoinOutcome::Admitted, None),
+                Ok(Admission::Queued { .. }) => (JoinOutcome::Queued, None),
+                Err(e) => (JoinOutcome::Declined, Some(e.to_string())),
+            };
+            state.audit(
+                ip,
+                AuditEvent::JoinAttempt {
+                    room: room.clone(),
+                    viewer: from.clone(),
+                    outcome,
+                    reason,
+                    invite: with_invite,
+                },
+            );
+            match admitted {
+                Ok(Admission::Admitted) => {
                     info!("{} joined room {}", from, room);
+                    state.bind_peer_account(&from, &socket_addr);
+                    state.bind_client_info(&room, &from, client);
                     forward_message(state, room)?;
                 }
+                Ok(Admission::Queued { position }) => {
+                    info!("{} queued for room {} at {}", from, room, position);
+                    state.bind_queued_account(&room, &from, &socket_addr);
+                    state.bind_client_info(&room, &from, client);
+                    tx.unbounded_send(Message::Text(serde_json::to_string(
+                        &SignallerMessage::Queued {
+                            to: from,
+                            room,
+                            position,
+                        },
+                    )?))?;
+                }
                 Err(e) => {
                     info!("Error joining room: {}", e);
+                    state.metrics.join_declined(&e.to_string());
                     tx.unbounded_send(Message::Text(serde_json::to_string(
                         &SignallerMessage::JoinDeclined {
                             to: from,
@@ -94,6 +224,11 @@ pub async fn handle_message(
             }
         }
         SignallerMessage::Leave { from } => {
+            if !state.is_peer_connection(&from, tx) && !state.is_queued_connection(&from, tx) {
+                return Err(failure::format_err!(
+                    "Peer does not belong to this connection"
+                ));
+            }
             state.leave_session(from)?;
         }
         SignallerMessage::Offer { to, .. }
@@ -103,62 +238,302 @@ pub async fn handle_message(
             forward_message(state, to)?;
         }
         SignallerMessage::IceServers { id } => {
-            let ice_servers = state.get_ice_servers(id).await;
+            state.metrics.ice_requests += 1;
+            let ice_servers = state.get_ice_servers(id.clone()).await;
+            state.audit(
+                state.connection_ip(&socket_addr),
+                AuditEvent::IceServersIssued {
+                    peer: id,
+                    servers: ice_servers.len(),
+                },
+            );
             tx.unbounded_send(Message::Text(serde_json::to_string(
                 &SignallerMessage::IceServersResponse { ice_servers },
             )?))?;
         }
-        SignallerMessage::GetRoomList {
-            os,
-            name,
-            version,
-            server,
-            sort,
-            control,
-            page,
-            per_page,
-        } => {
-            let (rooms, total_count) = state.get_available_rooms(
-                os.as_deref(),
-                version.as_deref(),
-                server.as_deref(),
-                name.as_deref(),
-                sort.as_deref(),
-                control,
-                page,
-                per_page,
-            );
+        SignallerMessage::GetRoomList { query } => {
+            let account = state.connection_account(&socket_addr);
+            let listing = state.get_available_rooms(&query, account.as_deref())?;
             tx.unbounded_send(Message::Text(serde_json::to_string(
                 &SignallerMessage::RoomListResponse {
-                    rooms,
-                    total_count,
-                    page,
-                    per_page,
+                    rooms: listing.rooms,
+                    total_count: listing.total_count,
+                    page: query.page,
+                    per_page: query.per_page,
+                    next_cursor: listing.next_cursor,
+                    offline_rooms: listing.offline_rooms,
+                    next_offline_cursor: listing.next_offline_cursor,
                 },
             )?))?;
         }
+        SignallerMessage::SearchRooms { query, limit } => {
+            let account = state.connection_account(&socket_addr);
+            let results = state.search_rooms(&query, limit, account.as_deref())?;
+            tx.unbounded_send(Message::Text(serde_json::to_string(
+                &SignallerMessage::SearchResults { query, results },
+            )?))?;
+        }
+        SignallerMessage::Login { .. } => {
+            // Password checks must not run under the lock, see `handle_login`
+            return Err(failure::format_err!("Logins are handled by handle_login"));
+        }
+        SignallerMessage::Authenticate { token } => {
+            let response = match state.authenticate_connection(socket_addr, &token) {
+                Ok(account) => SignallerMessage::LoginResponse {
+                    token,
+                    username: account.username,
+                    role: account.role,
+                },
+                Err(e) => SignallerMessage::LoginFailed {
+                    reason: e.to_string(),
+                },
+            };
+            tx.unbounded_send(Message::Text(serde_json::to_string(&response)?))?;
+        }
+        SignallerMessage::Logout {} => {
+            state.logout_connection(&socket_addr);
+        }
+        SignallerMessage::CreateInvite {
+            room,
+            role,
+            ttl_secs,
+            max_uses,
+        } => {
+            if !state.is_room_server(&room, &socket_addr) {
+                return Err(failure::format_err!(
+                    "Only the room's host can create invites"
+                ));
+            }
+            let (claims, token) = state.create_invite(
+                &room,
+                role,
+                ttl_secs.map_or(DEFAULT_INVITE_TTL, Duration::from_secs),
+                max_uses.unwrap_or(1),
+            )?;
+            tx.unbounded_send(Message::Text(serde_json::to_string(
+                &SignallerMessage::InviteCreated {
+                    id: claims.id,
+                    room: claims.room,
+                    role: claims.role,
+                    token,
+                    expires_at: claims.exp,
+                },
+            )?))?;
+        }
+        SignallerMessage::RevokeInvite { id } => {
+            let room = state
+                .server_socket_addr_to_room
+                .get(&socket_addr)
+                .cloned()
+                .ok_or_else(|| failure::format_err!("Only a room's host can revoke invites"))?;
+            state.revoke_invite(&id, Some(&room))?;
+            tx.unbounded_send(Message::Text(serde_json::to_string(
+                &SignallerMessage::InviteRevoked { id },
+            )?))?;
+        }
+        SignallerMessage::RequestPairingCode { device_secret } => {
+            let code = state.request_pairing_code(&device_secret, tx.clone(), socket_addr)?;
+            tx.unbounded_send(Message::Text(serde_json::to_string(
+                &SignallerMessage::PairingCode {
+                    code,
+                    expires_in: PAIRING_CODE_TTL.as_secs(),
+                },
+            )?))?;
+        }
+        SignallerMessage::ClaimPairingCode { code, name } => {
+            let owner = state
+                .connection_account(&socket_addr)
+                .ok_or_else(|| failure::format_err!("Login required to pair a device"))?;
+            let device = state.claim_pairing_code(&code, &owner, name)?;
+            tx.unbounded_send(Message::Text(serde_json::to_string(
+                &SignallerMessage::DevicePaired {
+                    room: device.room,
+                    name: device.name,
+                },
+            )?))?;
+        }
+        SignallerMessage::RequestControl { from } => {
+            if !state.is_peer_connection(&from, tx) {
+                return Err(failure::format_err!(
+                    "Peer does not belong to this connection"
+                ));
+            }
+            match state.request_control(&from) {
+                // The host decides with GrantControl
+                Ok(room) => forward_message(state, room)?,
+                Err(e) => {
+                    tx.unbounded_send(Message::Text(serde_json::to_string(
+                        &SignallerMessage::ControlDenied {
+                            to: from,
+                            reason: e.to_string(),
+                        },
+                    )?))?;
+                }
+            }
+        }
+        SignallerMessage::GrantControl { to } => {
+            let room = state
+                .server_socket_addr_to_room
+                .get(&socket_addr)
+                .cloned()
+                .ok_or_else(|| failure::format_err!("Only a room's host can grant control"))?;
+            match state.grant_control(&room, &to) {
+                Ok(()) => state.audit(
+                    state.connection_ip(&socket_addr),
+                    AuditEvent::ControlGranted { room, viewer: to },
+                ),
+                Err(e) => {
+                    tx.unbounded_send(Message::Text(serde_json::to_string(
+                        &SignallerMessage::ControlDenied {
+                            to,
+                            reason: e.to_string(),
+                        },
+                    )?))?;
+                }
+            }
+        }
+        SignallerMessage::RevokeControl { to } => {
+            let room = state
+                .server_socket_addr_to_room
+                .get(&socket_addr)
+                .cloned()
+                .ok_or_else(|| failure::format_err!("Only a room's host can revoke control"))?;
+            state.revoke_control(&room, &to)?;
+            state.audit(
+                state.connection_ip(&socket_addr),
+                AuditEvent::ControlRevoked { room, viewer: to },
+            );
+        }
+        SignallerMessage::ReleaseControl { from } => {
+            if !state.is_peer_connection(&from, tx) {
+                return Err(failure::format_err!(
+                    "Peer does not belong to this connection"
+                ));
+            }
+            state.release_control(&from)?;
+        }
+        SignallerMessage::Kick { viewer, reason } => {
+            let room = state
+                .server_socket_addr_to_room
+                .get(&socket_addr)
+                .cloned()
+                .ok_or_else(|| failure::format_err!("Only a room's host can kick viewers"))?;
+            state.kick_viewer(&room, &viewer, reason.clone())?;
+            state.audit(
+                state.connection_ip(&socket_addr),
+                AuditEvent::Kick {
+                    room,
+                    viewer,
+                    reason,
+                },
+            );
+        }
+        SignallerMessage::Ban {
+            viewer_or_ip,
+            duration,
+        } => {
+            let room = state
+                .server_socket_addr_to_room
+                .get(&socket_addr)
+                .cloned()
+                .ok_or_else(|| failure::format_err!("Only a room's host can ban viewers"))?;
+            state.ban(&room, &viewer_or_ip, duration.map(Duration::from_secs))?;
+            state.audit(
+                state.connection_ip(&socket_addr),
+                AuditEvent::Ban {
+                    room,
+                    target: viewer_or_ip,
+                    duration_secs: duration,
+                },
+            );
+        }
+        SignallerMessage::UpdateRoom { changes } => {
+            let room = state
+                .server_socket_addr_to_room
+                .get(&socket_addr)
+                .cloned()
+                .ok_or_else(|| failure::format_err!("Only a room's host can update it"))?;
+            state.update_room(&room, changes)?;
+        }
         SignallerMessage::KeepAlive {} => {}
         SignallerMessage::RoomListResponse { .. }
-        | SignallerMessage::NewRoomNotification { .. } => {
+        | SignallerMessage::LoginResponse { .. }
+        | SignallerMessage::LoginFailed { .. }
+        | SignallerMessage::InviteCreated { .. }
+        | SignallerMessage::InviteRevoked { .. }
+        | SignallerMessage::PairingCode { .. }
+        | SignallerMessage::DevicePaired { .. }
+        | SignallerMessage::Paired { .. }
+        | SignallerMessage::ControlDenied { .. }
+        | SignallerMessage::ControlChanged { .. }
+        | SignallerMessage::RoomAdded { .. }
+        | SignallerMessage::RoomUpdated { .. }
+        | SignallerMessage::RoomRemoved { .. }
+        | SignallerMessage::ViewerCountChanged { .. }
+        | SignallerMessage::RoomSnapshot { .. }
+        | SignallerMessage::SearchResults { .. }
+        | SignallerMessage::Kicked { .. }
+        | SignallerMessage::Queued { .. } => {
             log::warn!("Received unexpected message: {:?}", msg);
         }
-        SignallerMessage::SubscribeRoomUpdates {} => {
-            if let Some(peer) = state.peers.values().find(|p| std::ptr::eq(&p.sender, tx)) {
-                let room = peer.room.clone();
-                state.subscribe_room_updates(room);
-            }
+        SignallerMessage::SubscribeRoomUpdates {
+            subscription,
+            since,
+            filter,
+        } => {
+            let account = state.connection_account(&socket_addr);
+            state.send_room_events_since(since, &filter, account.as_deref(), tx);
+            let name = subscription.unwrap_or_else(|| DEFAULT_SUBSCRIPTION.to_string());
+            state.subscribe_room_updates(connection, name, filter);
         }
-        SignallerMessage::UnsubscribeRoomUpdates {} => {
-            if let Some(peer) = state.peers.values().find(|p| std::ptr::eq(&p.sender, tx)) {
-                let room = peer.room.clone();
-                state.unsubscribe_room_updates(&room);
-            }
+        SignallerMessage::UnsubscribeRoomUpdates { subscription } => {
+            state.unsubscribe_room_updates(&connection, subscription.as_deref());
         }
         _ => {}
     }
     Ok(())
 }
 
+/// Log the connection in. Unlike `handle_message` this takes the state lock
+/// only to read and write the account, not while checking the password.
+pub async fn handle_login(
+    state: &StateType,
+    tx: &Tx,
+    raw_payload: &str,
+    socket_addr: SocketAddr,
+) -> Result<(), failure::Error> {
+    let (username, password, totp) = match serde_json::from_str(raw_payload)? {
+        SignallerMessage::Login {
+            username,
+            password,
+            totp,
+        } => (username, password, totp),
+        _ => return Err(failure::format_err!("Not a login message")),
+    };
+    {
+        let mut locked_state = lock_state(state).await;
+        locked_state.metrics.message_received(raw_payload);
+        locked_state.connection_id(socket_addr, tx);
+    }
+    let response = match auth::login(state, &username, &password, totp.as_deref()).await {
+        Ok(token) => {
+            let account = lock_state(state)
+                .await
+                .authenticate_connection(socket_addr, &token)?;
+            SignallerMessage::LoginResponse {
+                token,
+                username: account.username,
+                role: account.role,
+            }
+        }
+        Err(e) => SignallerMessage::LoginFailed {
+            reason: e.to_string(),
+        },
+    };
+    tx.unbounded_send(Message::Text(serde_json::to_string(&response)?))?;
+    Ok(())
+}
+
 pub async fn process_message(
     msg: Message,
     state: StateType,
@@ -166,8 +541,13 @@ pub async fn process_message(
     socket_addr: SocketAddr,
 ) -> Result<(), axum::Error> {
     if let Message::Text(text) = msg {
-        let mut locked_state = state.lock().await;
-        if let Err(e) = handle_message(&mut locked_state, tx, &text, socket_addr).await {
+        let result = if message_type(&text) == Some("login") {
+            handle_login(&state, tx, &text, socket_addr).await
+        } else {
+            let mut locked_state = lock_state(&state).await;
+            handle_message(&mut locked_state, tx, &text, socket_addr).await
+        };
+        if let Err(e) = result {
             info!(
                 "Error occurred when handling message: {}\nMessage: {}",
                 e, text
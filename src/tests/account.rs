@@ -0,0 +1,248 @@
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+};
+use tower::ServiceExt;
+
+use crate::{
+    args::Args,
+    models::{
+        account::{AccountRole, MAX_FAILED_LOGINS},
+        rtc::SignallerMessage,
+        state::State,
+    },
+    routes::router::create_router,
+    services::{
+        auth,
+        websocket::{handle_login, handle_message},
+    },
+};
+use clap::Parser;
+
+#[tokio::test]
+async fn test_register_account() {
+    let state = State::new();
+
+    // Registration never grants admin, not even to the first account
+    let first = auth::register_account(&state, "alice", "correct horse")
+        .await
+        .unwrap();
+    assert_eq!(first.role, AccountRole::User);
+    assert_ne!(first.password_hash, "correct horse");
+
+    let user = auth::register_account(&state, "bob", "battery staple")
+        .await
+        .unwrap();
+    assert_eq!(user.role, AccountRole::User);
+
+    assert!(auth::register_account(&state, "bob", "another pass")
+        .await
+        .is_err());
+    assert!(auth::register_account(&state, "carol", "short")
+        .await
+        .is_err());
+    assert!(auth::register_account(&state, "bad name", "long enough")
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_login_and_logout() {
+    let state = State::new();
+    auth::register_account(&state, "alice", "correct horse")
+        .await
+        .unwrap();
+
+    assert!(auth::login(&state, "alice", "wrong password", None)
+        .await
+        .is_err());
+    assert!(auth::login(&state, "nobody", "correct horse", None)
+        .await
+        .is_err());
+
+    let token = auth::login(&state, "alice", "correct horse", None)
+        .await
+        .unwrap();
+    let mut locked_state = state.lock().await;
+    let account = locked_state.authenticate_token(&token).unwrap();
+    assert_eq!(account.username, "alice");
+
+    locked_state.logout(&token);
+    assert!(locked_state.authenticate_token(&token).is_none());
+}
+
+#[tokio::test]
+async fn test_expired_tokens() {
+    let state = State::new();
+    let (tx, _rx) = futures_channel::mpsc::unbounded();
+    let socket_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+
+    auth::register_account(&state, "alice", "correct horse")
+        .await
+        .unwrap();
+    let token = auth::login(&state, "alice", "correct horse", None)
+        .await
+        .unwrap();
+    {
+        let mut locked_state = state.lock().await;
+        locked_state.register_connection(socket_addr, tx, None);
+        locked_state
+            .authenticate_connection(socket_addr, &token)
+            .unwrap();
+        assert_eq!(
+            locked_state.connection_account(&socket_addr).as_deref(),
+            Some("alice")
+        );
+
+        locked_state.auth_tokens.get_mut(&token).unwrap().expires_at = SystemTime::now();
+        assert!(locked_state.connection_account(&socket_addr).is_none());
+    }
+
+    // Logging in again drops the expired token
+    auth::login(&state, "alice", "correct horse", None)
+        .await
+        .unwrap();
+    let locked_state = state.lock().await;
+    assert!(!locked_state.auth_tokens.contains_key(&token));
+    assert_eq!(locked_state.auth_tokens.len(), 1);
+}
+
+#[tokio::test]
+async fn test_login_lockout() {
+    let state = State::new();
+    auth::register_account(&state, "alice", "correct horse")
+        .await
+        .unwrap();
+
+    for _ in 0..MAX_FAILED_LOGINS {
+        assert!(auth::login(&state, "alice", "wrong password", None)
+            .await
+            .is_err());
+    }
+    let err = auth::login(&state, "alice", "correct horse", None)
+        .await
+        .unwrap_err();
+    // Indistinguishable from an unknown username
+    assert_eq!(err.to_string(), "Invalid username or password");
+    let err = auth::login(&state, "nobody", "correct horse", None)
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Invalid username or password");
+}
+
+#[tokio::test]
+async fn test_handle_login_binds_peer_account() {
+    let state = State::new();
+    let (tx, mut rx) = futures_channel::mpsc::unbounded();
+    let socket_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+
+    auth::register_account(&state, "alice", "correct horse")
+        .await
+        .unwrap();
+
+    let login = SignallerMessage::Login {
+        username: "alice".to_string(),
+        password: "correct horse".to_string(),
+        totp: None,
+    };
+    handle_login(
+        &state,
+        &tx,
+        &serde_json::to_string(&login).unwrap(),
+        socket_addr,
+    )
+    .await
+    .unwrap();
+
+    let response = rx.try_next().unwrap().unwrap();
+    let response: SignallerMessage = serde_json::from_str(response.to_text().unwrap()).unwrap();
+    assert!(matches!(response, SignallerMessage::LoginResponse { .. }));
+
+    let mut locked_state = state.lock().await;
+    let start = SignallerMessage::Start {
+        room: "test_room".to_string(),
+        name: "test_name".to_string(),
+        os: "test_os".to_string(),
+        version: "1.0".to_string(),
+        control: true,
+        device_secret: None,
+        max_viewers: None,
+        visibility: None,
+        tags: Default::default(),
+    };
+    handle_message(
+        &mut locked_state,
+        &tx,
+        &serde_json::to_string(&start).unwrap(),
+        socket_addr,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        locked_state.peers["test_room"].account.as_deref(),
+        Some("alice")
+    );
+}
+
+#[tokio::test]
+async fn test_rest_register_and_login() {
+    let app = create_router(State::new(), Args::parse_from(["program"]));
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/auth/register")
+                .header("content-type", "application/json")
+                .extension(axum::extract::ConnectInfo(SocketAddr::from((
+                    [127, 0, 0, 1],
+                    8080,
+                ))))
+                .body(Body::from(
+                    r#"{"username":"alice","password":"correct horse"}"#,
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/auth/login")
+                .header("content-type", "application/json")
+                .extension(axum::extract::ConnectInfo(SocketAddr::from((
+                    [127, 0, 0, 1],
+                    8080,
+                ))))
+                .body(Body::from(
+                    r#"{"username":"alice","password":"correct horse"}"#,
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let token = body["token"].as_str().unwrap();
+
+    let response = app
+        .oneshot(
+            Request::get("/auth/me")
+                .header("authorization", format!("Bearer {}", token))
+                .extension(axum::extract::ConnectInfo(SocketAddr::from((
+                    [127, 0, 0, 1],
+                    8080,
+                ))))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
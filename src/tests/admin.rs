@@ -0,0 +1,377 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::ws::Message,
+    http::{Request, StatusCode},
+    Router,
+};
+use futures_channel::mpsc::{unbounded, UnboundedReceiver};
+use tower::ServiceExt;
+
+use crate::{
+    args::Args,
+    models::{
+        account::AccountRole,
+        audit::{AuditLog, Rotation, AUDIT_FILE},
+        history::{session_history, EndReason},
+        peer::ViewerRole,
+        rtc::SignallerMessage,
+        state::{State, StateType},
+    },
+    routes::router::create_router,
+};
+use clap::Parser;
+
+use super::{drain, sign_in};
+
+struct Fixture {
+    state: StateType,
+    app: Router,
+    admin_token: String,
+    user_token: String,
+    viewer_rx: UnboundedReceiver<Message>,
+}
+
+/// kiosk1 hosted from one connection, with viewer v1 on another.
+async fn fixture() -> Fixture {
+    let state = State::new();
+    let (admin_token, user_token, viewer_rx) = {
+        let mut locked_state = state.lock().await;
+        let admin_token = sign_in(&mut locked_state, "admin", AccountRole::Admin);
+        let user_token = sign_in(&mut locked_state, "bob", AccountRole::User);
+
+        let host = SocketAddr::from(([127, 0, 0, 1], 9001));
+        let (host_tx, _host_rx) = unbounded();
+        locked_state.register_connection(
+            host,
+            host_tx.clone(),
+            Some("203.0.113.1".parse().unwrap()),
+        );
+        locked_state
+            .add_server(
+                "kiosk1".to_string(),
+                "kiosk1 host".to_string(),
+                "linux".to_string(),
+                "1.0".to_string(),
+                true,
+                host_tx,
+                host,
+            )
+            .unwrap();
+
+        let viewer = SocketAddr::from(([127, 0, 0, 1], 9002));
+        let (tx, viewer_rx) = unbounded();
+        locked_state.register_connection(viewer, tx.clone(), Some("198.51.100.1".parse().unwrap()));
+        locked_state
+            .add_viewer_with_role(
+                "v1".to_string(),
+                "kiosk1".to_string(),
+                tx,
+                ViewerRole::Control,
+                Some("198.51.100.1".parse().unwrap()),
+            )
+            .unwrap();
+        (admin_token, user_token, viewer_rx)
+    };
+    let app = create_router(state.clone(), Args::parse_from(["program"]));
+    Fixture {
+        state,
+        app,
+        admin_token,
+        user_token,
+        viewer_rx,
+    }
+}
+
+fn request(method: &str, uri: &str, token: Option<&str>, body: Option<&str>) -> Request<Body> {
+    let mut request =
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .extension(axum::extract::ConnectInfo(SocketAddr::from((
+                [127, 0, 0, 1],
+                8080,
+            ))));
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+    match body {
+        Some(body) => request
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap(),
+        None => request.body(Body::empty()).unwrap(),
+    }
+}
+
+async fn call(app: &Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body = serde_json::from_slice(&body).unwrap_or_default();
+    (status, body)
+}
+
+#[tokio::test]
+async fn test_admin_api_requires_admin() {
+    let fixture = fixture().await;
+    let get = |token: Option<&str>| request("GET", "/admin/sessions", token, None);
+
+    let (status, _) = call(&fixture.app, get(None)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = call(&fixture.app, get(Some(&fixture.user_token))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(&fixture.app, get(Some("not a token"))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = call(&fixture.app, get(Some(&fixture.admin_token))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The static token works once configured
+    fixture.state.lock().await.admin_token = Some("s3cret".to_string());
+    let (status, _) = call(&fixture.app, get(Some("s3cret"))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&fixture.app, get(Some("s3cre"))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Every route sits behind the same check, before its own extractors
+    for (method, uri) in [
+        ("GET", "/admin/connections"),
+        ("GET", "/admin/peers"),
+        ("DELETE", "/admin/sessions/kiosk1"),
+        ("POST", "/admin/sessions/kiosk1/viewers/v1/kick"),
+        ("POST", "/admin/peers/v1/messages"),
+    ] {
+        let (status, _) = call(
+            &fixture.app,
+            request(method, uri, Some(&fixture.user_token), None),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", method, uri);
+    }
+    assert_eq!(fixture.state.lock().await.sessions.len(), 1);
+}
+
+#[tokio::test]
+async fn test_set_account_role() {
+    let fixture = fixture().await;
+    let set_role = |token: Option<&str>, username: &str, role: &str| {
+        request(
+            "PUT",
+            &format!("/auth/accounts/{}/role", username),
+            token,
+            Some(&format!(r#"{{"role":"{}"}}"#, role)),
+        )
+    };
+
+    let (status, _) = call(&fixture.app, set_role(None, "bob", "admin")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = call(
+        &fixture.app,
+        set_role(Some(&fixture.user_token), "bob", "admin"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // ADMIN_TOKEN appoints the first admin of a deployment
+    fixture.state.lock().await.admin_token = Some("s3cret".to_string());
+    let (status, body) = call(&fixture.app, set_role(Some("s3cret"), "bob", "admin")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["role"], "admin");
+    let (status, _) = call(
+        &fixture.app,
+        request("GET", "/admin/sessions", Some(&fixture.user_token), None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = call(
+        &fixture.app,
+        set_role(Some(&fixture.admin_token), "bob", "user"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(
+        &fixture.app,
+        set_role(Some(&fixture.admin_token), "nobody", "admin"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_admin_lists_live_state() {
+    let fixture = fixture().await;
+    let token = Some(fixture.admin_token.as_str());
+
+    let (_, body) = call(&fixture.app, request("GET", "/admin/sessions", token, None)).await;
+    let session = &body["sessions"][0];
+    assert_eq!(session["room"], "kiosk1");
+    assert_eq!(session["server_socket_addr"], "127.0.0.1:9001");
+    assert_eq!(session["server_ip"], "203.0.113.1");
+    assert_eq!(session["viewers"], serde_json::json!(["v1"]));
+
+    let (_, body) = call(&fixture.app, request("GET", "/admin/peers", token, None)).await;
+    let peers = body["peers"].as_array().unwrap();
+    assert_eq!(peers.len(), 2);
+    assert_eq!(peers[0]["id"], "kiosk1");
+    assert_eq!(peers[0]["type"], "server");
+    assert_eq!(peers[0]["connection"], "conn-1");
+    assert_eq!(peers[1]["id"], "v1");
+    assert_eq!(peers[1]["role"], "control");
+    assert_eq!(peers[1]["ip"], "198.51.100.1");
+    assert_eq!(peers[1]["connection"], "conn-2");
+
+    let (_, body) = call(
+        &fixture.app,
+        request("GET", "/admin/connections", token, None),
+    )
+    .await;
+    let connections = body["connections"].as_array().unwrap();
+    assert_eq!(connections.len(), 2);
+    assert_eq!(connections[1]["id"], "conn-2");
+    assert_eq!(connections[1]["peers"], serde_json::json!(["v1"]));
+}
+
+#[tokio::test]
+async fn test_admin_actions() {
+    let dir = std::env::temp_dir().join(format!("remo-admin-audit-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let mut fixture = fixture().await;
+    fixture.state.lock().await.audit_log = Some(
+        AuditLog::open(
+            &dir,
+            Rotation {
+                max_bytes: u64::MAX,
+                max_age: Duration::from_secs(86400),
+            },
+        )
+        .unwrap(),
+    );
+    let token = Some(fixture.admin_token.as_str());
+
+    // Reading live state is logged too
+    for uri in ["/admin/sessions", "/admin/connections", "/admin/peers"] {
+        let (status, _) = call(&fixture.app, request("GET", uri, token, None)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    // Messages must be valid signalling messages
+    let (status, _) = call(
+        &fixture.app,
+        request(
+            "POST",
+            "/admin/peers/v1/messages",
+            token,
+            Some(r#"{"type": "nonsense"}"#),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let keep_alive = r#"{"type": "keep_alive"}"#;
+    let (status, _) = call(
+        &fixture.app,
+        request(
+            "POST",
+            "/admin/peers/nobody/messages",
+            token,
+            Some(keep_alive),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(
+        &fixture.app,
+        request("POST", "/admin/peers/v1/messages", token, Some(keep_alive)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(matches!(
+        &drain(&mut fixture.viewer_rx)[..],
+        [SignallerMessage::KeepAlive {}]
+    ));
+
+    let (status, _) = call(
+        &fixture.app,
+        request(
+            "POST",
+            "/admin/sessions/kiosk1/viewers/v1/kick",
+            token,
+            Some(r#"{"reason": "maintenance"}"#),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(matches!(
+        &drain(&mut fixture.viewer_rx)[..],
+        [SignallerMessage::Kicked { reason: Some(reason), .. }] if reason == "maintenance"
+    ));
+
+    // Closing tells the remaining viewers
+    let (tx, mut rx) = unbounded();
+    fixture
+        .state
+        .lock()
+        .await
+        .add_viewer_with_role(
+            "v2".to_string(),
+            "kiosk1".to_string(),
+            tx,
+            ViewerRole::ViewOnly,
+            None,
+        )
+        .unwrap();
+    let (status, _) = call(
+        &fixture.app,
+        request("DELETE", "/admin/sessions/kiosk1", token, None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(matches!(
+        &drain(&mut rx)[..],
+        [SignallerMessage::ServerClosed { to, room }] if to == "v2" && room == "kiosk1"
+    ));
+    let (status, _) = call(
+        &fixture.app,
+        request("DELETE", "/admin/sessions/kiosk1", token, None),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    {
+        let locked_state = fixture.state.lock().await;
+        assert!(locked_state.sessions.is_empty());
+        let history = session_history(
+            &locked_state.store,
+            &Default::default(),
+            &locked_state.caller_for(Some("admin")),
+        )
+        .unwrap();
+        assert_eq!(history[0].end_reason, EndReason::Closed);
+    }
+
+    let actions: Vec<String> = fs::read_to_string(dir.join(AUDIT_FILE))
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .filter(|entry| entry["event"] == "admin_action")
+        .map(|entry| {
+            assert_eq!(entry["account"], "admin");
+            assert_eq!(entry["ip"], "127.0.0.1");
+            entry["action"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            "list_sessions",
+            "list_connections",
+            "list_peers",
+            "send_message",
+            "kick_viewer",
+            "close_room"
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -5,6 +5,9 @@ use clap::Parser;
 fn test_default_args() {
     let args = Args::parse_from(["program"]);
     assert_eq!(args.address, "0.0.0.0:8080");
+    assert_eq!(args.data_dir, "data");
+    assert_eq!(args.audit_dir, None);
+    assert_eq!(args.audit_max_bytes, 10 * 1024 * 1024);
 }
 
 #[test]
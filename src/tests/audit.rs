@@ -0,0 +1,170 @@
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use futures_channel::mpsc::unbounded;
+
+use crate::{
+    models::{
+        audit::{audit_files, verify_audit_log, AuditEvent, AuditLog, Rotation, AUDIT_FILE},
+        history::EndReason,
+        rtc::SignallerMessage,
+        state::State,
+    },
+    services::websocket::handle_message,
+};
+
+/// A fresh directory under the system temp dir.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("remo-audit-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn unlimited() -> Rotation {
+    Rotation {
+        max_bytes: u64::MAX,
+        max_age: Duration::from_secs(86400),
+    }
+}
+
+fn stopped(room: &str) -> AuditEvent {
+    AuditEvent::RoomStopped {
+        room: room.to_string(),
+        reason: EndReason::HostLeft,
+    }
+}
+
+fn lines(dir: &Path) -> Vec<serde_json::Value> {
+    audit_files(dir)
+        .unwrap()
+        .iter()
+        .flat_map(|path| {
+            fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[test]
+fn test_audit_chain_verifies() {
+    let dir = temp_dir("chain");
+    let ip: IpAddr = "203.0.113.7".parse().unwrap();
+    {
+        let mut log = AuditLog::open(&dir, unlimited()).unwrap();
+        log.append(Some(ip), &stopped("kiosk1")).unwrap();
+        log.append(
+            None,
+            &AuditEvent::admin_action("admin", "delete_group", "lobby"),
+        )
+        .unwrap();
+    }
+    // Reopening continues the chain
+    let mut log = AuditLog::open(&dir, unlimited()).unwrap();
+    log.append(None, &stopped("kiosk2")).unwrap();
+
+    let summary = verify_audit_log(&dir).unwrap();
+    assert_eq!(summary.entries, 3);
+    assert_eq!(summary.first_seq, Some(0));
+    let entries = lines(&dir);
+    assert_eq!(entries[0]["event"], "room_stopped");
+    assert_eq!(entries[0]["ip"], "203.0.113.7");
+    assert_eq!(entries[1]["action"], "delete_group");
+    assert_eq!(entries[2]["seq"], 2);
+    assert_eq!(entries[2]["prev_hash"], entries[1]["hash"]);
+    assert_eq!(summary.last_hash, entries[2]["hash"].as_str().unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_audit_tampering_is_detected() {
+    let dir = temp_dir("tamper");
+    let mut log = AuditLog::open(&dir, unlimited()).unwrap();
+    for room in ["kiosk1", "kiosk2", "kiosk3"] {
+        log.append(None, &stopped(room)).unwrap();
+    }
+    let path = dir.join(AUDIT_FILE);
+    let original = fs::read_to_string(&path).unwrap();
+
+    // A changed entry
+    fs::write(&path, original.replacen("kiosk2", "kiosk9", 1)).unwrap();
+    let error = verify_audit_log(&dir).unwrap_err().to_string();
+    assert!(error.contains("entry 1 was modified"), "{}", error);
+
+    // A removed entry
+    let mut kept: Vec<&str> = original.lines().collect();
+    kept.remove(1);
+    fs::write(&path, kept.join("\n")).unwrap();
+    let error = verify_audit_log(&dir).unwrap_err().to_string();
+    assert!(error.contains("entry 2 breaks the chain"), "{}", error);
+
+    // A truncated start
+    let kept: Vec<&str> = original.lines().skip(1).collect();
+    fs::write(&path, kept.join("\n")).unwrap();
+    assert_eq!(verify_audit_log(&dir).unwrap().first_seq, Some(1));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_audit_rotation_keeps_the_chain() {
+    let dir = temp_dir("rotation");
+    let rotation = Rotation {
+        max_bytes: 1,
+        max_age: Duration::from_secs(86400),
+    };
+    let mut log = AuditLog::open(&dir, rotation.clone()).unwrap();
+    for room in ["kiosk1", "kiosk2", "kiosk3"] {
+        log.append(None, &stopped(room)).unwrap();
+    }
+    drop(log);
+    let mut log = AuditLog::open(&dir, rotation).unwrap();
+    log.append(None, &stopped("kiosk4")).unwrap();
+
+    // One entry per file
+    assert_eq!(audit_files(&dir).unwrap().len(), 4);
+    assert_eq!(verify_audit_log(&dir).unwrap().entries, 4);
+
+    // Deleting the oldest file is fine, removing one in between is not
+    let files = audit_files(&dir).unwrap();
+    fs::remove_file(&files[0]).unwrap();
+    assert_eq!(verify_audit_log(&dir).unwrap().first_seq, Some(1));
+    fs::remove_file(&files[2]).unwrap();
+    assert!(verify_audit_log(&dir).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_declined_join_is_audited() {
+    let dir = temp_dir("join");
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    locked_state.audit_log = Some(AuditLog::open(&dir, unlimited()).unwrap());
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 9001));
+    let (tx, _rx) = unbounded();
+    locked_state.register_connection(addr, tx.clone(), Some("198.51.100.9".parse().unwrap()));
+    let join: SignallerMessage =
+        serde_json::from_str(r#"{"type": "join", "room": "missing", "from": "v1"}"#).unwrap();
+    let _ = handle_message(
+        &mut locked_state,
+        &tx,
+        &serde_json::to_string(&join).unwrap(),
+        addr,
+    )
+    .await;
+
+    let entries = lines(&dir);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["event"], "join_attempt");
+    assert_eq!(entries[0]["room"], "missing");
+    assert_eq!(entries[0]["viewer"], "v1");
+    assert_eq!(entries[0]["outcome"], "declined");
+    assert_eq!(entries[0]["invite"], false);
+    assert_eq!(entries[0]["ip"], "198.51.100.9");
+    assert!(entries[0]["reason"].is_string());
+    fs::remove_dir_all(&dir).unwrap();
+}
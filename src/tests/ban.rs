@@ -0,0 +1,243 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use axum::extract::ws::Message;
+use futures_channel::mpsc::{unbounded, UnboundedReceiver};
+
+use crate::{
+    models::{peer::ViewerRole, rtc::SignallerMessage, state::State},
+    services::websocket::handle_message,
+};
+
+use super::{next_message, start_room};
+
+fn setup(locked_state: &mut State) -> UnboundedReceiver<Message> {
+    let (host_tx, host_rx) = unbounded();
+    start_room(
+        locked_state,
+        "test_room",
+        &host_tx,
+        SocketAddr::from(([127, 0, 0, 1], 8080)),
+    );
+    host_rx
+}
+
+#[tokio::test]
+async fn test_kick_viewer() {
+    let state = State::new();
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let viewer_addr = SocketAddr::from(([127, 0, 0, 1], 8081));
+    let (host_tx, _) = unbounded();
+    let (viewer_tx, mut viewer_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    let _host_rx = setup(&mut locked_state);
+    locked_state
+        .add_viewer(
+            "viewer1".to_string(),
+            "test_room".to_string(),
+            viewer_tx.clone(),
+        )
+        .unwrap();
+
+    let kick = serde_json::to_string(&SignallerMessage::Kick {
+        viewer: "viewer1".to_string(),
+        reason: Some("Session over".to_string()),
+    })
+    .unwrap();
+
+    // Viewers can't kick
+    assert!(
+        handle_message(&mut locked_state, &viewer_tx, &kick, viewer_addr)
+            .await
+            .is_err()
+    );
+    handle_message(&mut locked_state, &host_tx, &kick, host_addr)
+        .await
+        .unwrap();
+
+    match next_message(&mut viewer_rx) {
+        SignallerMessage::Kicked { to, room, reason } => {
+            assert_eq!(to, "viewer1");
+            assert_eq!(room, "test_room");
+            assert_eq!(reason.as_deref(), Some("Session over"));
+        }
+        other => panic!("Unexpected message: {:?}", other),
+    }
+    assert!(locked_state.sessions["test_room"].viewers.is_empty());
+    assert!(!locked_state.peers.contains_key("viewer1"));
+
+    // Kicking a viewer that isn't there fails
+    assert!(
+        handle_message(&mut locked_state, &host_tx, &kick, host_addr)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_leave_only_for_own_peers() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let _host_rx = setup(&mut locked_state);
+    let (viewer_tx, _viewer_rx) = unbounded();
+    locked_state
+        .add_viewer(
+            "viewer1".to_string(),
+            "test_room".to_string(),
+            viewer_tx.clone(),
+        )
+        .unwrap();
+    let intruder_addr = SocketAddr::from(([127, 0, 0, 1], 8082));
+    let (intruder_tx, _intruder_rx) = unbounded();
+    locked_state.register_connection(intruder_addr, intruder_tx.clone(), None);
+
+    let leave = |from: &str| {
+        serde_json::to_string(&SignallerMessage::Leave {
+            from: from.to_string(),
+        })
+        .unwrap()
+    };
+    // Neither someone else's room nor someone else's viewer
+    for from in ["test_room", "viewer1"] {
+        assert!(
+            handle_message(&mut locked_state, &intruder_tx, &leave(from), intruder_addr)
+                .await
+                .is_err()
+        );
+    }
+    assert!(locked_state.sessions.contains_key("test_room"));
+    assert!(locked_state.peers.contains_key("viewer1"));
+
+    // The viewer itself can
+    let viewer_addr = SocketAddr::from(([127, 0, 0, 1], 8081));
+    handle_message(
+        &mut locked_state,
+        &viewer_tx,
+        &leave("viewer1"),
+        viewer_addr,
+    )
+    .await
+    .unwrap();
+    assert!(!locked_state.peers.contains_key("viewer1"));
+}
+
+#[tokio::test]
+async fn test_ban_peer_also_bans_its_ip() {
+    let state = State::new();
+    let viewer_ip: IpAddr = "203.0.113.7".parse().unwrap();
+    let (viewer_tx, mut viewer_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    let _host_rx = setup(&mut locked_state);
+    locked_state
+        .add_viewer_with_role(
+            "viewer1".to_string(),
+            "test_room".to_string(),
+            viewer_tx.clone(),
+            ViewerRole::Control,
+            Some(viewer_ip),
+        )
+        .unwrap();
+
+    locked_state
+        .ban("test_room", "viewer1", Some(Duration::from_secs(600)))
+        .unwrap();
+    assert!(matches!(
+        next_message(&mut viewer_rx),
+        SignallerMessage::Kicked { .. }
+    ));
+    assert!(locked_state.sessions["test_room"].viewers.is_empty());
+
+    // Same ID from elsewhere, or a new ID from the same IP, stays out
+    assert!(locked_state
+        .add_viewer(
+            "viewer1".to_string(),
+            "test_room".to_string(),
+            viewer_tx.clone()
+        )
+        .is_err());
+    assert!(locked_state
+        .add_viewer_with_role(
+            "viewer2".to_string(),
+            "test_room".to_string(),
+            viewer_tx.clone(),
+            ViewerRole::Control,
+            Some(viewer_ip),
+        )
+        .is_err());
+    assert!(locked_state
+        .add_viewer("viewer2".to_string(), "test_room".to_string(), viewer_tx)
+        .is_ok());
+}
+
+#[tokio::test]
+async fn test_ban_ip_through_handle_message() {
+    let state = State::new();
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let viewer_addr = SocketAddr::from(([10, 0, 0, 5], 9000));
+    let (host_tx, _) = unbounded();
+    let (viewer_tx, mut viewer_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    let _host_rx = setup(&mut locked_state);
+    locked_state.register_connection(
+        viewer_addr,
+        viewer_tx.clone(),
+        Some("198.51.100.2".parse().unwrap()),
+    );
+
+    let join = serde_json::to_string(&SignallerMessage::Join {
+        from: "viewer1".to_string(),
+        room: "test_room".to_string(),
+        invite: None,
+        client: Default::default(),
+    })
+    .unwrap();
+    handle_message(&mut locked_state, &viewer_tx, &join, viewer_addr)
+        .await
+        .unwrap();
+    assert_eq!(locked_state.sessions["test_room"].viewers.len(), 1);
+
+    let ban = SignallerMessage::Ban {
+        viewer_or_ip: "198.51.100.2".to_string(),
+        duration: None,
+    };
+    handle_message(
+        &mut locked_state,
+        &host_tx,
+        &serde_json::to_string(&ban).unwrap(),
+        host_addr,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        next_message(&mut viewer_rx),
+        SignallerMessage::Kicked { .. }
+    ));
+
+    handle_message(&mut locked_state, &viewer_tx, &join, viewer_addr)
+        .await
+        .unwrap();
+    match next_message(&mut viewer_rx) {
+        SignallerMessage::JoinDeclined { reason, .. } => {
+            assert_eq!(reason, "You are banned from this room")
+        }
+        other => panic!("Unexpected message: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_ban_expires() {
+    let state = State::new();
+    let (viewer_tx, _viewer_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    let _host_rx = setup(&mut locked_state);
+    locked_state
+        .ban("test_room", "viewer1", Some(Duration::from_secs(0)))
+        .unwrap();
+    assert!(locked_state
+        .add_viewer("viewer1".to_string(), "test_room".to_string(), viewer_tx)
+        .is_ok());
+}
@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use futures_channel::mpsc::unbounded;
+
+use crate::models::{
+    filter::{AnyOf, RoomFilter},
+    group::DeviceGroup,
+    listing::{RoomQuery, SortKey, SortOrder},
+    room::{RoomChanges, Visibility},
+    selector::TagSelector,
+    state::State,
+};
+
+const OSES: [&str; 3] = ["Windows", "linux", "macOS"];
+const SITES: [&str; 4] = ["lab", "office", "warehouse", "home"];
+
+fn populate(state: &mut State, count: usize) {
+    for index in 0..count {
+        let room = format!("room{:03}", index);
+        let (tx, _rx) = unbounded();
+        state
+            .add_server(
+                room.clone(),
+                format!("Host-{}-{}", SITES[index % 4], index),
+                OSES[index % 3].to_string(),
+                format!("1.{}", index % 5),
+                index % 2 == 0,
+                tx,
+                SocketAddr::from(([10, 0, (index / 250) as u8, (index % 250) as u8], 9000)),
+            )
+            .unwrap();
+        let site = BTreeMap::from([("site".to_string(), SITES[index % 4].to_string())]);
+        state
+            .update_room(
+                &room,
+                RoomChanges {
+                    tags: Some(site),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let session = state.sessions.get_mut(&room).unwrap();
+        if index % 7 == 0 {
+            session.owner = Some("alice".to_string());
+        }
+        if index % 5 == 0 {
+            session.visibility = if index % 10 == 0 {
+                Visibility::OwnerOnly
+            } else {
+                Visibility::Unlisted
+            };
+        }
+        state.index_room(&room);
+    }
+}
+
+fn group(name: &str, parent: Option<&str>, rooms: impl Iterator<Item = usize>) -> DeviceGroup {
+    DeviceGroup {
+        name: name.to_string(),
+        parent: parent.map(str::to_string),
+        rooms: rooms.map(|index| format!("room{:03}", index)).collect(),
+        description: String::new(),
+    }
+}
+
+/// Every room `query` lists to `account`, following cursors across pages.
+fn list_all(state: &State, query: &RoomQuery, account: Option<&str>) -> (Vec<String>, usize) {
+    let mut query = query.clone();
+    query.per_page = Some(7);
+    let mut rooms = vec![];
+    loop {
+        let page = state.get_available_rooms(&query, account).unwrap();
+        rooms.extend(page.rooms.into_iter().map(|info| info.room));
+        match page.next_cursor {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => return (rooms, page.total_count),
+        }
+    }
+}
+
+/// The same listing computed by scanning every session.
+fn scan(state: &State, query: &RoomQuery, account: Option<&str>) -> Vec<String> {
+    let mut rooms: Vec<_> = state
+        .sessions
+        .iter()
+        .filter(|(_, session)| query.owned != Some(true) || session.owner.as_deref() == account)
+        .filter(|(_, session)| {
+            session
+                .visibility
+                .is_listed_for(session.owner.as_deref(), account)
+        })
+        .filter(|(room, _)| query.filter.matches(&state.room_info(room).unwrap()))
+        .map(|(room, session)| {
+            let value = crate::models::listing::sort_value(session, query.sort_by());
+            (value, room.clone())
+        })
+        .collect();
+    rooms.sort();
+    if query.sort() == SortOrder::Desc {
+        rooms.reverse();
+    }
+    rooms.into_iter().map(|(_, room)| room).collect()
+}
+
+fn queries() -> Vec<RoomQuery> {
+    let text = |values: &[&str]| AnyOf(values.iter().map(|value| value.to_string()).collect());
+    let filters = [
+        RoomFilter::default(),
+        RoomFilter {
+            os: text(&["windows", "MACOS"]),
+            ..Default::default()
+        },
+        RoomFilter {
+            version: text(&["1.3"]),
+            control: Some(true),
+            ..Default::default()
+        },
+        RoomFilter {
+            name: text(&["lab-1"]),
+            ..Default::default()
+        },
+        RoomFilter {
+            name: text(&["-9", "house"]),
+            os: text(&["linux"]),
+            ..Default::default()
+        },
+        RoomFilter {
+            server: text(&["ROOM042"]),
+            ..Default::default()
+        },
+        RoomFilter {
+            tags: BTreeMap::from([("site".to_string(), "office".to_string())]),
+            control: Some(false),
+            ..Default::default()
+        },
+        RoomFilter {
+            tags: BTreeMap::from([("site".to_string(), String::new())]),
+            name: text(&["nothing-like-this"]),
+            ..Default::default()
+        },
+        RoomFilter {
+            group: text(&["EMEA"]),
+            ..Default::default()
+        },
+        RoomFilter {
+            group: text(&["berlin", "paris"]),
+            os: text(&["linux"]),
+            ..Default::default()
+        },
+        RoomFilter {
+            selector: Some(TagSelector::parse("site in (lab, home)").unwrap()),
+            ..Default::default()
+        },
+        RoomFilter {
+            selector: Some(TagSelector::parse("site!=office,!retired").unwrap()),
+            group: text(&["emea"]),
+            ..Default::default()
+        },
+    ];
+    let sorts = [
+        (SortKey::Name, SortOrder::Asc),
+        (SortKey::ViewerCount, SortOrder::Desc),
+        (SortKey::Os, SortOrder::Desc),
+        (SortKey::StartTime, SortOrder::Desc),
+    ];
+    filters
+        .iter()
+        .flat_map(|filter| {
+            sorts.iter().map(|(sort_by, sort)| RoomQuery {
+                filter: filter.clone(),
+                sort_by: Some(*sort_by),
+                sort: Some(*sort),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+fn assert_consistent(state: &State) {
+    for mut query in queries() {
+        for (account, owned) in [
+            (None, None),
+            (Some("alice"), None),
+            (Some("alice"), Some(true)),
+        ] {
+            query.owned = owned;
+            let expected = scan(state, &query, account);
+            let (listed, total_count) = list_all(state, &query, account);
+            assert_eq!(listed, expected, "{:?} listed to {:?}", query, account);
+            assert_eq!(total_count, expected.len());
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_catalog_matches_a_full_scan() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    populate(&mut locked_state, 300);
+    for group in [
+        group("emea", None, std::iter::empty()),
+        group("berlin", Some("emea"), (0..300).step_by(3)),
+        group("paris", Some("emea"), (0..300).step_by(8)),
+    ] {
+        locked_state.put_group(group).unwrap();
+    }
+    assert_consistent(&locked_state);
+
+    // Keep the indexes in sync as rooms change
+    for index in (0..300).step_by(11) {
+        let (tx, _rx) = unbounded();
+        locked_state
+            .add_viewer(format!("viewer{}", index), format!("room{:03}", index), tx)
+            .unwrap();
+    }
+    for index in (0..300).step_by(13) {
+        locked_state
+            .update_room(
+                &format!("room{:03}", index),
+                RoomChanges {
+                    name: Some(format!("Renamed lab-{}", index)),
+                    control: Some(index % 2 == 1),
+                    tags: Some(BTreeMap::new()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+    }
+    for index in (0..300).step_by(17) {
+        locked_state
+            .leave_session(format!("room{:03}", index))
+            .unwrap();
+    }
+    locked_state.leave_session("viewer22".to_string()).unwrap();
+    locked_state
+        .put_group(group("paris", Some("emea"), (0..300).step_by(9)))
+        .unwrap();
+    assert_consistent(&locked_state);
+    assert_eq!(locked_state.catalog.len(), locked_state.sessions.len());
+}
@@ -0,0 +1,130 @@
+use std::net::SocketAddr;
+
+use futures_channel::mpsc::unbounded;
+
+use crate::models::{rtc::SignallerMessage, state::State};
+
+use super::{drain, send};
+
+fn start(max_viewers: Option<usize>) -> SignallerMessage {
+    SignallerMessage::Start {
+        room: "test_room".to_string(),
+        name: "test_name".to_string(),
+        os: "test_os".to_string(),
+        version: "1.0".to_string(),
+        control: true,
+        device_secret: None,
+        max_viewers,
+        visibility: None,
+        tags: Default::default(),
+    }
+}
+
+fn join(from: &str) -> SignallerMessage {
+    SignallerMessage::Join {
+        from: from.to_string(),
+        room: "test_room".to_string(),
+        invite: None,
+        client: Default::default(),
+    }
+}
+
+#[tokio::test]
+async fn test_lobby_connection_subscribes_without_a_peer() {
+    let state = State::new();
+    let lobby_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8081));
+    let (lobby_tx, mut lobby_rx) = unbounded();
+    let (host_tx, _host_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    let lobby = locked_state.register_connection(lobby_addr, lobby_tx.clone(), None);
+    let host = locked_state.register_connection(host_addr, host_tx.clone(), None);
+    assert_ne!(lobby, host);
+
+    let subscribe = SignallerMessage::SubscribeRoomUpdates {
+        subscription: None,
+        since: None,
+        filter: Default::default(),
+    };
+    send(&mut locked_state, &lobby_tx, subscribe, lobby_addr)
+        .await
+        .unwrap();
+    assert!(locked_state.room_update_subscribers.contains_key(&lobby));
+    assert!(locked_state.peers.is_empty());
+    assert!(matches!(
+        &drain(&mut lobby_rx)[..],
+        [SignallerMessage::RoomSnapshot { rooms, .. }] if rooms.is_empty()
+    ));
+
+    send(&mut locked_state, &host_tx, start(None), host_addr)
+        .await
+        .unwrap();
+    assert!(drain(&mut lobby_rx).iter().any(
+        |event| matches!(event, SignallerMessage::RoomAdded { room, .. } if room == "test_room")
+    ));
+
+    let list = SignallerMessage::GetRoomList {
+        query: Default::default(),
+    };
+    send(&mut locked_state, &lobby_tx, list, lobby_addr)
+        .await
+        .unwrap();
+    assert!(matches!(
+        &drain(&mut lobby_rx)[..],
+        [SignallerMessage::RoomListResponse { total_count: 1, .. }]
+    ));
+
+    locked_state.on_disconnect(&lobby_addr);
+    assert!(locked_state.room_update_subscribers.is_empty());
+    assert!(!locked_state.connections.contains_key(&lobby_addr));
+    assert!(locked_state.sessions.contains_key("test_room"));
+}
+
+#[tokio::test]
+async fn test_disconnect_removes_viewers_and_queued_viewers() {
+    let state = State::new();
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let viewer_addr = SocketAddr::from(([127, 0, 0, 1], 8081));
+    let waiting_addr = SocketAddr::from(([127, 0, 0, 1], 8082));
+    let queued_addr = SocketAddr::from(([127, 0, 0, 1], 8083));
+    let (host_tx, mut host_rx) = unbounded();
+    let (viewer_tx, _viewer_rx) = unbounded();
+    let (waiting_tx, _waiting_rx) = unbounded();
+    let (queued_tx, _queued_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    send(&mut locked_state, &host_tx, start(Some(1)), host_addr)
+        .await
+        .unwrap();
+    send(&mut locked_state, &viewer_tx, join("viewer"), viewer_addr)
+        .await
+        .unwrap();
+    send(
+        &mut locked_state,
+        &waiting_tx,
+        join("waiting"),
+        waiting_addr,
+    )
+    .await
+    .unwrap();
+    send(&mut locked_state, &queued_tx, join("queued"), queued_addr)
+        .await
+        .unwrap();
+    drain(&mut host_rx);
+
+    // A queued viewer going away just leaves the queue
+    locked_state.on_disconnect(&queued_addr);
+    assert_eq!(locked_state.sessions["test_room"].waiting.len(), 1);
+
+    // An admitted viewer going away frees its slot for the next in line
+    locked_state.on_disconnect(&viewer_addr);
+    let session = &locked_state.sessions["test_room"];
+    assert!(!session.viewers.contains("viewer"));
+    assert!(session.viewers.contains("waiting"));
+    assert!(session.waiting.is_empty());
+    assert!(!locked_state.peers.contains_key("viewer"));
+    assert!(drain(&mut host_rx)
+        .iter()
+        .any(|msg| matches!(msg, SignallerMessage::Join { from, .. } if from == "waiting")));
+}
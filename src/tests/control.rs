@@ -0,0 +1,229 @@
+use std::net::SocketAddr;
+
+use futures_channel::mpsc::unbounded;
+
+use crate::models::{peer::ViewerRole, rtc::SignallerMessage, state::State};
+
+use super::{drain, send, start_room};
+
+#[tokio::test]
+async fn test_control_arbitration() {
+    let state = State::new();
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let viewer_addr = SocketAddr::from(([127, 0, 0, 1], 8081));
+    let (host_tx, mut host_rx) = unbounded();
+    let (driver_tx, mut driver_rx) = unbounded();
+    let (watcher_tx, mut watcher_rx) = unbounded();
+    let (other_tx, _other_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    start_room(&mut locked_state, "test_room", &host_tx, host_addr);
+    locked_state
+        .add_viewer(
+            "driver".to_string(),
+            "test_room".to_string(),
+            driver_tx.clone(),
+        )
+        .unwrap();
+    locked_state
+        .add_viewer_with_role(
+            "watcher".to_string(),
+            "test_room".to_string(),
+            watcher_tx.clone(),
+            ViewerRole::ViewOnly,
+            None,
+        )
+        .unwrap();
+    locked_state
+        .add_viewer(
+            "other".to_string(),
+            "test_room".to_string(),
+            other_tx.clone(),
+        )
+        .unwrap();
+
+    // A connection can only ask for control on behalf of its own peer
+    assert!(send(
+        &mut locked_state,
+        &watcher_tx,
+        SignallerMessage::RequestControl {
+            from: "driver".to_string()
+        },
+        viewer_addr,
+    )
+    .await
+    .is_err());
+
+    // Requests go to the host
+    send(
+        &mut locked_state,
+        &driver_tx,
+        SignallerMessage::RequestControl {
+            from: "driver".to_string(),
+        },
+        viewer_addr,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        drain(&mut host_rx)[..],
+        [SignallerMessage::RequestControl { .. }]
+    ));
+
+    // View-only viewers are refused outright
+    send(
+        &mut locked_state,
+        &watcher_tx,
+        SignallerMessage::RequestControl {
+            from: "watcher".to_string(),
+        },
+        viewer_addr,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        drain(&mut watcher_rx)[..],
+        [SignallerMessage::ControlDenied { .. }]
+    ));
+
+    // Only the host can grant
+    assert!(send(
+        &mut locked_state,
+        &driver_tx,
+        SignallerMessage::GrantControl {
+            to: "driver".to_string()
+        },
+        viewer_addr,
+    )
+    .await
+    .is_err());
+    send(
+        &mut locked_state,
+        &host_tx,
+        SignallerMessage::GrantControl {
+            to: "driver".to_string(),
+        },
+        host_addr,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        locked_state.sessions["test_room"].controller.as_deref(),
+        Some("driver")
+    );
+    for rx in [&mut host_rx, &mut driver_rx, &mut watcher_rx] {
+        match &drain(rx)[..] {
+            [SignallerMessage::ControlChanged { controller, .. }] => {
+                assert_eq!(controller.as_deref(), Some("driver"))
+            }
+            other => panic!("Unexpected messages: {:?}", other),
+        }
+    }
+
+    send(
+        &mut locked_state,
+        &host_tx,
+        SignallerMessage::GrantControl {
+            to: "watcher".to_string(),
+        },
+        host_addr,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        drain(&mut host_rx)[..],
+        [SignallerMessage::ControlDenied { .. }]
+    ));
+
+    // Granting to someone else moves control, there is never a second holder
+    locked_state.grant_control("test_room", "other").unwrap();
+    assert_eq!(
+        locked_state.sessions["test_room"].controller.as_deref(),
+        Some("other")
+    );
+    assert!(locked_state.release_control("driver").is_err());
+
+    // Leaving while holding control frees it
+    locked_state.leave_session("other".to_string()).unwrap();
+    assert_eq!(locked_state.sessions["test_room"].controller, None);
+}
+
+#[tokio::test]
+async fn test_control_disabled_room() {
+    let state = State::new();
+    let (host_tx, _host_rx) = unbounded();
+    let (viewer_tx, _viewer_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    start_room(
+        &mut locked_state,
+        "test_room",
+        &host_tx,
+        SocketAddr::from(([127, 0, 0, 1], 8080)),
+    );
+    locked_state.sessions.get_mut("test_room").unwrap().control = false;
+    locked_state
+        .add_viewer("viewer1".to_string(), "test_room".to_string(), viewer_tx)
+        .unwrap();
+
+    assert!(locked_state.request_control("viewer1").is_err());
+    assert!(locked_state.grant_control("test_room", "viewer1").is_err());
+
+    locked_state.sessions.get_mut("test_room").unwrap().control = true;
+    locked_state.grant_control("test_room", "viewer1").unwrap();
+    locked_state.release_control("viewer1").unwrap();
+    assert_eq!(locked_state.sessions["test_room"].controller, None);
+}
+
+#[tokio::test]
+async fn test_join_cannot_take_over_peer_ids() {
+    let state = State::new();
+    let (host_tx, _host_rx) = unbounded();
+    let (driver_tx, _driver_rx) = unbounded();
+    let (attacker_tx, mut attacker_rx) = unbounded();
+    let attacker_addr = SocketAddr::from(([127, 0, 0, 1], 8082));
+
+    let mut locked_state = state.lock().await;
+    start_room(
+        &mut locked_state,
+        "test_room",
+        &host_tx,
+        SocketAddr::from(([127, 0, 0, 1], 8080)),
+    );
+    locked_state
+        .add_viewer(
+            "driver".to_string(),
+            "test_room".to_string(),
+            driver_tx.clone(),
+        )
+        .unwrap();
+    locked_state.grant_control("test_room", "driver").unwrap();
+
+    // Neither the controller's ID nor the host's can be claimed
+    for id in ["driver", "test_room"] {
+        send(
+            &mut locked_state,
+            &attacker_tx,
+            SignallerMessage::Join {
+                from: id.to_string(),
+                room: "test_room".to_string(),
+                invite: None,
+                client: Default::default(),
+            },
+            attacker_addr,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            &drain(&mut attacker_rx)[..],
+            [SignallerMessage::JoinDeclined { reason, .. }] if reason == "Peer ID is already in use"
+        ));
+    }
+    assert!(locked_state.is_peer_connection("driver", &driver_tx));
+    assert!(!locked_state.is_peer_connection("test_room", &attacker_tx));
+    assert_eq!(
+        locked_state.sessions["test_room"].controller.as_deref(),
+        Some("driver")
+    );
+    assert_eq!(locked_state.sessions["test_room"].viewers.len(), 1);
+}
@@ -0,0 +1,189 @@
+use std::net::SocketAddr;
+
+use futures_channel::mpsc::unbounded;
+
+use crate::models::{
+    account::AccountRole, device::MAX_PAIRING_GUESSES, listing::RoomQuery, rtc::SignallerMessage,
+    state::State,
+};
+
+use super::{add_account, next_message, send};
+
+const HOST_SECRET: &str = "6c1fdf1ea0b14a4bb0f2d2e0b3c8a3d7";
+
+#[tokio::test]
+async fn test_pairing_flow() {
+    let state = State::new();
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let user_addr = SocketAddr::from(([127, 0, 0, 1], 8081));
+    let (host_tx, mut host_rx) = unbounded();
+    let (user_tx, mut user_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    add_account(&mut locked_state, "alice", AccountRole::User);
+
+    send(
+        &mut locked_state,
+        &host_tx,
+        SignallerMessage::RequestPairingCode {
+            device_secret: HOST_SECRET.to_string(),
+        },
+        host_addr,
+    )
+    .await
+    .unwrap();
+    let code = match next_message(&mut host_rx) {
+        SignallerMessage::PairingCode { code, .. } => code,
+        other => panic!("Unexpected response: {:?}", other),
+    };
+    assert_eq!(code.len(), 6);
+
+    // Claiming requires an authenticated connection
+    let claim = || SignallerMessage::ClaimPairingCode {
+        code: code.to_lowercase(),
+        name: Some("Front desk".to_string()),
+    };
+    assert!(send(&mut locked_state, &user_tx, claim(), user_addr)
+        .await
+        .is_err());
+
+    let token = locked_state.issue_token("alice");
+    send(
+        &mut locked_state,
+        &user_tx,
+        SignallerMessage::Authenticate { token },
+        user_addr,
+    )
+    .await
+    .unwrap();
+    next_message(&mut user_rx);
+
+    send(&mut locked_state, &user_tx, claim(), user_addr)
+        .await
+        .unwrap();
+    let room = match next_message(&mut user_rx) {
+        SignallerMessage::DevicePaired { room, name } => {
+            assert_eq!(name.as_deref(), Some("Front desk"));
+            room
+        }
+        other => panic!("Unexpected response: {:?}", other),
+    };
+    match next_message(&mut host_rx) {
+        SignallerMessage::Paired {
+            room: paired_room,
+            owner,
+        } => {
+            assert_eq!(paired_room, room);
+            assert_eq!(owner, "alice");
+        }
+        other => panic!("Unexpected response: {:?}", other),
+    }
+    assert_eq!(locked_state.devices_for_owner("alice").unwrap().len(), 1);
+
+    // A paired room only starts with the secret it was paired with
+    let start = |device_secret: &str| SignallerMessage::Start {
+        room: room.clone(),
+        name: "Front desk".to_string(),
+        os: "Windows".to_string(),
+        version: "1.0".to_string(),
+        control: true,
+        device_secret: Some(device_secret.to_string()),
+        max_viewers: None,
+        visibility: None,
+        tags: Default::default(),
+    };
+    assert!(send(
+        &mut locked_state,
+        &host_tx,
+        start("other-secret-of-the-right-length-0000"),
+        host_addr
+    )
+    .await
+    .is_err());
+    send(&mut locked_state, &host_tx, start(HOST_SECRET), host_addr)
+        .await
+        .unwrap();
+    assert_eq!(locked_state.sessions[&room].owner.as_deref(), Some("alice"));
+
+    let listing = locked_state
+        .get_available_rooms(
+            &RoomQuery {
+                owned: Some(true),
+                ..Default::default()
+            },
+            Some("alice"),
+        )
+        .unwrap();
+    assert_eq!(listing.total_count, 1);
+    assert_eq!(listing.rooms[0].room, room);
+}
+
+#[tokio::test]
+async fn test_claim_invalid_pairing_code() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    assert!(locked_state
+        .claim_pairing_code("NOPE00", "alice", None)
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_pairing_guess_limit() {
+    let state = State::new();
+    let (tx, _rx) = unbounded();
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let mut locked_state = state.lock().await;
+    let code = locked_state
+        .request_pairing_code(HOST_SECRET, tx, host_addr)
+        .unwrap();
+
+    for _ in 0..MAX_PAIRING_GUESSES {
+        assert_eq!(
+            locked_state
+                .claim_pairing_code("NOPE00", "mallory", None)
+                .unwrap_err()
+                .to_string(),
+            "Invalid pairing code"
+        );
+    }
+    // Even the right code is refused once the guesses are used up
+    assert_eq!(
+        locked_state
+            .claim_pairing_code(&code, "mallory", None)
+            .unwrap_err()
+            .to_string(),
+        "Too many pairing attempts, try again later"
+    );
+    locked_state
+        .claim_pairing_code(&code, "alice", None)
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_device_secret_is_hashed() {
+    let state = State::new();
+    let (tx, _rx) = unbounded();
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let mut locked_state = state.lock().await;
+    assert!(locked_state
+        .request_pairing_code("short", tx.clone(), host_addr)
+        .is_err());
+
+    let code = locked_state
+        .request_pairing_code(HOST_SECRET, tx, host_addr)
+        .unwrap();
+    let device = locked_state
+        .claim_pairing_code(&code, "alice", None)
+        .unwrap();
+    assert_ne!(device.secret_hash, HOST_SECRET);
+    assert_eq!(
+        locked_state
+            .check_device_secret(&device.room, Some(HOST_SECRET))
+            .unwrap()
+            .as_deref(),
+        Some("alice")
+    );
+    assert!(locked_state
+        .check_device_secret(&device.room, None)
+        .is_err());
+}
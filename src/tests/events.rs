@@ -0,0 +1,347 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use futures_channel::mpsc::unbounded;
+
+use crate::models::{
+    events::ROOM_EVENT_HISTORY,
+    filter::{AnyOf, RoomFilter},
+    room::RoomChanges,
+    rtc::SignallerMessage,
+    state::State,
+};
+
+use super::{add_room, drain, send, start_message};
+
+fn version_of(event: &SignallerMessage) -> u64 {
+    match event {
+        SignallerMessage::RoomAdded { version, .. }
+        | SignallerMessage::RoomUpdated { version, .. }
+        | SignallerMessage::RoomRemoved { version, .. }
+        | SignallerMessage::ViewerCountChanged { version, .. } => *version,
+        other => panic!("Not a room event: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_room_event_stream() {
+    let state = State::new();
+    let lobby_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8081));
+    let viewer_addr = SocketAddr::from(([127, 0, 0, 1], 8082));
+    let (lobby_tx, mut lobby_rx) = unbounded();
+    let (host_tx, _host_rx) = unbounded();
+    let (viewer_tx, _viewer_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    send(
+        &mut locked_state,
+        &lobby_tx,
+        start_message("lobby"),
+        lobby_addr,
+    )
+    .await
+    .unwrap();
+    drain(&mut lobby_rx);
+
+    // A fresh subscriber starts from a snapshot
+    send(
+        &mut locked_state,
+        &lobby_tx,
+        SignallerMessage::SubscribeRoomUpdates {
+            subscription: None,
+            since: None,
+            filter: Default::default(),
+        },
+        lobby_addr,
+    )
+    .await
+    .unwrap();
+    let snapshot_version = match &drain(&mut lobby_rx)[..] {
+        [SignallerMessage::RoomSnapshot { version, rooms }] => {
+            assert!(rooms.iter().any(|info| info.room == "lobby"));
+            *version
+        }
+        other => panic!("Unexpected messages: {:?}", other),
+    };
+
+    send(
+        &mut locked_state,
+        &host_tx,
+        start_message("test_room"),
+        host_addr,
+    )
+    .await
+    .unwrap();
+    let join = SignallerMessage::Join {
+        from: "viewer".to_string(),
+        room: "test_room".to_string(),
+        invite: None,
+        client: Default::default(),
+    };
+    send(&mut locked_state, &viewer_tx, join, viewer_addr)
+        .await
+        .unwrap();
+    let update = SignallerMessage::UpdateRoom {
+        changes: RoomChanges {
+            name: Some("renamed".to_string()),
+            ..Default::default()
+        },
+    };
+    send(&mut locked_state, &host_tx, update, host_addr)
+        .await
+        .unwrap();
+    let leave = SignallerMessage::Leave {
+        from: "viewer".to_string(),
+    };
+    send(&mut locked_state, &viewer_tx, leave, viewer_addr)
+        .await
+        .unwrap();
+    locked_state.on_disconnect(&host_addr);
+
+    let events = drain(&mut lobby_rx);
+    assert!(matches!(
+        &events[..],
+        [
+            SignallerMessage::RoomAdded { info, .. },
+            SignallerMessage::ViewerCountChanged { viewer_count: 1, .. },
+            SignallerMessage::RoomUpdated { .. },
+            SignallerMessage::ViewerCountChanged { viewer_count: 0, .. },
+            SignallerMessage::RoomRemoved { .. },
+        ] if info.name == "test_name"
+    ));
+    let versions: Vec<u64> = events.iter().map(version_of).collect();
+    assert!(versions[0] > snapshot_version);
+    assert!(versions.windows(2).all(|pair| pair[0] < pair[1]));
+
+    // Catching up replays exactly the missed events
+    send(
+        &mut locked_state,
+        &lobby_tx,
+        SignallerMessage::SubscribeRoomUpdates {
+            subscription: None,
+            filter: Default::default(),
+            since: Some(versions[1]),
+        },
+        lobby_addr,
+    )
+    .await
+    .unwrap();
+    let replayed: Vec<u64> = drain(&mut lobby_rx).iter().map(version_of).collect();
+    assert_eq!(replayed, versions[2..]);
+
+    // A version from before a restart gets a snapshot
+    send(
+        &mut locked_state,
+        &lobby_tx,
+        SignallerMessage::SubscribeRoomUpdates {
+            subscription: None,
+            filter: Default::default(),
+            since: Some(versions[4] + 100),
+        },
+        lobby_addr,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        &drain(&mut lobby_rx)[..],
+        [SignallerMessage::RoomSnapshot { version, .. }] if *version == versions[4]
+    ));
+}
+
+#[tokio::test]
+async fn test_room_event_history_is_bounded() {
+    let state = State::new();
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let (host_tx, _host_rx) = unbounded();
+    let (tx, mut rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    send(
+        &mut locked_state,
+        &host_tx,
+        start_message("test_room"),
+        host_addr,
+    )
+    .await
+    .unwrap();
+    for _ in 0..ROOM_EVENT_HISTORY {
+        locked_state.publish_viewer_count("test_room");
+    }
+    assert_eq!(locked_state.room_events.len(), ROOM_EVENT_HISTORY);
+
+    // The very first event is gone, so the client needs a snapshot
+    locked_state.send_room_events_since(Some(0), &Default::default(), None, &tx);
+    assert!(matches!(
+        &drain(&mut rx)[..],
+        [SignallerMessage::RoomSnapshot { rooms, .. }] if rooms[0].room == "test_room"
+    ));
+
+    locked_state.send_room_events_since(Some(1), &Default::default(), None, &tx);
+    assert_eq!(drain(&mut rx).len(), ROOM_EVENT_HISTORY);
+}
+
+fn subscribe(subscription: &str, filter: RoomFilter) -> SignallerMessage {
+    SignallerMessage::SubscribeRoomUpdates {
+        subscription: Some(subscription.to_string()),
+        since: None,
+        filter,
+    }
+}
+
+fn update(changes: RoomChanges) -> SignallerMessage {
+    SignallerMessage::UpdateRoom { changes }
+}
+
+fn start_with_os(room: &str, os: &str) -> SignallerMessage {
+    match start_message(room) {
+        SignallerMessage::Start {
+            room,
+            name,
+            version,
+            control,
+            device_secret,
+            max_viewers,
+            visibility,
+            tags,
+            ..
+        } => SignallerMessage::Start {
+            room,
+            name,
+            os: os.to_string(),
+            version,
+            control,
+            device_secret,
+            max_viewers,
+            visibility,
+            tags,
+        },
+        _ => unreachable!(),
+    }
+}
+
+#[tokio::test]
+async fn test_filtered_subscriptions() {
+    let state = State::new();
+    let lobby_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let windows_addr = SocketAddr::from(([127, 0, 0, 1], 8081));
+    let linux_addr = SocketAddr::from(([127, 0, 0, 1], 8082));
+    let (lobby_tx, mut lobby_rx) = unbounded();
+    let (windows_tx, _windows_rx) = unbounded();
+    let (linux_tx, _linux_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    send(
+        &mut locked_state,
+        &lobby_tx,
+        start_message("lobby"),
+        lobby_addr,
+    )
+    .await
+    .unwrap();
+    let windows = RoomFilter {
+        os: AnyOf(vec!["Windows".to_string()]),
+        ..Default::default()
+    };
+    send(
+        &mut locked_state,
+        &lobby_tx,
+        subscribe("windows", windows),
+        lobby_addr,
+    )
+    .await
+    .unwrap();
+    let lab = RoomFilter {
+        tags: BTreeMap::from([("site".to_string(), "lab".to_string())]),
+        ..Default::default()
+    };
+    send(
+        &mut locked_state,
+        &lobby_tx,
+        subscribe("lab", lab),
+        lobby_addr,
+    )
+    .await
+    .unwrap();
+    drain(&mut lobby_rx);
+
+    let windows_start = start_with_os("windows_room", "windows");
+    send(&mut locked_state, &windows_tx, windows_start, windows_addr)
+        .await
+        .unwrap();
+    let linux_start = start_with_os("linux_room", "linux");
+    send(&mut locked_state, &linux_tx, linux_start, linux_addr)
+        .await
+        .unwrap();
+    let events = drain(&mut lobby_rx);
+    assert!(matches!(
+        &events[..],
+        [SignallerMessage::RoomAdded { room, .. }] if room == "windows_room"
+    ));
+
+    // Starting to match the tag filter is reported
+    let tag = RoomChanges {
+        tags: Some(BTreeMap::from([("site".to_string(), "lab".to_string())])),
+        ..Default::default()
+    };
+    send(&mut locked_state, &linux_tx, update(tag), linux_addr)
+        .await
+        .unwrap();
+    assert!(matches!(
+        &drain(&mut lobby_rx)[..],
+        [SignallerMessage::RoomUpdated { room, .. }] if room == "linux_room"
+    ));
+
+    // Cancelling one subscription leaves the others
+    let cancel = SignallerMessage::UnsubscribeRoomUpdates {
+        subscription: Some("lab".to_string()),
+    };
+    send(&mut locked_state, &lobby_tx, cancel, lobby_addr)
+        .await
+        .unwrap();
+    let describe = || RoomChanges {
+        description: Some("changed".to_string()),
+        ..Default::default()
+    };
+    send(&mut locked_state, &linux_tx, update(describe()), linux_addr)
+        .await
+        .unwrap();
+    assert!(drain(&mut lobby_rx).is_empty());
+    send(
+        &mut locked_state,
+        &windows_tx,
+        update(describe()),
+        windows_addr,
+    )
+    .await
+    .unwrap();
+    assert_eq!(drain(&mut lobby_rx).len(), 1);
+
+    let cancel = SignallerMessage::UnsubscribeRoomUpdates { subscription: None };
+    send(&mut locked_state, &lobby_tx, cancel, lobby_addr)
+        .await
+        .unwrap();
+    assert!(locked_state.room_update_subscribers.is_empty());
+}
+
+#[tokio::test]
+async fn test_room_snapshot_is_ordered() {
+    let state = State::new();
+    let (tx, mut rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    add_room(&mut locked_state, "c", "beta", "linux", 8001);
+    add_room(&mut locked_state, "a", "Gamma", "linux", 8002);
+    add_room(&mut locked_state, "b", "alpha", "linux", 8003);
+    add_room(&mut locked_state, "d", "beta", "linux", 8004);
+
+    locked_state.send_room_events_since(None, &Default::default(), None, &tx);
+    let rooms = match &drain(&mut rx)[..] {
+        [SignallerMessage::RoomSnapshot { rooms, .. }] => rooms
+            .iter()
+            .map(|info| info.room.clone())
+            .collect::<Vec<_>>(),
+        other => panic!("Unexpected messages: {:?}", other),
+    };
+    assert_eq!(rooms, ["b", "c", "d", "a"]);
+}
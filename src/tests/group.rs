@@ -0,0 +1,342 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use futures_channel::mpsc::unbounded;
+
+use crate::models::{
+    filter::{AnyOf, RoomFilter},
+    group::DeviceGroup,
+    listing::RoomQuery,
+    room::Visibility,
+    rtc::SignallerMessage,
+    selector::{Requirement, TagSelector},
+    state::State,
+    store::Store,
+};
+
+use super::{drain, send};
+
+fn start(room: &str, tags: &[(&str, &str)]) -> SignallerMessage {
+    SignallerMessage::Start {
+        room: room.to_string(),
+        name: format!("{} host", room),
+        os: "linux".to_string(),
+        version: "1.0".to_string(),
+        control: true,
+        device_secret: None,
+        max_viewers: None,
+        visibility: None,
+        tags: tags
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+    }
+}
+
+fn group(name: &str, parent: Option<&str>, rooms: &[&str]) -> DeviceGroup {
+    DeviceGroup {
+        name: name.to_string(),
+        parent: parent.map(str::to_string),
+        rooms: rooms.iter().map(|room| room.to_string()).collect(),
+        description: String::new(),
+    }
+}
+
+fn listed(state: &State, filter: RoomFilter) -> Vec<String> {
+    let query = RoomQuery {
+        filter,
+        ..Default::default()
+    };
+    let mut rooms: Vec<String> = state
+        .get_available_rooms(&query, None)
+        .unwrap()
+        .rooms
+        .into_iter()
+        .map(|info| info.room)
+        .collect();
+    rooms.sort();
+    rooms
+}
+
+fn by_group(group: &str) -> RoomFilter {
+    RoomFilter {
+        group: AnyOf(vec![group.to_string()]),
+        ..Default::default()
+    }
+}
+
+fn by_selector(selector: &str) -> RoomFilter {
+    RoomFilter {
+        selector: Some(TagSelector::parse(selector).unwrap()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_parse_selector() {
+    let selector = TagSelector::parse("site=berlin, floor in (1, 2),!retired,kiosk").unwrap();
+    assert_eq!(
+        selector.requirements,
+        vec![
+            Requirement::In("site".to_string(), vec!["berlin".to_string()]),
+            Requirement::In("floor".to_string(), vec!["1".to_string(), "2".to_string()]),
+            Requirement::NotExists("retired".to_string()),
+            Requirement::Exists("kiosk".to_string()),
+        ]
+    );
+    assert_eq!(
+        String::from(selector),
+        "site=berlin,floor in (1, 2),!retired,kiosk"
+    );
+
+    let selector: TagSelector = serde_json::from_str("\"site!=lab,floor notin (3)\"").unwrap();
+    let tags = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    };
+    assert!(selector.matches(&tags(&[])));
+    assert!(selector.matches(&tags(&[("site", "home"), ("floor", "1")])));
+    assert!(!selector.matches(&tags(&[("site", "lab")])));
+    assert!(!selector.matches(&tags(&[("floor", "3")])));
+
+    assert!(TagSelector::parse("site in berlin").is_err());
+    assert!(TagSelector::parse("sit e=berlin").is_err());
+    assert!(serde_json::from_str::<TagSelector>("\"=berlin\"").is_err());
+}
+
+#[tokio::test]
+async fn test_start_tags() {
+    let state = State::new();
+    let (tx, _rx) = unbounded();
+    let mut locked_state = state.lock().await;
+    let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    send(
+        &mut locked_state,
+        &tx,
+        start("kiosk", &[("site", "berlin"), ("floor", "2")]),
+        addr,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        locked_state.room_info("kiosk").unwrap().tags["site"],
+        "berlin"
+    );
+    assert_eq!(listed(&locked_state, by_selector("site=berlin")), ["kiosk"]);
+
+    // Tags are validated like in UpdateRoom
+    let (tx, _rx) = unbounded();
+    assert!(send(
+        &mut locked_state,
+        &tx,
+        start("other", &[("bad key", "x")]),
+        SocketAddr::from(([127, 0, 0, 1], 8081)),
+    )
+    .await
+    .is_err());
+    assert!(!locked_state.sessions.contains_key("other"));
+}
+
+#[tokio::test]
+async fn test_nested_groups() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let mut hosts = vec![];
+    for (index, (room, site)) in [
+        ("kiosk1", "berlin"),
+        ("kiosk2", "berlin"),
+        ("kiosk3", "paris"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let (tx, rx) = unbounded();
+        hosts.push(rx);
+        send(
+            &mut locked_state,
+            &tx,
+            start(room, &[("site", site)]),
+            SocketAddr::from(([127, 0, 0, 1], 8080 + index as u16)),
+        )
+        .await
+        .unwrap();
+    }
+
+    locked_state.put_group(group("emea", None, &[])).unwrap();
+    locked_state
+        .put_group(group("berlin", Some("emea"), &["kiosk1"]))
+        .unwrap();
+    locked_state
+        .put_group(group("floor-2", Some("berlin"), &["kiosk2"]))
+        .unwrap();
+
+    assert_eq!(
+        locked_state.room_info("kiosk2").unwrap().groups,
+        ["berlin", "emea", "floor-2"]
+    );
+    assert!(locked_state.room_info("kiosk3").unwrap().groups.is_empty());
+    assert_eq!(
+        listed(&locked_state, by_group("EMEA")),
+        ["kiosk1", "kiosk2"]
+    );
+    assert_eq!(listed(&locked_state, by_group("floor-2")), ["kiosk2"]);
+    assert_eq!(
+        listed(
+            &locked_state,
+            RoomFilter {
+                selector: Some(TagSelector::parse("site=berlin").unwrap()),
+                group: AnyOf(vec!["floor-2".to_string()]),
+                ..Default::default()
+            }
+        ),
+        ["kiosk2"]
+    );
+    assert_eq!(
+        listed(&locked_state, by_selector("site notin (berlin)")),
+        ["kiosk3"]
+    );
+
+    // No cycles, and the parent must exist
+    assert!(locked_state
+        .put_group(group("emea", Some("floor-2"), &[]))
+        .is_err());
+    assert!(locked_state
+        .put_group(group("emea", Some("emea"), &[]))
+        .is_err());
+    assert!(locked_state
+        .put_group(group("lyon", Some("france"), &[]))
+        .is_err());
+
+    // Groups with subgroups cannot be deleted
+    assert!(locked_state.delete_group("berlin").is_err());
+    locked_state.delete_group("floor-2").unwrap();
+    assert_eq!(listed(&locked_state, by_group("emea")), ["kiosk1"]);
+
+    // Moving a group re-indexes the rooms below it, and replacing one the
+    // rooms it gained or lost
+    locked_state
+        .put_group(group("floor-2", Some("berlin"), &["kiosk2"]))
+        .unwrap();
+    locked_state.put_group(group("europe", None, &[])).unwrap();
+    locked_state
+        .put_group(group("berlin", Some("europe"), &["kiosk1"]))
+        .unwrap();
+    assert_eq!(
+        listed(&locked_state, by_group("europe")),
+        ["kiosk1", "kiosk2"]
+    );
+    assert!(listed(&locked_state, by_group("emea")).is_empty());
+    locked_state
+        .put_group(group("berlin", Some("europe"), &["kiosk3"]))
+        .unwrap();
+    assert_eq!(
+        listed(&locked_state, by_group("berlin")),
+        ["kiosk2", "kiosk3"]
+    );
+}
+
+#[tokio::test]
+async fn test_group_listing_hides_rooms() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let (tx, _rx) = unbounded();
+    send(
+        &mut locked_state,
+        &tx,
+        start("kiosk1", &[]),
+        SocketAddr::from(([127, 0, 0, 1], 8080)),
+    )
+    .await
+    .unwrap();
+    let session = locked_state.sessions.get_mut("kiosk1").unwrap();
+    session.owner = Some("alice".to_string());
+    session.visibility = Visibility::Unlisted;
+    locked_state
+        .put_group(group("lab", None, &["kiosk1", "kiosk9"]))
+        .unwrap();
+
+    let rooms = |account: Option<&str>| -> Vec<String> {
+        locked_state.list_groups(account)[0]
+            .rooms
+            .iter()
+            .cloned()
+            .collect()
+    };
+    assert_eq!(rooms(Some("alice")), ["kiosk1", "kiosk9"]);
+    assert_eq!(rooms(Some("bob")), ["kiosk9"]);
+}
+
+#[tokio::test]
+async fn test_groups_are_persisted() {
+    let store = Store::temporary();
+    {
+        let state = State::with_store(store.clone());
+        let mut locked_state = state.lock().await;
+        locked_state.put_group(group("emea", None, &[])).unwrap();
+        locked_state
+            .put_group(group("berlin", Some("emea"), &["kiosk1"]))
+            .unwrap();
+    }
+    let state = State::with_store(store);
+    let locked_state = state.lock().await;
+    let names: Vec<String> = locked_state
+        .list_groups(None)
+        .into_iter()
+        .map(|group| group.name)
+        .collect();
+    assert_eq!(names, ["berlin", "emea"]);
+    assert_eq!(
+        locked_state
+            .room_groups("kiosk1")
+            .into_iter()
+            .collect::<Vec<_>>(),
+        ["berlin", "emea"]
+    );
+}
+
+#[tokio::test]
+async fn test_subscribe_by_selector() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let lobby_addr = SocketAddr::from(([127, 0, 0, 1], 9000));
+    let (lobby_tx, mut lobby_rx) = unbounded();
+    locked_state.register_connection(lobby_addr, lobby_tx.clone(), None);
+    send(
+        &mut locked_state,
+        &lobby_tx,
+        SignallerMessage::SubscribeRoomUpdates {
+            subscription: Some("berlin".to_string()),
+            since: None,
+            filter: by_selector("site=berlin"),
+        },
+        lobby_addr,
+    )
+    .await
+    .unwrap();
+    drain(&mut lobby_rx);
+
+    for (index, (room, site)) in [("kiosk1", "berlin"), ("kiosk2", "paris")]
+        .iter()
+        .enumerate()
+    {
+        let (tx, _rx) = unbounded();
+        send(
+            &mut locked_state,
+            &tx,
+            start(room, &[("site", site)]),
+            SocketAddr::from(([127, 0, 0, 1], 8080 + index as u16)),
+        )
+        .await
+        .unwrap();
+    }
+    let added: Vec<String> = drain(&mut lobby_rx)
+        .into_iter()
+        .filter_map(|message| match message {
+            SignallerMessage::RoomAdded { room, .. } => Some(room),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(added, ["kiosk1"]);
+}
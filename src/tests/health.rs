@@ -1,4 +1,29 @@
-use crate::controllers::health::health_check;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+    Router,
+};
+use tokio::net::UdpSocket;
+use tower::ServiceExt;
+
+use crate::{
+    args::Args,
+    controllers::health::{health_check, VERSION},
+    models::{
+        account::AccountRole,
+        health::{IceCheck, ServerMode, ICE_CHECK_TTL},
+        rtc::IceServer,
+        state::State,
+    },
+    routes::router::create_router,
+    services::ice::{check_ice_servers, parse_ice_url, probe_ice_url, IceEndpoint},
+};
+use clap::Parser;
+
+use super::sign_in;
 
 #[tokio::test]
 async fn test_health_check() {
@@ -6,3 +31,193 @@ async fn test_health_check() {
     let json = response.0;
     assert_eq!(json["status"], "ok");
 }
+
+async fn call(app: &Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+fn get(uri: &str) -> Request<Body> {
+    Request::get(uri)
+        .extension(axum::extract::ConnectInfo(SocketAddr::from((
+            [127, 0, 0, 1],
+            8080,
+        ))))
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn set_maintenance(token: &str, enabled: bool) -> Request<Body> {
+    Request::put("/health/maintenance")
+        .extension(axum::extract::ConnectInfo(SocketAddr::from((
+            [127, 0, 0, 1],
+            8080,
+        ))))
+        .header("authorization", format!("Bearer {}", token))
+        .header("content-type", "application/json")
+        .body(Body::from(format!(r#"{{"enabled": {}}}"#, enabled)))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_live_and_ready() {
+    let app = create_router(State::new(), Args::parse_from(["program"]));
+    let (status, body) = call(&app, get("/health/live")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+    assert_eq!(body["version"], VERSION);
+    assert!(body["uptime_secs"].is_u64());
+    assert_eq!(body["counters"]["sessions"], 0);
+
+    let (status, body) = call(&app, get("/health/ready")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["checks"]["state_lock"], "ok");
+    assert_eq!(body["checks"]["store"], "ok");
+    assert_eq!(body["checks"]["mode"], "ok");
+    assert_eq!(body["counters"]["connections"], 0);
+}
+
+#[tokio::test]
+async fn test_maintenance_and_draining() {
+    let state = State::new();
+    let (token, user_token) = {
+        let mut locked_state = state.lock().await;
+        (
+            sign_in(&mut locked_state, "admin", AccountRole::Admin),
+            sign_in(&mut locked_state, "bob", AccountRole::User),
+        )
+    };
+    let app = create_router(state.clone(), Args::parse_from(["program"]));
+
+    let (status, _) = call(&app, set_maintenance(&user_token, true)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, body) = call(&app, set_maintenance(&token, true)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["mode"], "maintenance");
+
+    let (status, body) = call(&app, get("/health/ready")).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "not_ready");
+    assert_eq!(body["checks"]["mode"], "maintenance");
+    // Still alive
+    let (status, _) = call(&app, get("/health/live")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    call(&app, set_maintenance(&token, false)).await;
+    let (status, _) = call(&app, get("/health/ready")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // A draining server cannot be put back
+    state.lock().await.mode = ServerMode::Draining;
+    let (status, _) = call(&app, set_maintenance(&token, false)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (_, body) = call(&app, get("/health/ready")).await;
+    assert_eq!(body["checks"]["mode"], "draining");
+}
+
+#[tokio::test]
+async fn test_ready_reuses_the_ice_check() {
+    let state = State::new();
+    state.lock().await.ice_check = Some(IceCheck {
+        checked_at: Instant::now(),
+        result: Err("No ICE server responded".to_string()),
+    });
+    let app = create_router(state.clone(), Args::parse_from(["program"]));
+    let (status, body) = call(&app, get("/health/ready")).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["checks"]["ice"], "No ICE server responded");
+
+    // Stale results are probed again
+    state.lock().await.ice_check.as_mut().unwrap().checked_at =
+        Instant::now().checked_sub(ICE_CHECK_TTL).unwrap();
+    let (status, body) = call(&app, get("/health/ready")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["checks"]["ice"], "not_configured");
+}
+
+#[tokio::test]
+async fn test_ready_fails_while_state_is_locked() {
+    let state = State::new();
+    let app = create_router(state.clone(), Args::parse_from(["program"]));
+    let _locked_state = state.lock().await;
+    let (status, body) = call(&app, get("/health/ready")).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["checks"]["state_lock"], "timed out");
+}
+
+#[test]
+fn test_parse_ice_url() {
+    let endpoint = |host: &str, port, tcp| IceEndpoint {
+        host: host.to_string(),
+        port,
+        tcp,
+    };
+    assert_eq!(
+        parse_ice_url("stun:stun.example.com").unwrap(),
+        endpoint("stun.example.com", 3478, false)
+    );
+    assert_eq!(
+        parse_ice_url("turn:turn.example.com:3479?transport=tcp").unwrap(),
+        endpoint("turn.example.com", 3479, true)
+    );
+    assert_eq!(
+        parse_ice_url("turns:turn.example.com").unwrap(),
+        endpoint("turn.example.com", 5349, true)
+    );
+    assert_eq!(
+        parse_ice_url("stun:[::1]:3478").unwrap(),
+        endpoint("::1", 3478, false)
+    );
+    assert!(parse_ice_url("https://example.com").is_err());
+    assert!(parse_ice_url("stun:example.com:port").is_err());
+}
+
+/// A STUN server answering every binding request with success.
+async fn fake_stun_server() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1024];
+        while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+            let mut response = buf[..len].to_vec();
+            response[..2].copy_from_slice(&[0x01, 0x01]);
+            let _ = socket.send_to(&response, from).await;
+        }
+    });
+    address
+}
+
+#[tokio::test]
+async fn test_ice_probe() {
+    let answering = fake_stun_server().await;
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let silent_url = format!("stun:{}", silent.local_addr().unwrap());
+    let limit = Duration::from_millis(200);
+
+    probe_ice_url(&format!("stun:{}", answering), limit)
+        .await
+        .unwrap();
+    assert!(probe_ice_url(&silent_url, limit).await.is_err());
+
+    let server = |url: String| IceServer {
+        urls: vec![url],
+        ..Default::default()
+    };
+    // One answering server is enough
+    check_ice_servers(
+        &[
+            server(silent_url.clone()),
+            server(format!("turn:{}", answering)),
+        ],
+        limit,
+    )
+    .await
+    .unwrap();
+    assert!(check_ice_servers(&[server(silent_url)], limit)
+        .await
+        .is_err());
+    check_ice_servers(&[], limit).await.unwrap();
+}
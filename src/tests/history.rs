@@ -0,0 +1,362 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::ws::Message,
+    http::{Request, StatusCode},
+};
+use futures_channel::mpsc::{unbounded, UnboundedReceiver};
+use tower::ServiceExt;
+
+use crate::{
+    args::Args,
+    models::{
+        account::{now_secs, AccountRole},
+        history::{
+            session_history, session_usage, EndReason, HistoryQuery, SessionRecord,
+            MAX_SESSION_VISITS, OWNER_HISTORY_TREE, SESSION_HISTORY_TREE,
+        },
+        peer::ViewerRole,
+        state::State,
+        store::Store,
+        view::Caller,
+    },
+    routes::router::create_router,
+};
+use clap::Parser;
+
+use super::sign_in;
+
+const HOUR: u64 = 3600;
+
+/// Start `room` an hour ago from a connection with a real IP.
+fn start(state: &mut State, room: &str, owner: Option<&str>, port: u16) -> SocketAddr {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let (tx, _rx) = unbounded();
+    state.register_connection(addr, tx.clone(), Some("203.0.113.1".parse().unwrap()));
+    state
+        .add_server(
+            room.to_string(),
+            format!("{} host", room),
+            "linux".to_string(),
+            "1.0".to_string(),
+            true,
+            tx,
+            addr,
+        )
+        .unwrap();
+    let session = state.sessions.get_mut(room).unwrap();
+    session.owner = owner.map(str::to_string);
+    session.start_time = SystemTime::now() - Duration::from_secs(HOUR);
+    addr
+}
+
+/// Admit `viewer` to `room`, pretending it joined `ago` seconds ago.
+fn join(
+    state: &mut State,
+    room: &str,
+    viewer: &str,
+    ip: &str,
+    ago: u64,
+) -> UnboundedReceiver<Message> {
+    let (tx, rx) = unbounded();
+    let ip: IpAddr = ip.parse().unwrap();
+    state
+        .add_viewer_with_role(
+            viewer.to_string(),
+            room.to_string(),
+            tx,
+            ViewerRole::Control,
+            Some(ip),
+        )
+        .unwrap();
+    state.peers.get_mut(viewer).unwrap().joined_at = SystemTime::now() - Duration::from_secs(ago);
+    rx
+}
+
+fn admin() -> Caller {
+    Caller {
+        account: Some("admin".to_string()),
+        admin: true,
+    }
+}
+
+fn owner(account: &str) -> Caller {
+    Caller {
+        account: Some(account.to_string()),
+        admin: false,
+    }
+}
+
+/// Two ended sessions: kiosk1 with two viewers, whose host disconnected,
+/// and kiosk2 of alice with one viewer, whose host left.
+fn populate(state: &mut State) {
+    let addr = start(state, "kiosk1", None, 9001);
+    let _v1 = join(state, "kiosk1", "v1", "198.51.100.1", HOUR);
+    let _v2 = join(state, "kiosk1", "v2", "198.51.100.2", HOUR / 2);
+    state.leave_session("v1".to_string()).unwrap();
+    state.on_disconnect(&addr);
+
+    start(state, "kiosk2", Some("alice"), 9002);
+    let _v1 = join(state, "kiosk2", "v1", "198.51.100.1", HOUR / 4);
+    state.leave_session("kiosk2".to_string()).unwrap();
+}
+
+#[tokio::test]
+async fn test_session_history() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    populate(&mut locked_state);
+
+    let sessions = session_history(&locked_state.store, &Default::default(), &admin()).unwrap();
+    assert_eq!(sessions.len(), 2);
+    let kiosk1 = sessions.iter().find(|s| s.room == "kiosk1").unwrap();
+    assert_eq!(kiosk1.end_reason, EndReason::Disconnected);
+    assert_eq!(kiosk1.host_ip, Some("203.0.113.1".parse().unwrap()));
+    assert!((HOUR..HOUR + 5).contains(&(kiosk1.ended_at - kiosk1.started_at)));
+    let mut viewers: Vec<(&str, Option<IpAddr>)> = kiosk1
+        .viewers
+        .iter()
+        .map(|visit| (visit.id.as_str(), visit.ip))
+        .collect();
+    viewers.sort();
+    assert_eq!(
+        viewers,
+        [
+            ("v1", Some("198.51.100.1".parse().unwrap())),
+            ("v2", Some("198.51.100.2".parse().unwrap()))
+        ]
+    );
+    let kiosk2 = sessions.iter().find(|s| s.room == "kiosk2").unwrap();
+    assert_eq!(kiosk2.end_reason, EndReason::HostLeft);
+
+    let query = |room: Option<&str>, viewer: Option<&str>| HistoryQuery {
+        room: room.map(str::to_string),
+        viewer: viewer.map(str::to_string),
+        ..Default::default()
+    };
+    let rooms = |query: HistoryQuery, caller: &Caller| -> Vec<String> {
+        session_history(&locked_state.store, &query, caller)
+            .unwrap()
+            .into_iter()
+            .map(|record| record.room)
+            .collect()
+    };
+    assert_eq!(rooms(query(Some("kiosk2"), None), &admin()), ["kiosk2"]);
+    assert_eq!(rooms(query(None, Some("v2")), &admin()), ["kiosk1"]);
+    assert!(rooms(query(None, Some("v3")), &admin()).is_empty());
+
+    // Owners only see their rooms, without IPs
+    assert_eq!(rooms(query(None, None), &owner("alice")), ["kiosk2"]);
+    assert!(rooms(query(None, None), &owner("bob")).is_empty());
+    let sessions =
+        session_history(&locked_state.store, &Default::default(), &owner("alice")).unwrap();
+    assert_eq!(sessions[0].host_ip, None);
+    assert_eq!(sessions[0].viewers[0].ip, None);
+
+    // Time ranges
+    let future = HistoryQuery {
+        from: Some(now_secs() + HOUR),
+        ..Default::default()
+    };
+    assert!(rooms(future, &admin()).is_empty());
+    let past = HistoryQuery {
+        to: Some(now_secs() - 2 * HOUR),
+        ..Default::default()
+    };
+    assert!(rooms(past, &admin()).is_empty());
+    let backwards = HistoryQuery {
+        from: Some(2),
+        to: Some(1),
+        ..Default::default()
+    };
+    assert!(session_history(&locked_state.store, &backwards, &admin()).is_err());
+}
+
+#[tokio::test]
+async fn test_session_usage() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    populate(&mut locked_state);
+
+    let usage = session_usage(&locked_state.store, &Default::default(), &admin()).unwrap();
+    assert_eq!(usage.len(), 2);
+    assert_eq!(usage[0].room, "kiosk1");
+    assert_eq!(usage[0].sessions, 1);
+    assert!((HOUR..HOUR + 5).contains(&usage[0].connected_secs));
+    // v1 stayed the full hour, v2 the last half
+    assert!((HOUR * 3 / 2..HOUR * 3 / 2 + 5).contains(&usage[0].viewer_secs));
+    assert_eq!(usage[0].unique_viewers, 2);
+
+    // Clipped to the last half hour, and to one viewer
+    let query = HistoryQuery {
+        from: Some(now_secs() - HOUR / 2),
+        viewer: Some("v2".to_string()),
+        ..Default::default()
+    };
+    let usage = session_usage(&locked_state.store, &query, &admin()).unwrap();
+    assert_eq!(usage.len(), 1);
+    assert!((HOUR / 2..HOUR / 2 + 5).contains(&usage[0].connected_secs));
+    assert!((HOUR / 2..HOUR / 2 + 5).contains(&usage[0].viewer_secs));
+    assert_eq!(usage[0].unique_viewers, 1);
+}
+
+#[tokio::test]
+async fn test_history_api_requires_login() {
+    let state = State::new();
+    let token = {
+        let mut locked_state = state.lock().await;
+        populate(&mut locked_state);
+        sign_in(&mut locked_state, "admin", AccountRole::Admin)
+    };
+    let app = create_router(state, Args::parse_from(["program"]));
+    let request = |uri: &str, token: Option<&str>| {
+        let mut request = Request::get(uri).extension(axum::extract::ConnectInfo(
+            SocketAddr::from(([127, 0, 0, 1], 8080)),
+        ));
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        request.body(Body::empty()).unwrap()
+    };
+
+    let response = app
+        .clone()
+        .oneshot(request("/history/sessions", None))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .clone()
+        .oneshot(request("/history/sessions?room=kiosk1", Some(&token)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["sessions"].as_array().unwrap().len(), 1);
+    assert_eq!(body["sessions"][0]["end_reason"], "disconnected");
+
+    let response = app
+        .oneshot(request("/history/usage?viewer=v1", Some(&token)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["devices"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_history_retention() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    populate(&mut locked_state);
+    locked_state.history_retention = Some(Duration::from_secs(2 * HOUR));
+    locked_state.prune_history();
+    assert_eq!(
+        session_history(&locked_state.store, &Default::default(), &admin())
+            .unwrap()
+            .len(),
+        2
+    );
+
+    // Both sessions started an hour ago
+    locked_state.history_retention = Some(Duration::from_secs(HOUR / 2));
+    locked_state.prune_history();
+    assert!(
+        session_history(&locked_state.store, &Default::default(), &admin())
+            .unwrap()
+            .is_empty()
+    );
+    // Along with the index of alice's sessions
+    assert!(locked_state
+        .store
+        .values::<String>(OWNER_HISTORY_TREE)
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_session_visits_are_capped() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    start(&mut locked_state, "kiosk1", None, 9001);
+    let _v1 = join(&mut locked_state, "kiosk1", "v1", "198.51.100.1", HOUR);
+    locked_state.leave_session("v1".to_string()).unwrap();
+    let visit = locked_state.sessions["kiosk1"].visits[0].clone();
+    locked_state.sessions.get_mut("kiosk1").unwrap().visits = vec![visit; MAX_SESSION_VISITS];
+
+    let _v2 = join(&mut locked_state, "kiosk1", "v2", "198.51.100.2", HOUR / 2);
+    locked_state.leave_session("v2".to_string()).unwrap();
+    locked_state.leave_session("kiosk1".to_string()).unwrap();
+
+    let sessions = session_history(&locked_state.store, &Default::default(), &admin()).unwrap();
+    assert_eq!(sessions[0].viewers.len(), MAX_SESSION_VISITS);
+    assert_eq!(sessions[0].viewers.last().unwrap().id, "v2");
+    assert_eq!(sessions[0].dropped_visits, 1);
+}
+
+#[tokio::test]
+async fn test_history_range_includes_long_sessions() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    populate(&mut locked_state);
+
+    // kiosk1 started an hour ago and is the only one running five minutes ago
+    let query = HistoryQuery {
+        from: Some(now_secs() - 300),
+        to: Some(now_secs() - 300),
+        room: Some("kiosk1".to_string()),
+        ..Default::default()
+    };
+    let sessions = session_history(&locked_state.store, &query, &admin()).unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].room, "kiosk1");
+}
+
+#[tokio::test]
+async fn test_session_history_limit() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    populate(&mut locked_state);
+    let query = HistoryQuery {
+        limit: Some(1),
+        ..Default::default()
+    };
+    let sessions = session_history(&locked_state.store, &query, &admin()).unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].room, "kiosk2");
+}
+
+#[tokio::test]
+async fn test_history_indexed_on_upgrade() {
+    // A session recorded before owners and lengths were indexed
+    let store = Store::temporary();
+    let started_at = now_secs() - 10 * HOUR;
+    let record = SessionRecord {
+        room: "kiosk1".to_string(),
+        name: "kiosk1 host".to_string(),
+        host: "kiosk1".to_string(),
+        host_ip: None,
+        owner: Some("alice".to_string()),
+        started_at,
+        ended_at: now_secs(),
+        end_reason: EndReason::HostLeft,
+        viewers: vec![],
+        dropped_visits: 0,
+    };
+    let key = format!("{:020}/kiosk1", started_at);
+    store.put(SESSION_HISTORY_TREE, &key, &record).unwrap();
+
+    let state = State::with_store(store);
+    let locked_state = state.lock().await;
+    let query = HistoryQuery {
+        from: Some(now_secs() - HOUR),
+        ..Default::default()
+    };
+    let sessions = session_history(&locked_state.store, &query, &owner("alice")).unwrap();
+    assert_eq!(sessions, [record]);
+}
@@ -0,0 +1,174 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures_channel::mpsc::unbounded;
+
+use crate::{
+    models::{
+        peer::{PeerType, ViewerRole},
+        rtc::SignallerMessage,
+        state::State,
+    },
+    services::websocket::handle_message,
+};
+
+use super::start_room;
+
+#[tokio::test]
+async fn test_invite_roundtrip_and_max_uses() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let (tx, _rx) = unbounded();
+    start_room(
+        &mut locked_state,
+        "test_room",
+        &tx,
+        SocketAddr::from(([127, 0, 0, 1], 8080)),
+    );
+
+    let (claims, token) = locked_state
+        .create_invite(
+            "test_room",
+            ViewerRole::ViewOnly,
+            Duration::from_secs(60),
+            2,
+        )
+        .unwrap();
+    assert_eq!(claims.room, "test_room");
+
+    for _ in 0..2 {
+        let verified = locked_state.verify_invite(&token, "test_room").unwrap();
+        assert_eq!(verified.role, ViewerRole::ViewOnly);
+        locked_state.redeem_invite(&verified);
+    }
+    let err = locked_state.verify_invite(&token, "test_room").unwrap_err();
+    assert_eq!(err.to_string(), "Invite has already been used");
+}
+
+#[tokio::test]
+async fn test_invite_rejections() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let (tx, _rx) = unbounded();
+    start_room(
+        &mut locked_state,
+        "test_room",
+        &tx,
+        SocketAddr::from(([127, 0, 0, 1], 8080)),
+    );
+
+    assert!(locked_state
+        .create_invite(
+            "missing_room",
+            ViewerRole::Control,
+            Duration::from_secs(60),
+            1
+        )
+        .is_err());
+
+    assert!(locked_state
+        .create_invite(
+            "test_room",
+            ViewerRole::Control,
+            Duration::from_secs(u64::MAX),
+            1
+        )
+        .is_err());
+
+    let (expired_claims, expired) = locked_state
+        .create_invite("test_room", ViewerRole::Control, Duration::from_secs(0), 1)
+        .unwrap();
+    assert_eq!(
+        locked_state
+            .verify_invite(&expired, "test_room")
+            .unwrap_err()
+            .to_string(),
+        "Invite has expired"
+    );
+
+    let (claims, token) = locked_state
+        .create_invite("test_room", ViewerRole::Control, Duration::from_secs(60), 1)
+        .unwrap();
+    // Creating an invite drops the records of expired ones
+    assert!(!locked_state.invites.contains_key(&expired_claims.id));
+    assert!(locked_state.verify_invite(&token, "other_room").is_err());
+
+    let mut tampered = token.clone();
+    tampered.insert(0, 'x');
+    assert_eq!(
+        locked_state
+            .verify_invite(&tampered, "test_room")
+            .unwrap_err()
+            .to_string(),
+        "Invalid invite signature"
+    );
+
+    locked_state
+        .revoke_invite(&claims.id, Some("test_room"))
+        .unwrap();
+    assert_eq!(
+        locked_state
+            .verify_invite(&token, "test_room")
+            .unwrap_err()
+            .to_string(),
+        "Invite has been revoked"
+    );
+}
+
+#[tokio::test]
+async fn test_handle_message_join_with_invite() {
+    let state = State::new();
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let viewer_addr = SocketAddr::from(([127, 0, 0, 1], 8081));
+    let (host_tx, mut host_rx) = unbounded();
+    let (viewer_tx, _viewer_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    start_room(&mut locked_state, "test_room", &host_tx, host_addr);
+
+    let create = SignallerMessage::CreateInvite {
+        room: "test_room".to_string(),
+        role: ViewerRole::ViewOnly,
+        ttl_secs: Some(1800),
+        max_uses: Some(1),
+    };
+    let create = serde_json::to_string(&create).unwrap();
+
+    // Only the host may mint invites for its room
+    assert!(
+        handle_message(&mut locked_state, &viewer_tx, &create, viewer_addr)
+            .await
+            .is_err()
+    );
+    handle_message(&mut locked_state, &host_tx, &create, host_addr)
+        .await
+        .unwrap();
+
+    let response = host_rx.try_next().unwrap().unwrap();
+    let token = match serde_json::from_str(response.to_text().unwrap()).unwrap() {
+        SignallerMessage::InviteCreated { token, .. } => token,
+        other => panic!("Unexpected response: {:?}", other),
+    };
+
+    let join = SignallerMessage::Join {
+        from: "viewer1".to_string(),
+        room: "test_room".to_string(),
+        invite: Some(token),
+        client: Default::default(),
+    };
+    handle_message(
+        &mut locked_state,
+        &viewer_tx,
+        &serde_json::to_string(&join).unwrap(),
+        viewer_addr,
+    )
+    .await
+    .unwrap();
+
+    assert!(matches!(
+        locked_state.peers["viewer1"].peer_type,
+        PeerType::Viewer {
+            role: ViewerRole::ViewOnly
+        }
+    ));
+}
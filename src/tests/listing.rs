@@ -0,0 +1,163 @@
+use std::net::SocketAddr;
+
+use futures_channel::mpsc::unbounded;
+
+use crate::{
+    models::{
+        filter::AnyOf,
+        listing::{RoomQuery, SortKey, SortOrder},
+        rtc::SignallerMessage,
+        state::State,
+    },
+    services::websocket::handle_message,
+};
+
+use super::{add_room, drain};
+
+fn rooms(state: &State, query: &RoomQuery) -> Vec<String> {
+    state
+        .get_available_rooms(query, None)
+        .unwrap()
+        .rooms
+        .into_iter()
+        .map(|info| info.room)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_sorting() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    add_room(&mut locked_state, "room1", "Charlie", "linux", 8001);
+    add_room(&mut locked_state, "room2", "alpha", "windows", 8002);
+    add_room(&mut locked_state, "room3", "Bravo", "macos", 8003);
+    for viewer in ["a", "b"] {
+        let (tx, _rx) = unbounded();
+        locked_state
+            .add_viewer(viewer.to_string(), "room3".to_string(), tx)
+            .unwrap();
+    }
+
+    let sorted = |sort_by, sort| RoomQuery {
+        sort_by: Some(sort_by),
+        sort,
+        ..Default::default()
+    };
+    assert_eq!(
+        rooms(&locked_state, &sorted(SortKey::Name, None)),
+        ["room2", "room3", "room1"]
+    );
+    assert_eq!(
+        rooms(&locked_state, &sorted(SortKey::Name, Some(SortOrder::Desc))),
+        ["room1", "room3", "room2"]
+    );
+    assert_eq!(
+        rooms(&locked_state, &sorted(SortKey::Os, None)),
+        ["room1", "room3", "room2"]
+    );
+    // Ties are broken by room ID, reversed along with the order
+    assert_eq!(
+        rooms(
+            &locked_state,
+            &sorted(SortKey::ViewerCount, Some(SortOrder::Desc))
+        ),
+        ["room3", "room2", "room1"]
+    );
+
+    // Started one after the other, so the first room has the longest uptime
+    for (room, micros) in [("room1", 1), ("room2", 2), ("room3", 3)] {
+        locked_state.sessions.get_mut(room).unwrap().start_time =
+            std::time::UNIX_EPOCH + std::time::Duration::from_micros(micros);
+        locked_state.index_room(room);
+    }
+    assert_eq!(
+        rooms(
+            &locked_state,
+            &sorted(SortKey::Uptime, Some(SortOrder::Desc))
+        ),
+        ["room1", "room2", "room3"]
+    );
+    assert_eq!(
+        rooms(&locked_state, &RoomQuery::default()),
+        ["room3", "room2", "room1"]
+    );
+}
+
+#[tokio::test]
+async fn test_cursor_pagination_is_stable() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    for (index, name) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+        add_room(&mut locked_state, name, name, "linux", 8000 + index as u16);
+    }
+    let mut query = RoomQuery {
+        sort_by: Some(SortKey::Name),
+        per_page: Some(2),
+        ..Default::default()
+    };
+
+    let first = locked_state.get_available_rooms(&query, None).unwrap();
+    assert_eq!(first.total_count, 5);
+    let names: Vec<_> = first.rooms.iter().map(|info| info.room.as_str()).collect();
+    assert_eq!(names, ["a", "b"]);
+
+    // Rooms coming and going before the cursor do not shift the next page
+    locked_state.leave_session("a".to_string()).unwrap();
+    add_room(&mut locked_state, "aa", "aa", "linux", 8100);
+    query.cursor = first.next_cursor;
+    let second = locked_state.get_available_rooms(&query, None).unwrap();
+    let names: Vec<_> = second.rooms.iter().map(|info| info.room.as_str()).collect();
+    assert_eq!(names, ["c", "d"]);
+
+    query.cursor = second.next_cursor;
+    let last = locked_state.get_available_rooms(&query, None).unwrap();
+    assert_eq!(last.rooms.len(), 1);
+    assert_eq!(last.next_cursor, None);
+
+    // A cursor only works with the order it was issued for
+    query.sort = Some(SortOrder::Desc);
+    assert!(locked_state.get_available_rooms(&query, None).is_err());
+    query.cursor = Some("garbage".to_string());
+    assert!(locked_state.get_available_rooms(&query, None).is_err());
+
+    // Pages far past the end are empty rather than overflowing
+    query.cursor = None;
+    query.page = Some(usize::MAX);
+    let beyond = locked_state.get_available_rooms(&query, None).unwrap();
+    assert!(beyond.rooms.is_empty());
+    assert_eq!(beyond.total_count, 5);
+}
+
+#[tokio::test]
+async fn test_multi_value_filter_over_websocket() {
+    let state = State::new();
+    let (tx, mut rx) = unbounded();
+    let mut locked_state = state.lock().await;
+    add_room(&mut locked_state, "room1", "one", "Linux", 8001);
+    add_room(&mut locked_state, "room2", "two", "windows", 8002);
+    add_room(&mut locked_state, "room3", "three", "macos", 8003);
+
+    let request = r#"{"type": "get_room_list", "os": ["linux", "Windows"], "sort_by": "name"}"#;
+    handle_message(
+        &mut locked_state,
+        &tx,
+        request,
+        SocketAddr::from(([127, 0, 0, 1], 9000)),
+    )
+    .await
+    .unwrap();
+    match &drain(&mut rx)[..] {
+        [SignallerMessage::RoomListResponse {
+            rooms, total_count, ..
+        }] => {
+            assert_eq!(*total_count, 2);
+            let names: Vec<_> = rooms.iter().map(|info| info.room.as_str()).collect();
+            assert_eq!(names, ["room1", "room2"]);
+        }
+        other => panic!("Unexpected messages: {:?}", other),
+    }
+
+    let query: RoomQuery = serde_json::from_str(r#"{"os": "linux", "name": null}"#).unwrap();
+    assert_eq!(query.filter.os, AnyOf(vec!["linux".to_string()]));
+    assert!(query.filter.name.is_empty());
+}
@@ -0,0 +1,111 @@
+use std::net::SocketAddr;
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Request, StatusCode},
+};
+use futures_channel::mpsc::unbounded;
+use tower::ServiceExt;
+
+use crate::{
+    args::Args,
+    models::{history::EndReason, metrics::lock_state, state::State},
+    routes::router::create_router,
+    services::websocket::handle_message,
+};
+use clap::Parser;
+
+fn start_message(room: &str) -> String {
+    format!(
+        r#"{{"type": "start", "room": "{}", "name": "host", "os": "linux", "version": "1.0", "control": true}}"#,
+        room
+    )
+}
+
+#[tokio::test]
+async fn test_counters() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let host = SocketAddr::from(([127, 0, 0, 1], 9001));
+    let (host_tx, _host_rx) = unbounded();
+    locked_state.register_connection(host, host_tx.clone(), None);
+    handle_message(&mut locked_state, &host_tx, &start_message("kiosk1"), host)
+        .await
+        .unwrap();
+
+    let viewer = SocketAddr::from(([127, 0, 0, 1], 9002));
+    let (tx, _rx) = unbounded();
+    locked_state.register_connection(viewer, tx.clone(), None);
+    for room in ["kiosk1", "missing", "missing"] {
+        let join = format!(r#"{{"type": "join", "room": "{}", "from": "v1"}}"#, room);
+        let _ = handle_message(&mut locked_state, &tx, &join, viewer).await;
+    }
+    let offer = r#"{"type": "offer", "to": "nobody", "from": "v1"}"#;
+    assert!(handle_message(&mut locked_state, &tx, offer, viewer)
+        .await
+        .is_err());
+    let ice = r#"{"type": "ice_servers", "id": "v1"}"#;
+    handle_message(&mut locked_state, &tx, ice, viewer)
+        .await
+        .unwrap();
+    // Not a SignallerMessage, so not counted
+    let _ = handle_message(&mut locked_state, &tx, r#"{"type": "bogus"}"#, viewer).await;
+
+    let metrics = &locked_state.metrics;
+    assert_eq!(metrics.messages_received["start"], 1);
+    assert_eq!(metrics.messages_received["join"], 3);
+    assert_eq!(metrics.messages_received["ice_servers"], 1);
+    assert!(!metrics.messages_received.contains_key("bogus"));
+    assert_eq!(metrics.join_declines.values().sum::<u64>(), 2);
+    assert_eq!(metrics.forward_failures, 1);
+    assert_eq!(metrics.ice_requests, 1);
+
+    let rendered = locked_state.render_metrics();
+    assert!(rendered.contains("remo_sessions 1\n"));
+    assert!(rendered.contains("remo_viewers 1\n"));
+    assert!(rendered.contains("remo_connections 2\n"));
+    assert!(rendered.contains("remo_messages_received_total{type=\"join\"} 3\n"));
+    assert!(rendered.contains("remo_ice_requests_total 1\n"));
+
+    locked_state.remove_session(&"kiosk1".to_string(), EndReason::Closed);
+    assert_eq!(locked_state.metrics.session_duration.count(), 1);
+    let rendered = locked_state.render_metrics();
+    assert!(rendered.contains("remo_sessions 0\n"));
+    assert!(rendered.contains("remo_session_duration_seconds_bucket{le=\"60\"} 1\n"));
+    assert!(rendered.contains("remo_session_duration_seconds_count 1\n"));
+}
+
+#[tokio::test]
+async fn test_lock_wait_is_recorded() {
+    let state = State::new();
+    drop(lock_state(&state).await);
+    drop(lock_state(&state).await);
+    assert_eq!(state.lock().await.metrics.lock_wait.count(), 2);
+}
+
+#[tokio::test]
+async fn test_metrics_endpoint() {
+    let app = create_router(State::new(), Args::parse_from(["program"]));
+    let response = app
+        .oneshot(
+            Request::get("/metrics")
+                .extension(axum::extract::ConnectInfo(SocketAddr::from((
+                    [127, 0, 0, 1],
+                    8080,
+                ))))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()[header::CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("# TYPE remo_sessions gauge\n"));
+    assert!(body.contains("# TYPE remo_state_lock_wait_seconds histogram\n"));
+    assert!(body.contains("remo_state_lock_wait_seconds_count 1\n"));
+}
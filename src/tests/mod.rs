@@ -1,6 +1,131 @@
+mod account;
+mod admin;
 mod args;
+mod audit;
+mod ban;
+mod catalog;
+mod connection;
+mod control;
+mod device;
+mod events;
+mod group;
 mod health;
+mod history;
+mod invite;
+mod listing;
+mod metrics;
 mod middleware;
+mod oidc;
+mod queue;
+mod registry;
+mod room;
 mod rtc;
+mod search;
 mod state;
+mod totp;
+mod view;
+mod visibility;
 mod websocket;
+
+use std::net::SocketAddr;
+
+use axum::extract::ws::Message;
+use futures_channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    models::{account::AccountRole, rtc::SignallerMessage, state::State},
+    services::websocket::handle_message,
+};
+
+type Tx = UnboundedSender<Message>;
+
+/// All messages queued for a connection, oldest first.
+fn drain(rx: &mut UnboundedReceiver<Message>) -> Vec<SignallerMessage> {
+    let mut messages = vec![];
+    while let Ok(Some(message)) = rx.try_next() {
+        messages.push(serde_json::from_str(message.to_text().unwrap()).unwrap());
+    }
+    messages
+}
+
+/// The oldest message queued for a connection, which must exist.
+fn next_message(rx: &mut UnboundedReceiver<Message>) -> SignallerMessage {
+    let message = rx.try_next().unwrap().unwrap();
+    serde_json::from_str(message.to_text().unwrap()).unwrap()
+}
+
+/// Handle `msg` as if it came from the connection at `socket_addr`.
+async fn send(
+    state: &mut State,
+    tx: &Tx,
+    msg: SignallerMessage,
+    socket_addr: SocketAddr,
+) -> Result<(), failure::Error> {
+    handle_message(
+        state,
+        tx,
+        &serde_json::to_string(&msg).unwrap(),
+        socket_addr,
+    )
+    .await
+}
+
+/// A `start` message for `room` with the default settings.
+fn start_message(room: &str) -> SignallerMessage {
+    SignallerMessage::Start {
+        room: room.to_string(),
+        name: "test_name".to_string(),
+        os: "test_os".to_string(),
+        version: "1.0".to_string(),
+        control: true,
+        device_secret: None,
+        max_viewers: None,
+        visibility: None,
+        tags: Default::default(),
+    }
+}
+
+/// Start `room` hosted by `tx`, with the default settings.
+fn start_room(state: &mut State, room: &str, tx: &Tx, socket_addr: SocketAddr) {
+    state
+        .add_server(
+            room.to_string(),
+            "test_name".to_string(),
+            "test_os".to_string(),
+            "1.0".to_string(),
+            true,
+            tx.clone(),
+            socket_addr,
+        )
+        .unwrap();
+}
+
+/// Start `room` with the given name and OS, from a host nobody listens to.
+fn add_room(state: &mut State, room: &str, name: &str, os: &str, port: u16) {
+    let (tx, _rx) = unbounded();
+    state
+        .add_server(
+            room.to_string(),
+            name.to_string(),
+            os.to_string(),
+            "1.0".to_string(),
+            true,
+            tx,
+            SocketAddr::from(([127, 0, 0, 1], port)),
+        )
+        .unwrap();
+}
+
+/// Create `username` with `role` and no usable password, for tests that need
+/// an account rather than the registration flow of `services::auth`.
+fn add_account(state: &mut State, username: &str, role: AccountRole) {
+    let mut account = state.create_account(username, String::new()).unwrap();
+    account.role = role;
+    state.save_account(&account).unwrap();
+}
+
+/// Create `username` with `role` and return a session token for it.
+fn sign_in(state: &mut State, username: &str, role: AccountRole) -> String {
+    add_account(state, username, role);
+    state.issue_token(username)
+}
@@ -0,0 +1,139 @@
+use std::net::SocketAddr;
+
+use futures_channel::mpsc::unbounded;
+
+use crate::{
+    models::{queue::Admission, rtc::SignallerMessage, state::State},
+    services::websocket::handle_message,
+};
+
+use super::{drain, start_room, Tx};
+
+async fn join(state: &mut State, tx: &Tx, from: &str) {
+    let msg = SignallerMessage::Join {
+        from: from.to_string(),
+        room: "test_room".to_string(),
+        invite: None,
+        client: Default::default(),
+    };
+    handle_message(
+        state,
+        tx,
+        &serde_json::to_string(&msg).unwrap(),
+        SocketAddr::from(([127, 0, 0, 1], 9000)),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_capacity_queue_and_promotion() {
+    let state = State::new();
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let (host_tx, mut host_rx) = unbounded();
+    let viewers: Vec<_> = (0..4).map(|_| unbounded()).collect();
+    let (txs, mut rxs): (Vec<_>, Vec<_>) = viewers.into_iter().unzip();
+
+    let mut locked_state = state.lock().await;
+    let start = SignallerMessage::Start {
+        room: "test_room".to_string(),
+        name: "test_name".to_string(),
+        os: "test_os".to_string(),
+        version: "1.0".to_string(),
+        control: true,
+        device_secret: None,
+        max_viewers: Some(2),
+        visibility: None,
+        tags: Default::default(),
+    };
+    handle_message(
+        &mut locked_state,
+        &host_tx,
+        &serde_json::to_string(&start).unwrap(),
+        host_addr,
+    )
+    .await
+    .unwrap();
+    drain(&mut host_rx);
+
+    for (index, tx) in txs.iter().enumerate() {
+        join(&mut locked_state, tx, &format!("viewer{}", index)).await;
+    }
+    assert_eq!(locked_state.sessions["test_room"].viewers.len(), 2);
+    assert_eq!(drain(&mut host_rx).len(), 2);
+    for (index, expected) in [(2, 1), (3, 2)] {
+        match &drain(&mut rxs[index])[..] {
+            [SignallerMessage::Queued { position, .. }] => assert_eq!(*position, expected),
+            other => panic!("Unexpected messages: {:?}", other),
+        }
+    }
+
+    let listing = locked_state
+        .get_available_rooms(&Default::default(), None)
+        .unwrap();
+    assert_eq!(listing.rooms[0].max_viewers, Some(2));
+    assert_eq!(listing.rooms[0].queue_length, 2);
+
+    // A free slot admits the head of the queue and moves everyone up
+    locked_state.leave_session("viewer0".to_string()).unwrap();
+    assert!(locked_state.sessions["test_room"]
+        .viewers
+        .contains("viewer2"));
+    match &drain(&mut host_rx)[..] {
+        [SignallerMessage::Join { from, .. }] => assert_eq!(from, "viewer2"),
+        other => panic!("Unexpected messages: {:?}", other),
+    }
+    match &drain(&mut rxs[3])[..] {
+        [SignallerMessage::Queued { position, .. }] => assert_eq!(*position, 1),
+        other => panic!("Unexpected messages: {:?}", other),
+    }
+
+    // Leaving the queue just drops the entry
+    locked_state.leave_session("viewer3".to_string()).unwrap();
+    assert!(locked_state.sessions["test_room"].waiting.is_empty());
+}
+
+#[tokio::test]
+async fn test_promotion_skips_closed_connections() {
+    let state = State::new();
+    let (host_tx, _host_rx) = unbounded();
+    let (viewer_tx, _viewer_rx) = unbounded();
+    let (gone_tx, gone_rx) = unbounded();
+    let (waiting_tx, _waiting_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    start_room(
+        &mut locked_state,
+        "test_room",
+        &host_tx,
+        SocketAddr::from(([127, 0, 0, 1], 8080)),
+    );
+    locked_state
+        .sessions
+        .get_mut("test_room")
+        .unwrap()
+        .max_viewers = Some(1);
+
+    let admit = |state: &mut State, id: &str, tx: &Tx| {
+        state.add_viewer(id.to_string(), "test_room".to_string(), tx.clone())
+    };
+    assert_eq!(
+        admit(&mut locked_state, "viewer1", &viewer_tx).unwrap(),
+        Admission::Admitted
+    );
+    assert_eq!(
+        admit(&mut locked_state, "gone", &gone_tx).unwrap(),
+        Admission::Queued { position: 1 }
+    );
+    assert!(admit(&mut locked_state, "gone", &gone_tx).is_err());
+    assert_eq!(
+        admit(&mut locked_state, "waiting", &waiting_tx).unwrap(),
+        Admission::Queued { position: 2 }
+    );
+
+    drop(gone_rx);
+    locked_state.leave_session("viewer1".to_string()).unwrap();
+    let viewers = &locked_state.sessions["test_room"].viewers;
+    assert!(viewers.contains("waiting"));
+    assert!(!viewers.contains("gone"));
+}
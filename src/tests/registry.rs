@@ -0,0 +1,233 @@
+use std::net::{IpAddr, SocketAddr};
+
+use futures_channel::mpsc::unbounded;
+
+use crate::models::{
+    account::{now_secs, AccountRole},
+    listing::RoomQuery,
+    registry::{DeviceStatus, KnownDevice, DEVICE_REGISTRY_TREE, UNPAIRED_DEVICE_TTL},
+    room::Visibility,
+    rtc::SignallerMessage,
+    state::{RoomInfo, State},
+    store::Store,
+};
+
+use super::{add_account, drain, send};
+
+/// Start `room` from a connection with a real IP and return its address.
+async fn start(state: &mut State, room: &str, version: &str, port: u16) -> SocketAddr {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let (tx, _rx) = unbounded();
+    let ip: IpAddr = format!("203.0.113.{}", port % 256).parse().unwrap();
+    state.register_connection(addr, tx.clone(), Some(ip));
+    send(
+        state,
+        &tx,
+        SignallerMessage::Start {
+            room: room.to_string(),
+            name: format!("{} host", room),
+            os: "linux".to_string(),
+            version: version.to_string(),
+            control: true,
+            device_secret: None,
+            max_viewers: None,
+            visibility: None,
+            tags: Default::default(),
+        },
+        addr,
+    )
+    .await
+    .unwrap();
+    addr
+}
+
+fn offline(state: &State, account: Option<&str>) -> Vec<RoomInfo> {
+    let query = RoomQuery {
+        include_offline: Some(true),
+        ..Default::default()
+    };
+    state
+        .get_available_rooms(&query, account)
+        .unwrap()
+        .offline_rooms
+}
+
+#[tokio::test]
+async fn test_offline_devices_are_listed() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let addr = start(&mut locked_state, "kiosk1", "1.0", 9001).await;
+    start(&mut locked_state, "kiosk2", "1.0", 9002).await;
+    assert!(offline(&locked_state, None).is_empty());
+
+    locked_state.on_disconnect(&addr);
+    let rooms = offline(&locked_state, None);
+    assert_eq!(rooms.len(), 1);
+    assert_eq!(rooms[0].room, "kiosk1");
+    assert_eq!(rooms[0].status, DeviceStatus::Offline);
+    assert_eq!(rooms[0].version, "1.0");
+    assert!(rooms[0].last_seen.is_some());
+    assert_eq!(rooms[0].last_session_secs, Some(0));
+    // The last IP is only shown to admins
+    assert_eq!(rooms[0].server_ip, None);
+
+    // Online rooms are unchanged, and offline ones are left out by default
+    let page = locked_state
+        .get_available_rooms(&Default::default(), None)
+        .unwrap();
+    assert_eq!(page.rooms.len(), 1);
+    assert_eq!(page.rooms[0].status, DeviceStatus::Online);
+    assert!(page.offline_rooms.is_empty());
+
+    // Filters apply to offline devices too
+    let query = RoomQuery {
+        include_offline: Some(true),
+        filter: serde_json::from_str(r#"{"version": "2.0"}"#).unwrap(),
+        ..Default::default()
+    };
+    assert!(locked_state
+        .get_available_rooms(&query, None)
+        .unwrap()
+        .offline_rooms
+        .is_empty());
+
+    add_account(&mut locked_state, "admin", AccountRole::Admin);
+    let rooms = offline(&locked_state, Some("admin"));
+    assert_eq!(rooms[0].server_ip, Some("203.0.113.41".parse().unwrap()));
+}
+
+#[tokio::test]
+async fn test_offline_devices_are_paged() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    for index in 0..5 {
+        let room = format!("kiosk{}", index);
+        let addr = start(&mut locked_state, &room, "1.0", 9001 + index).await;
+        locked_state.on_disconnect(&addr);
+    }
+    start(&mut locked_state, "live", "1.0", 9010).await;
+
+    let mut query = RoomQuery {
+        include_offline: Some(true),
+        per_page: Some(3),
+        ..Default::default()
+    };
+    let page = locked_state.get_available_rooms(&query, None).unwrap();
+    let mut rooms: Vec<String> = page.offline_rooms.iter().map(|r| r.room.clone()).collect();
+    assert_eq!(rooms.len(), 3);
+    assert_eq!(page.rooms.len(), 1);
+
+    // Offline devices page on their own cursor, whatever page of rooms
+    query.page = Some(2);
+    query.offline_cursor = page.next_offline_cursor;
+    let page = locked_state.get_available_rooms(&query, None).unwrap();
+    rooms.extend(page.offline_rooms.iter().map(|r| r.room.clone()));
+    rooms.sort();
+    assert_eq!(rooms, ["kiosk0", "kiosk1", "kiosk2", "kiosk3", "kiosk4"]);
+    assert!(page.next_offline_cursor.is_none());
+    assert!(page.rooms.is_empty());
+
+    query.page = Some(2);
+    query.offline_cursor = None;
+    let page = locked_state.get_available_rooms(&query, None).unwrap();
+    assert!(page.offline_rooms.is_empty());
+
+    query.offline_cursor = Some("garbage".to_string());
+    assert!(locked_state.get_available_rooms(&query, None).is_err());
+}
+
+#[tokio::test]
+async fn test_unpaired_devices_expire() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let addr = start(&mut locked_state, "kiosk1", "1.0", 9001).await;
+    locked_state.on_disconnect(&addr);
+    let addr = start(&mut locked_state, "kiosk2", "1.0", 9002).await;
+    locked_state.on_disconnect(&addr);
+    let long_ago = now_secs() - UNPAIRED_DEVICE_TTL.as_secs() - 1;
+    for room in ["kiosk1", "kiosk2"] {
+        locked_state.known_devices.get_mut(room).unwrap().last_seen = long_ago;
+    }
+    locked_state.known_devices.get_mut("kiosk2").unwrap().owner = Some("alice".to_string());
+
+    locked_state.registry_pruned_at = 0;
+    start(&mut locked_state, "kiosk3", "1.0", 9003).await;
+    assert!(!locked_state.known_devices.contains_key("kiosk1"));
+    assert!(locked_state
+        .store
+        .get::<KnownDevice>(DEVICE_REGISTRY_TREE, "kiosk1")
+        .unwrap()
+        .is_none());
+    // Paired devices are kept however long they were away
+    assert!(locked_state.known_devices.contains_key("kiosk2"));
+    assert!(locked_state.known_devices.contains_key("kiosk3"));
+}
+
+#[tokio::test]
+async fn test_registry_survives_restarts() {
+    let store = Store::temporary();
+    let first_seen = {
+        let state = State::with_store(store.clone());
+        let mut locked_state = state.lock().await;
+        let addr = start(&mut locked_state, "kiosk1", "1.0", 9001).await;
+        locked_state.on_disconnect(&addr);
+        locked_state.known_devices["kiosk1"].first_seen
+    };
+
+    let state = State::with_store(store);
+    let mut locked_state = state.lock().await;
+    let rooms = offline(&locked_state, None);
+    assert_eq!(rooms.len(), 1);
+    assert_eq!(rooms[0].room, "kiosk1");
+
+    // Coming back online keeps the first sighting but records the new version
+    start(&mut locked_state, "kiosk1", "2.0", 9003).await;
+    assert!(offline(&locked_state, None).is_empty());
+    let device = &locked_state.known_devices["kiosk1"];
+    assert_eq!(device.first_seen, first_seen);
+    assert_eq!(device.version, "2.0");
+    assert_eq!(device.last_ip, Some("203.0.113.43".parse().unwrap()));
+    assert_eq!(device.last_session_secs, Some(0));
+}
+
+#[tokio::test]
+async fn test_hidden_offline_devices() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let addr = start(&mut locked_state, "kiosk1", "1.0", 9001).await;
+    let session = locked_state.sessions.get_mut("kiosk1").unwrap();
+    session.owner = Some("alice".to_string());
+    session.visibility = Visibility::OwnerOnly;
+    locked_state.on_disconnect(&addr);
+
+    assert!(offline(&locked_state, None).is_empty());
+    assert!(offline(&locked_state, Some("bob")).is_empty());
+    let rooms = offline(&locked_state, Some("alice"));
+    assert_eq!(rooms.len(), 1);
+    assert_eq!(rooms[0].owner.as_deref(), Some("alice"));
+}
+
+#[tokio::test]
+async fn test_offline_devices_over_websocket() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let addr = start(&mut locked_state, "kiosk1", "1.0", 9001).await;
+    locked_state.on_disconnect(&addr);
+
+    let lobby = SocketAddr::from(([127, 0, 0, 1], 9100));
+    let (tx, mut rx) = unbounded();
+    locked_state.register_connection(lobby, tx.clone(), None);
+    send(
+        &mut locked_state,
+        &tx,
+        serde_json::from_str(r#"{"type": "get_room_list", "include_offline": true}"#).unwrap(),
+        lobby,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        &drain(&mut rx)[..],
+        [SignallerMessage::RoomListResponse { rooms, total_count: 0, offline_rooms, .. }]
+            if rooms.is_empty() && offline_rooms[0].room == "kiosk1"
+    ));
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use futures_channel::mpsc::unbounded;
+
+use crate::models::{
+    filter::{AnyOf, RoomFilter},
+    listing::RoomQuery,
+    room::RoomChanges,
+    rtc::SignallerMessage,
+    state::State,
+};
+
+use super::{drain, send, start_message, start_room};
+
+#[tokio::test]
+async fn test_update_room_in_place() {
+    let state = State::new();
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let other_addr = SocketAddr::from(([127, 0, 0, 1], 8081));
+    let viewer_addr = SocketAddr::from(([127, 0, 0, 1], 8082));
+    let (host_tx, mut host_rx) = unbounded();
+    let (viewer_tx, mut viewer_rx) = unbounded();
+    let (other_tx, mut other_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    start_room(&mut locked_state, "test_room", &host_tx, host_addr);
+    start_room(&mut locked_state, "other_room", &other_tx, other_addr);
+    locked_state
+        .add_viewer(
+            "viewer".to_string(),
+            "test_room".to_string(),
+            viewer_tx.clone(),
+        )
+        .unwrap();
+    locked_state.grant_control("test_room", "viewer").unwrap();
+    let other_connection = locked_state.connection_id(other_addr, &other_tx);
+    locked_state.subscribe_room_updates(other_connection, "all".to_string(), Default::default());
+    drain(&mut host_rx);
+    drain(&mut viewer_rx);
+
+    let tags = BTreeMap::from([("site".to_string(), "lab".to_string())]);
+    let update = || SignallerMessage::UpdateRoom {
+        changes: RoomChanges {
+            name: Some("renamed".to_string()),
+            // Unchanged, so not reported
+            version: Some("1.0".to_string()),
+            control: Some(false),
+            tags: Some(tags.clone()),
+            description: Some("Front desk".to_string()),
+        },
+    };
+
+    // Only the host may update its room
+    assert!(send(&mut locked_state, &viewer_tx, update(), viewer_addr)
+        .await
+        .is_err());
+    send(&mut locked_state, &host_tx, update(), host_addr)
+        .await
+        .unwrap();
+
+    let session = &locked_state.sessions["test_room"];
+    assert_eq!(session.name, "renamed");
+    assert!(!session.control);
+    assert_eq!(session.tags, tags);
+    assert_eq!(session.description, "Front desk");
+    assert!(session.viewers.contains("viewer"));
+    assert_eq!(session.controller, None);
+
+    let expected = RoomChanges {
+        name: Some("renamed".to_string()),
+        control: Some(false),
+        tags: Some(tags),
+        description: Some("Front desk".to_string()),
+        ..Default::default()
+    };
+    for rx in [&mut host_rx, &mut viewer_rx] {
+        let messages = drain(rx);
+        assert!(matches!(
+            messages[0],
+            SignallerMessage::ControlChanged {
+                controller: None,
+                ..
+            }
+        ));
+        match &messages[1..] {
+            [SignallerMessage::RoomUpdated { room, changes, .. }] => {
+                assert_eq!(room, "test_room");
+                assert_eq!(changes, &expected);
+            }
+            other => panic!("Unexpected messages: {:?}", other),
+        }
+    }
+    match &drain(&mut other_rx)[..] {
+        [SignallerMessage::RoomUpdated { changes, .. }] => assert_eq!(changes, &expected),
+        other => panic!("Unexpected messages: {:?}", other),
+    }
+
+    let query = RoomQuery {
+        filter: RoomFilter {
+            name: AnyOf(vec!["renamed".to_string()]),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let listing = locked_state.get_available_rooms(&query, None).unwrap();
+    assert_eq!(listing.rooms[0].description, "Front desk");
+}
+
+#[tokio::test]
+async fn test_update_room_validation() {
+    let state = State::new();
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let (host_tx, mut host_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    start_room(&mut locked_state, "test_room", &host_tx, host_addr);
+
+    let invalid = [
+        RoomChanges {
+            name: Some(" ".to_string()),
+            ..Default::default()
+        },
+        RoomChanges {
+            version: Some("v".repeat(100)),
+            ..Default::default()
+        },
+        RoomChanges {
+            description: Some("d".repeat(1000)),
+            ..Default::default()
+        },
+        RoomChanges {
+            tags: Some(BTreeMap::from([("bad key".to_string(), String::new())])),
+            ..Default::default()
+        },
+    ];
+    for changes in invalid {
+        assert!(locked_state.update_room("test_room", changes).is_err());
+    }
+    assert_eq!(locked_state.sessions["test_room"].name, "test_name");
+
+    // Nothing changed, nobody is notified
+    let applied = locked_state
+        .update_room(
+            "test_room",
+            RoomChanges {
+                name: Some("test_name".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(applied.is_empty());
+    assert!(drain(&mut host_rx).is_empty());
+}
+
+#[tokio::test]
+async fn test_start_validation() {
+    let state = State::new();
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let (host_tx, _host_rx) = unbounded();
+
+    let mut locked_state = state.lock().await;
+    // Start enforces the same limits as UpdateRoom
+    let long_name = "n".repeat(100);
+    let long_version = "v".repeat(100);
+    for (name, version) in [
+        (" ", "1.0"),
+        (long_name.as_str(), "1.0"),
+        ("test_name", ""),
+        ("test_name", long_version.as_str()),
+    ] {
+        let mut start = start_message("test_room");
+        if let SignallerMessage::Start {
+            name: start_name,
+            version: start_version,
+            ..
+        } = &mut start
+        {
+            *start_name = name.to_string();
+            *start_version = version.to_string();
+        }
+        assert!(send(&mut locked_state, &host_tx, start, host_addr)
+            .await
+            .is_err());
+    }
+    assert!(locked_state.sessions.is_empty());
+}
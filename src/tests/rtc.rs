@@ -8,6 +8,10 @@ fn test_signaller_message_serialization() {
         os: "test_os".to_string(),
         version: "1.0".to_string(),
         control: true,
+        device_secret: None,
+        max_viewers: None,
+        visibility: None,
+        tags: Default::default(),
     };
 
     let serialized = serde_json::to_string(&msg).unwrap();
@@ -20,6 +24,7 @@ fn test_signaller_message_serialization() {
             os,
             version,
             control,
+            ..
         } => {
             assert_eq!(room, "test_room");
             assert_eq!(name, "test_name");
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use futures_channel::mpsc::unbounded;
+
+use crate::{
+    models::{rtc::SignallerMessage, search::Highlight, state::State},
+    services::websocket::handle_message,
+};
+
+use super::{add_room, drain};
+
+fn ranking(state: &State, query: &str) -> Vec<String> {
+    state
+        .search_rooms(query, None, None)
+        .unwrap()
+        .into_iter()
+        .map(|hit| hit.info.room)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_search_ranking() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    add_room(
+        &mut locked_state,
+        "room1",
+        "kiosk-lobby-01",
+        "windows",
+        8001,
+    );
+    add_room(&mut locked_state, "room2", "frontdesk", "linux", 8002);
+    add_room(
+        &mut locked_state,
+        "room3",
+        "backoffice-kiosk",
+        "linux",
+        8003,
+    );
+    add_room(&mut locked_state, "kiosk", "printer", "linux", 8004);
+    let session = locked_state.sessions.get_mut("room2").unwrap();
+    session.tags = BTreeMap::from([("site".to_string(), "Berlin".to_string())]);
+    session.description = "Reception kiosk near the entrance".to_string();
+    locked_state.index_room("room2");
+
+    // The room ID and a name prefix come first, then a word inside a name,
+    // then a match in the description
+    assert_eq!(
+        ranking(&locked_state, "kiosk"),
+        ["kiosk", "room1", "room3", "room2"]
+    );
+
+    // Typos and swapped letters still match
+    assert_eq!(ranking(&locked_state, "frnotdesk"), ["room2"]);
+    assert_eq!(ranking(&locked_state, "kisok lobby"), ["room1"]);
+
+    // Initials match as a subsequence
+    assert_eq!(ranking(&locked_state, "fdsk"), ["room2"]);
+
+    // Every term has to match somewhere
+    assert_eq!(ranking(&locked_state, "berlin linux"), ["room2"]);
+    assert!(ranking(&locked_state, "berlin windows").is_empty());
+
+    assert!(locked_state.search_rooms("  ", None, None).is_err());
+    assert!(locked_state
+        .search_rooms(&"a".repeat(200), None, None)
+        .is_err());
+    assert_eq!(
+        locked_state
+            .search_rooms("kiosk", Some(2), None)
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_search_beyond_trigram_candidates() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    add_room(&mut locked_state, "room2", "frontdesk", "linux", 8002);
+    add_room(&mut locked_state, "dsk-9", "scanner", "linux", 8003);
+
+    // "dsk-9" shares a trigram with "fdsk", "frontdesk" only matches it as a
+    // subsequence and must not be lost
+    assert_eq!(
+        locked_state
+            .catalog
+            .search_candidates(&["fdsk".chars().collect()])
+            .map(|rooms| rooms.len()),
+        Some(1)
+    );
+    assert_eq!(ranking(&locked_state, "fdsk"), ["dsk-9", "room2"]);
+    // Same for typos
+    assert_eq!(ranking(&locked_state, "frnotdesk"), ["room2"]);
+}
+
+#[tokio::test]
+async fn test_search_candidates() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    add_room(&mut locked_state, "room1", "kiosk-lobby", "windows", 8001);
+    add_room(&mut locked_state, "room2", "frontdesk", "linux", 8002);
+    add_room(&mut locked_state, "room3", "printer", "linux", 8003);
+    let candidates = |query: &str| {
+        let terms: Vec<Vec<char>> = query
+            .split_whitespace()
+            .map(|t| t.chars().collect())
+            .collect();
+        locked_state.catalog.search_candidates(&terms).map(|rooms| {
+            let mut rooms: Vec<String> = rooms.into_iter().map(str::to_string).collect();
+            rooms.sort();
+            rooms
+        })
+    };
+
+    assert_eq!(
+        candidates("linux"),
+        Some(vec!["room2".into(), "room3".into()])
+    );
+    assert_eq!(candidates("linux desk"), Some(vec!["room2".into()]));
+    // Room IDs are indexed too
+    assert_eq!(candidates("room").map(|rooms| rooms.len()), Some(3));
+    // A term no room shares a trigram with does not narrow
+    assert_eq!(candidates("kisok lobby"), Some(vec!["room1".into()]));
+    assert_eq!(candidates("fdsk"), None);
+    assert_eq!(candidates("pr"), None);
+}
+
+#[tokio::test]
+async fn test_search_highlights() {
+    let state = State::new();
+    let (tx, mut rx) = unbounded();
+    let mut locked_state = state.lock().await;
+    add_room(&mut locked_state, "room1", "Kiosk-Lobby", "Windows", 8001);
+
+    let search = SignallerMessage::SearchRooms {
+        query: "lobby WINDOWS".to_string(),
+        limit: None,
+    };
+    handle_message(
+        &mut locked_state,
+        &tx,
+        &serde_json::to_string(&search).unwrap(),
+        SocketAddr::from(([127, 0, 0, 1], 9000)),
+    )
+    .await
+    .unwrap();
+
+    match &drain(&mut rx)[..] {
+        [SignallerMessage::SearchResults { query, results }] => {
+            assert_eq!(query, "lobby WINDOWS");
+            let hit = &results[0];
+            assert_eq!(hit.info.room, "room1");
+            assert!(hit.score > 0.0 && hit.score <= 1.0);
+            assert_eq!(
+                hit.highlights,
+                [
+                    Highlight {
+                        field: "name".to_string(),
+                        text: "Kiosk-Lobby".to_string(),
+                        spans: vec![(6, 11)],
+                    },
+                    Highlight {
+                        field: "os".to_string(),
+                        text: "Windows".to_string(),
+                        spans: vec![(0, 7)],
+                    },
+                ]
+            );
+        }
+        other => panic!("Unexpected messages: {:?}", other),
+    }
+}
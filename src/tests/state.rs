@@ -2,6 +2,8 @@ use futures_channel::mpsc::unbounded;
 use std::env;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
+use crate::models::filter::{AnyOf, RoomFilter};
+use crate::models::listing::{RoomQuery, SortOrder};
 use crate::models::state::State;
 
 #[tokio::test]
@@ -154,53 +156,46 @@ async fn test_get_available_rooms() {
         )
         .unwrap();
 
+    let query = |os: Option<&str>, name: &str| RoomQuery {
+        filter: RoomFilter {
+            os: AnyOf(os.into_iter().map(str::to_string).collect()),
+            version: AnyOf(vec!["1.0".to_string()]),
+            name: AnyOf(vec![name.to_string()]),
+            control: Some(true),
+            ..Default::default()
+        },
+        sort: Some(SortOrder::Desc),
+        page: Some(1),
+        per_page: Some(10),
+        ..Default::default()
+    };
+
     // Test with exact case match
-    let (rooms, total_count) = locked_state.get_available_rooms(
-        Some("Windows"),
-        Some("1.0"),
-        None,
-        Some("test"),
-        Some("desc"),
-        Some(true),
-        Some(1),
-        Some(10),
-    );
+    let listing = locked_state
+        .get_available_rooms(&query(Some("Windows"), "test"), None)
+        .unwrap();
 
-    assert_eq!(total_count, 1);
-    assert_eq!(rooms.len(), 1);
-    assert!(rooms.contains_key("room1"));
+    assert_eq!(listing.total_count, 1);
+    assert_eq!(listing.rooms.len(), 1);
+    assert_eq!(listing.rooms[0].room, "room1");
 
     // Test with case-insensitive OS filter
-    let (rooms, total_count) = locked_state.get_available_rooms(
-        Some("windows"), // lowercase, should still match "Windows"
-        Some("1.0"),
-        None,
-        Some("test"),
-        Some("desc"),
-        Some(true),
-        Some(1),
-        Some(10),
-    );
+    let listing = locked_state
+        .get_available_rooms(&query(Some("windows"), "test"), None) // lowercase, should still match "Windows"
+        .unwrap();
 
-    assert_eq!(total_count, 1);
-    assert_eq!(rooms.len(), 1);
-    assert!(rooms.contains_key("room1"));
+    assert_eq!(listing.total_count, 1);
+    assert_eq!(listing.rooms.len(), 1);
+    assert_eq!(listing.rooms[0].room, "room1");
 
     // Test case-insensitive name filter
-    let (rooms, total_count) = locked_state.get_available_rooms(
-        None,
-        Some("1.0"),
-        None,
-        Some("testname"), // lowercase, should match "TeStName"
-        Some("desc"),
-        Some(true),
-        Some(1),
-        Some(10),
-    );
+    let listing = locked_state
+        .get_available_rooms(&query(None, "testname"), None) // lowercase, should match "TeStName"
+        .unwrap();
 
-    assert_eq!(total_count, 1);
-    assert_eq!(rooms.len(), 1);
-    assert!(rooms.contains_key("room2"));
+    assert_eq!(listing.total_count, 1);
+    assert_eq!(listing.rooms.len(), 1);
+    assert_eq!(listing.rooms[0].room, "room2");
 }
 
 #[tokio::test]
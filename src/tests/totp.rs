@@ -0,0 +1,219 @@
+use data_encoding::BASE32_NOPAD;
+
+use crate::{
+    models::{
+        account::{now_secs, MAX_FAILED_LOGINS},
+        metrics::lock_state,
+        state::{State, StateType},
+        totp::{is_recovery_code, otpauth_uri, totp_code, verify_totp, TOTP_STEP_SECS},
+    },
+    services::auth,
+};
+
+#[test]
+fn test_totp_rfc6238_vectors() {
+    // SHA1 test vectors from RFC 6238 appendix B, truncated to 6 digits
+    let secret = b"12345678901234567890";
+    assert_eq!(totp_code(secret, 59 / TOTP_STEP_SECS), 287082);
+    assert_eq!(totp_code(secret, 1111111109 / TOTP_STEP_SECS), 81804);
+    assert_eq!(totp_code(secret, 1234567890 / TOTP_STEP_SECS), 5924);
+}
+
+#[test]
+fn test_verify_totp_skew_window() {
+    let secret = b"12345678901234567890";
+    let now = 1111111109;
+    let step = now / TOTP_STEP_SECS;
+    let code = |step| format!("{:06}", totp_code(secret, step));
+
+    assert_eq!(verify_totp(secret, &code(step), now), Some(step));
+    assert_eq!(verify_totp(secret, &code(step - 1), now), Some(step - 1));
+    assert_eq!(verify_totp(secret, &code(step + 1), now), Some(step + 1));
+    assert_eq!(verify_totp(secret, &code(step + 2), now), None);
+    assert_eq!(verify_totp(secret, "not a code", now), None);
+}
+
+#[test]
+fn test_otpauth_uri() {
+    let uri = otpauth_uri("alice", "JBSWY3DPEHPK3PXP");
+    assert!(uri.starts_with("otpauth://totp/remo-auth%3Aalice?secret=JBSWY3DPEHPK3PXP"));
+    assert!(uri.contains("issuer=remo-auth"));
+}
+
+fn current_code(secret: &str) -> String {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).unwrap();
+    format!("{:06}", totp_code(&secret, now_secs() / TOTP_STEP_SECS))
+}
+
+/// Register `username` and enable TOTP for it, returning the secret and the
+/// recovery codes.
+async fn enroll(state: &StateType, username: &str) -> (String, Vec<String>) {
+    let mut account = auth::register_account(state, username, "correct horse")
+        .await
+        .unwrap();
+    let (secret, _) = lock_state(state)
+        .await
+        .begin_totp_enrollment(&mut account)
+        .unwrap();
+    let recovery_codes = auth::confirm_totp_enrollment(state, &mut account, &current_code(&secret))
+        .await
+        .unwrap();
+    (secret, recovery_codes)
+}
+
+#[tokio::test]
+async fn test_totp_enrollment_and_login() {
+    let state = State::new();
+    let mut account = auth::register_account(&state, "alice", "correct horse")
+        .await
+        .unwrap();
+    let (secret, _) = lock_state(&state)
+        .await
+        .begin_totp_enrollment(&mut account)
+        .unwrap();
+    // Not enforced until confirmed
+    assert!(auth::login(&state, "alice", "correct horse", None)
+        .await
+        .is_ok());
+
+    assert!(
+        auth::confirm_totp_enrollment(&state, &mut account, "000000x")
+            .await
+            .is_err()
+    );
+    let recovery_codes =
+        auth::confirm_totp_enrollment(&state, &mut account, &current_code(&secret))
+            .await
+            .unwrap();
+    assert_eq!(recovery_codes.len(), 10);
+
+    assert_eq!(
+        auth::login(&state, "alice", "correct horse", None)
+            .await
+            .unwrap_err()
+            .to_string(),
+        "Two-factor code required"
+    );
+    // The step used to confirm can't be replayed
+    let used_step = lock_state(&state)
+        .await
+        .get_account("alice")
+        .unwrap()
+        .unwrap()
+        .totp_last_step;
+    let replayed = totp_code(&BASE32_NOPAD.decode(secret.as_bytes()).unwrap(), used_step);
+    assert!(auth::login(
+        &state,
+        "alice",
+        "correct horse",
+        Some(&format!("{:06}", replayed))
+    )
+    .await
+    .is_err());
+
+    // Recovery codes work once
+    assert!(
+        auth::login(&state, "alice", "correct horse", Some(&recovery_codes[0]))
+            .await
+            .is_ok()
+    );
+    assert!(
+        auth::login(&state, "alice", "correct horse", Some(&recovery_codes[0]))
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_concurrent_logins_with_one_code() {
+    let state = State::new();
+    let (secret, _) = enroll(&state, "alice").await;
+    // Make the current step usable again, it was used to confirm
+    {
+        let locked_state = lock_state(&state).await;
+        let mut account = locked_state.get_account("alice").unwrap().unwrap();
+        account.totp_last_step = now_secs() / TOTP_STEP_SECS - 2;
+        locked_state.save_account(&account).unwrap();
+    }
+
+    // Two logins that both read the account before either checked the code
+    let mut locked_state = lock_state(&state).await;
+    let mut first = locked_state.get_account("alice").unwrap().unwrap();
+    let mut second = first.clone();
+    let code = current_code(&secret);
+    assert!(locked_state.accept_totp(&mut first, &code).unwrap());
+    assert!(!locked_state.accept_totp(&mut second, &code).unwrap());
+}
+
+#[tokio::test]
+async fn test_recovery_codes_outside_lock() {
+    let state = State::new();
+    let (_, recovery_codes) = enroll(&state, "alice").await;
+    assert!(recovery_codes.iter().all(|code| is_recovery_code(code)));
+    assert!(!is_recovery_code("123456"));
+    assert!(!is_recovery_code("abcd_efgh"));
+    assert!(!is_recovery_code("abcd-efgh1"));
+    assert!(!is_recovery_code("abcd-efg1"));
+
+    let login = |code: &str| {
+        let state = state.clone();
+        let code = code.to_string();
+        async move { auth::login(&state, "alice", "correct horse", Some(&code)).await }
+    };
+    assert!(login(&recovery_codes[1]).await.is_ok());
+    assert!(login(&recovery_codes[1]).await.is_err());
+    assert!(login(&recovery_codes[1].to_uppercase()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_totp_failures_count_toward_lockout() {
+    let state = State::new();
+    let (_, recovery_codes) = enroll(&state, "alice").await;
+
+    for _ in 0..MAX_FAILED_LOGINS {
+        assert!(auth::login(&state, "alice", "correct horse", Some("wrong"))
+            .await
+            .is_err());
+    }
+    assert_eq!(
+        auth::login(&state, "alice", "correct horse", Some(&recovery_codes[0]))
+            .await
+            .unwrap_err()
+            .to_string(),
+        "Invalid username or password"
+    );
+}
+
+#[tokio::test]
+async fn test_totp_required_flag() {
+    let state = State::new();
+    auth::register_account(&state, "alice", "correct horse")
+        .await
+        .unwrap();
+    lock_state(&state)
+        .await
+        .set_totp_required("alice", true)
+        .unwrap();
+
+    assert_eq!(
+        auth::login(&state, "alice", "correct horse", None)
+            .await
+            .unwrap_err()
+            .to_string(),
+        "Two-factor enrollment required"
+    );
+
+    let mut account = lock_state(&state)
+        .await
+        .get_account("alice")
+        .unwrap()
+        .unwrap();
+    let (secret, _) = lock_state(&state)
+        .await
+        .begin_totp_enrollment(&mut account)
+        .unwrap();
+    auth::confirm_totp_enrollment(&state, &mut account, &current_code(&secret))
+        .await
+        .unwrap();
+    assert!(lock_state(&state).await.disable_totp(&mut account).is_err());
+}
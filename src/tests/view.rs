@@ -0,0 +1,339 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::extract::ws::Message;
+use clap::Parser;
+use futures_channel::mpsc::{unbounded, UnboundedReceiver};
+
+use crate::{
+    args::Args,
+    models::{
+        account::AccountRole,
+        peer::{ClientInfo, DeviceType, ViewerRole},
+        rtc::SignallerMessage,
+        state::{RoomInfo, State},
+        view::{Audience, RoomField, ViewerInfo},
+    },
+    services::websocket::handle_message,
+};
+
+use super::{add_account, drain, sign_in, Tx};
+
+fn ip(last: u8) -> IpAddr {
+    IpAddr::from([203, 0, 113, last])
+}
+
+/// Room `test_room` owned by alice, with a logged in viewer. Keeps the
+/// receivers alive.
+fn setup(state: &mut State) -> Vec<UnboundedReceiver<Message>> {
+    add_account(state, "root", AccountRole::Admin);
+    for username in ["alice", "bob"] {
+        add_account(state, username, AccountRole::User);
+    }
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let (host_tx, host_rx) = unbounded();
+    let (viewer_tx, viewer_rx) = unbounded();
+    state.register_connection(host_addr, host_tx.clone(), Some(ip(1)));
+    state
+        .add_server(
+            "test_room".to_string(),
+            "Front desk".to_string(),
+            "linux".to_string(),
+            "1.0".to_string(),
+            true,
+            host_tx,
+            host_addr,
+        )
+        .unwrap();
+    state.sessions.get_mut("test_room").unwrap().owner = Some("alice".to_string());
+    state.index_room("test_room");
+    state
+        .add_viewer_with_role(
+            "viewer".to_string(),
+            "test_room".to_string(),
+            viewer_tx,
+            ViewerRole::Control,
+            Some(ip(2)),
+        )
+        .unwrap();
+    state.peers.get_mut("viewer").unwrap().account = Some("bob".to_string());
+    vec![host_rx, viewer_rx]
+}
+
+fn listed(state: &State, account: Option<&str>) -> RoomInfo {
+    state
+        .get_available_rooms(&Default::default(), account)
+        .unwrap()
+        .rooms
+        .remove(0)
+}
+
+#[tokio::test]
+async fn test_room_info_by_role() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let _receivers = setup(&mut locked_state);
+
+    // Counts only, for anonymous callers and other accounts alike
+    for account in [None, Some("bob")] {
+        let info = listed(&locked_state, account);
+        assert_eq!(info.viewer_count, 1);
+        assert_eq!(info.name, "Front desk");
+        assert_eq!(info.server, None);
+        assert_eq!(info.viewers, None);
+        assert_eq!(info.owner, None);
+        assert_eq!(info.server_ip, None);
+        let json = serde_json::to_value(&info).unwrap();
+        assert!(json.get("viewers").is_none());
+        assert!(json.get("server").is_none());
+    }
+
+    let info = listed(&locked_state, Some("alice"));
+    assert_eq!(info.server.as_deref(), Some("test_room"));
+    assert_eq!(info.owner.as_deref(), Some("alice"));
+    assert_eq!(info.server_ip, None);
+    let viewers = info.viewers.unwrap();
+    assert_eq!(
+        viewers,
+        [ViewerInfo {
+            id: "viewer".to_string(),
+            client: ClientInfo {
+                display_name: Some("bob".to_string()),
+                ..Default::default()
+            },
+            account: Some("bob".to_string()),
+            joined_at: viewers[0].joined_at,
+            ip: None,
+        }]
+    );
+
+    let info = listed(&locked_state, Some("root"));
+    assert_eq!(info.server_ip, Some(ip(1)));
+    assert_eq!(info.viewers.unwrap()[0].ip, Some(ip(2)));
+
+    let hits = locked_state
+        .search_rooms("front", None, Some("bob"))
+        .unwrap();
+    assert_eq!(hits[0].info.viewers, None);
+}
+
+fn subscriber(
+    state: &mut State,
+    username: Option<&str>,
+    port: u16,
+) -> (SocketAddr, Tx, UnboundedReceiver<Message>) {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let (tx, rx) = unbounded();
+    state.register_connection(addr, tx.clone(), None);
+    if let Some(username) = username {
+        let token = state.issue_token(username);
+        state.authenticate_connection(addr, &token).unwrap();
+    }
+    (addr, tx, rx)
+}
+
+#[tokio::test]
+async fn test_room_events_by_role() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let _receivers = setup(&mut locked_state);
+    let mut subscribers = vec![];
+    for (username, port) in [(None, 9001), (Some("alice"), 9002), (Some("root"), 9003)] {
+        let (addr, tx, mut rx) = subscriber(&mut locked_state, username, port);
+        let subscribe = SignallerMessage::SubscribeRoomUpdates {
+            subscription: None,
+            since: None,
+            filter: Default::default(),
+        };
+        handle_message(
+            &mut locked_state,
+            &tx,
+            &serde_json::to_string(&subscribe).unwrap(),
+            addr,
+        )
+        .await
+        .unwrap();
+        let snapshot = match &drain(&mut rx)[..] {
+            [SignallerMessage::RoomSnapshot { rooms, .. }] => rooms[0].clone(),
+            other => panic!("Unexpected messages: {:?}", other),
+        };
+        subscribers.push((snapshot, rx));
+    }
+    assert_eq!(subscribers[0].0.viewers, None);
+    assert_eq!(subscribers[1].0.viewers.as_ref().unwrap()[0].ip, None);
+    assert_eq!(
+        subscribers[2].0.viewers.as_ref().unwrap()[0].ip,
+        Some(ip(2))
+    );
+
+    locked_state.publish_room_added("test_room");
+    let added: Vec<RoomInfo> = subscribers
+        .iter_mut()
+        .map(|(_, rx)| match &drain(rx)[..] {
+            [SignallerMessage::RoomAdded { info, .. }] => info.clone(),
+            other => panic!("Unexpected messages: {:?}", other),
+        })
+        .collect();
+    assert_eq!(added[0].server, None);
+    assert_eq!(added[1].server.as_deref(), Some("test_room"));
+    assert_eq!(added[1].server_ip, None);
+    assert_eq!(added[2].server_ip, Some(ip(1)));
+}
+
+#[tokio::test]
+async fn test_caller_role_cached_on_connection() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let token = sign_in(&mut locked_state, "root", AccountRole::Admin);
+    let addr = SocketAddr::from(([127, 0, 0, 1], 9001));
+    let (tx, _rx) = unbounded();
+    locked_state.register_connection(addr, tx, None);
+    locked_state.authenticate_connection(addr, &token).unwrap();
+    assert_eq!(
+        locked_state.connections[&addr].role,
+        Some(AccountRole::Admin)
+    );
+    assert!(
+        locked_state
+            .caller_of(&locked_state.connections[&addr])
+            .admin
+    );
+
+    // Demotion applies to the open connection right away, as does a
+    // promotion
+    locked_state
+        .set_account_role("root", AccountRole::User)
+        .unwrap();
+    assert!(
+        !locked_state
+            .caller_of(&locked_state.connections[&addr])
+            .admin
+    );
+    locked_state
+        .set_account_role("root", AccountRole::Admin)
+        .unwrap();
+    assert!(
+        locked_state
+            .caller_of(&locked_state.connections[&addr])
+            .admin
+    );
+
+    locked_state.logout(&token);
+    assert_eq!(locked_state.connections[&addr].role, None);
+    let caller = locked_state.caller_of(&locked_state.connections[&addr]);
+    assert!(!caller.admin);
+    assert_eq!(caller.account, None);
+}
+
+#[tokio::test]
+async fn test_configured_projection() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let _receivers = setup(&mut locked_state);
+    locked_state
+        .projection
+        .set_fields(Audience::Anonymous, [RoomField::Viewers]);
+    locked_state
+        .projection
+        .set_fields(Audience::Owner, [RoomField::Owner]);
+
+    let info = listed(&locked_state, None);
+    assert_eq!(info.viewers.unwrap()[0].id, "viewer");
+    assert_eq!(info.server, None);
+    assert_eq!(info.owner, None);
+
+    let info = listed(&locked_state, Some("alice"));
+    assert_eq!(info.viewers, None);
+    assert_eq!(info.owner.as_deref(), Some("alice"));
+
+    let args = Args::parse_from(["program", "--anonymous-fields", "viewers,server"]);
+    assert_eq!(
+        args.anonymous_fields,
+        Some(vec![RoomField::Viewers, RoomField::Server])
+    );
+    assert!(Args::try_parse_from(["program", "--owner-fields", "secrets"]).is_err());
+}
+
+#[tokio::test]
+async fn test_viewer_client_metadata() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let mut receivers = setup(&mut locked_state);
+    locked_state
+        .sessions
+        .get_mut("test_room")
+        .unwrap()
+        .max_viewers = Some(2);
+    let (tx, mut rx) = unbounded();
+    let (queued_tx, _queued_rx) = unbounded();
+
+    let join = |from: &str, display_name: &str| SignallerMessage::Join {
+        from: from.to_string(),
+        room: "test_room".to_string(),
+        invite: None,
+        client: ClientInfo {
+            display_name: Some(display_name.to_string()),
+            client_os: Some("Android 14".to_string()),
+            app_version: Some("2.3.1".to_string()),
+            device_type: Some(DeviceType::Tablet),
+        },
+    };
+    let send = |msg: SignallerMessage| serde_json::to_string(&msg).unwrap();
+    let addr = SocketAddr::from(([127, 0, 0, 1], 9001));
+
+    // Over-long names are declined
+    let long = send(join("long", &"n".repeat(100)));
+    handle_message(&mut locked_state, &tx, &long, addr)
+        .await
+        .unwrap();
+    assert!(matches!(
+        &drain(&mut rx)[..],
+        [SignallerMessage::JoinDeclined { .. }]
+    ));
+
+    handle_message(
+        &mut locked_state,
+        &tx,
+        &send(join("tablet", "Lobby tablet")),
+        addr,
+    )
+    .await
+    .unwrap();
+    // The host sees who is connecting
+    match &drain(&mut receivers[0])[..] {
+        [SignallerMessage::Join { from, client, .. }] => {
+            assert_eq!(from, "tablet");
+            assert_eq!(client.display_name.as_deref(), Some("Lobby tablet"));
+        }
+        other => panic!("Unexpected messages: {:?}", other),
+    }
+
+    // Metadata survives waiting in the queue
+    handle_message(
+        &mut locked_state,
+        &queued_tx,
+        &send(join("queued", "Waiting phone")),
+        SocketAddr::from(([127, 0, 0, 1], 9002)),
+    )
+    .await
+    .unwrap();
+    locked_state.leave_session("tablet".to_string()).unwrap();
+    match &drain(&mut receivers[0])[..] {
+        [SignallerMessage::Join { from, client, .. }] => {
+            assert_eq!(from, "queued");
+            assert_eq!(client.device_type, Some(DeviceType::Tablet));
+        }
+        other => panic!("Unexpected messages: {:?}", other),
+    }
+
+    let info = listed(&locked_state, Some("alice"));
+    assert!(info.start_time > 0);
+    let viewers = info.viewers.unwrap();
+    let queued = viewers.iter().find(|viewer| viewer.id == "queued").unwrap();
+    assert_eq!(queued.client.display_name.as_deref(), Some("Waiting phone"));
+    assert_eq!(queued.client.client_os.as_deref(), Some("Android 14"));
+    assert!(queued.joined_at >= info.start_time);
+
+    let info = listed(&locked_state, None);
+    assert!(info.start_time > 0);
+    assert_eq!(info.viewers, None);
+}
@@ -0,0 +1,206 @@
+use std::net::SocketAddr;
+
+use axum::extract::ws::Message;
+use futures_channel::mpsc::{unbounded, UnboundedReceiver};
+
+use crate::models::{account::AccountRole, room::Visibility, rtc::SignallerMessage, state::State};
+
+use super::{drain, send, sign_in, Tx};
+
+fn add_room(
+    state: &mut State,
+    room: &str,
+    visibility: Visibility,
+    port: u16,
+) -> UnboundedReceiver<Message> {
+    let (tx, rx) = unbounded();
+    state
+        .add_server(
+            room.to_string(),
+            format!("{} host", room),
+            "linux".to_string(),
+            "1.0".to_string(),
+            true,
+            tx,
+            SocketAddr::from(([127, 0, 0, 1], port)),
+        )
+        .unwrap();
+    let session = state.sessions.get_mut(room).unwrap();
+    session.owner = Some("alice".to_string());
+    session.visibility = visibility;
+    state.index_room(room);
+    rx
+}
+
+/// A lobby connection, logged in as `username` if given.
+fn lobby(
+    state: &mut State,
+    username: Option<&str>,
+    port: u16,
+) -> (SocketAddr, Tx, UnboundedReceiver<Message>) {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let (tx, rx) = unbounded();
+    state.register_connection(addr, tx.clone(), None);
+    if let Some(username) = username {
+        let token = sign_in(state, username, AccountRole::User);
+        state.authenticate_connection(addr, &token).unwrap();
+    }
+    (addr, tx, rx)
+}
+
+fn listed(state: &State, account: Option<&str>) -> Vec<String> {
+    let mut rooms: Vec<String> = state
+        .get_available_rooms(&Default::default(), account)
+        .unwrap()
+        .rooms
+        .into_iter()
+        .map(|info| info.room)
+        .collect();
+    rooms.sort();
+    rooms
+}
+
+#[tokio::test]
+async fn test_hidden_rooms_are_not_listed() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    add_room(&mut locked_state, "public", Visibility::Public, 8001);
+    add_room(&mut locked_state, "unlisted", Visibility::Unlisted, 8002);
+    add_room(&mut locked_state, "private", Visibility::OwnerOnly, 8003);
+
+    assert_eq!(listed(&locked_state, None), ["public"]);
+    assert_eq!(listed(&locked_state, Some("bob")), ["public"]);
+    assert_eq!(
+        listed(&locked_state, Some("alice")),
+        ["private", "public", "unlisted"]
+    );
+    let page = locked_state
+        .get_available_rooms(&Default::default(), None)
+        .unwrap();
+    assert_eq!(page.total_count, 1);
+
+    let searched = |account| {
+        locked_state
+            .search_rooms("host", None, account)
+            .unwrap()
+            .len()
+    };
+    assert_eq!(searched(None), 1);
+    assert_eq!(searched(Some("alice")), 3);
+}
+
+#[tokio::test]
+async fn test_joining_hidden_rooms() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let _unlisted_rx = add_room(&mut locked_state, "unlisted", Visibility::Unlisted, 8001);
+    let _private_rx = add_room(&mut locked_state, "private", Visibility::OwnerOnly, 8002);
+    let (guest_addr, guest_tx, mut guest_rx) = lobby(&mut locked_state, None, 9001);
+    let (alice_addr, alice_tx, _alice_rx) = lobby(&mut locked_state, Some("alice"), 9002);
+
+    let join = |from: &str, room: &str| SignallerMessage::Join {
+        from: from.to_string(),
+        room: room.to_string(),
+        invite: None,
+        client: Default::default(),
+    };
+    // Anyone with the ID can join an unlisted room
+    send(
+        &mut locked_state,
+        &guest_tx,
+        join("guest", "unlisted"),
+        guest_addr,
+    )
+    .await
+    .unwrap();
+    assert!(locked_state.sessions["unlisted"].viewers.contains("guest"));
+
+    // An owner-only room looks offline to everyone but its owner
+    send(
+        &mut locked_state,
+        &guest_tx,
+        join("guest2", "private"),
+        guest_addr,
+    )
+    .await
+    .unwrap();
+    assert!(drain(&mut guest_rx).iter().any(|message| matches!(
+        message,
+        SignallerMessage::JoinDeclined { reason, .. } if reason == "Device is offline"
+    )));
+    send(
+        &mut locked_state,
+        &alice_tx,
+        join("alice", "private"),
+        alice_addr,
+    )
+    .await
+    .unwrap();
+    assert!(locked_state.sessions["private"].viewers.contains("alice"));
+}
+
+#[tokio::test]
+async fn test_hidden_rooms_in_room_updates() {
+    let state = State::new();
+    let mut locked_state = state.lock().await;
+    let (guest_addr, guest_tx, mut guest_rx) = lobby(&mut locked_state, None, 9001);
+    let (alice_addr, alice_tx, mut alice_rx) = lobby(&mut locked_state, Some("alice"), 9002);
+
+    let subscribe = || SignallerMessage::SubscribeRoomUpdates {
+        subscription: None,
+        since: None,
+        filter: Default::default(),
+    };
+    send(&mut locked_state, &guest_tx, subscribe(), guest_addr)
+        .await
+        .unwrap();
+    send(&mut locked_state, &alice_tx, subscribe(), alice_addr)
+        .await
+        .unwrap();
+    drain(&mut guest_rx);
+    drain(&mut alice_rx);
+
+    add_room(&mut locked_state, "private", Visibility::OwnerOnly, 8001);
+    locked_state.publish_room_added("private");
+    assert!(drain(&mut guest_rx).is_empty());
+    assert!(matches!(
+        &drain(&mut alice_rx)[..],
+        [SignallerMessage::RoomAdded { room, .. }] if room == "private"
+    ));
+
+    // Snapshots leave the room out too
+    send(&mut locked_state, &guest_tx, subscribe(), guest_addr)
+        .await
+        .unwrap();
+    assert!(matches!(
+        &drain(&mut guest_rx)[..],
+        [SignallerMessage::RoomSnapshot { rooms, .. }] if rooms.is_empty()
+    ));
+}
+
+#[tokio::test]
+async fn test_only_paired_rooms_can_be_hidden() {
+    let state = State::new();
+    let (tx, _rx) = unbounded();
+    let mut locked_state = state.lock().await;
+    let start = SignallerMessage::Start {
+        room: "test_room".to_string(),
+        name: "test_name".to_string(),
+        os: "linux".to_string(),
+        version: "1.0".to_string(),
+        control: true,
+        device_secret: None,
+        max_viewers: None,
+        visibility: Some(Visibility::Unlisted),
+        tags: Default::default(),
+    };
+    assert!(send(
+        &mut locked_state,
+        &tx,
+        start,
+        SocketAddr::from(([127, 0, 0, 1], 8080))
+    )
+    .await
+    .is_err());
+    assert!(locked_state.sessions.is_empty());
+}
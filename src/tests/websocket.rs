@@ -1,8 +1,10 @@
 use crate::{
-    models::{rtc::SignallerMessage, state::State},
+    models::{account::AccountRole, rtc::SignallerMessage, state::State},
     services::websocket::handle_message,
 };
 
+use super::sign_in;
+
 #[tokio::test]
 async fn test_handle_message_start() {
     let state = State::new();
@@ -17,6 +19,10 @@ async fn test_handle_message_start() {
         os: "test_os".to_string(),
         version: "1.0".to_string(),
         control: true,
+        device_secret: None,
+        max_viewers: None,
+        visibility: None,
+        tags: Default::default(),
     };
 
     let result = handle_message(
@@ -46,6 +52,10 @@ async fn test_handle_message_join() {
         os: "test_os".to_string(),
         version: "1.0".to_string(),
         control: true,
+        device_secret: None,
+        max_viewers: None,
+        visibility: None,
+        tags: Default::default(),
     };
 
     handle_message(
@@ -61,6 +71,8 @@ async fn test_handle_message_join() {
     let join_msg = SignallerMessage::Join {
         from: "viewer1".to_string(),
         room: "test_room".to_string(),
+        invite: None,
+        client: Default::default(),
     };
 
     let result = handle_message(
@@ -82,3 +94,34 @@ async fn test_handle_message_join() {
         1
     );
 }
+
+#[tokio::test]
+async fn test_handshake_token_authentication() {
+    use crate::{args::Args, routes::router::create_router};
+    use clap::Parser;
+
+    let state = State::new();
+    let token = {
+        let mut locked_state = state.lock().await;
+        sign_in(&mut locked_state, "alice", AccountRole::User)
+    };
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = create_router(state, Args::parse_from(["program"]));
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+        )
+        .await
+        .unwrap()
+    });
+
+    let rejected = tokio_tungstenite::connect_async(format!("ws://{}/?token=forged", addr)).await;
+    assert!(rejected.is_err());
+
+    let accepted =
+        tokio_tungstenite::connect_async(format!("ws://{}/?token={}", addr, token)).await;
+    assert!(accepted.is_ok());
+}